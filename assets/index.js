@@ -12,11 +12,13 @@
  * @property {string} uri_prefix
  * @property {"Index" | "Edit" | "View"} kind
  * @property {PathItem[]} paths
+ * @property {string} [next_cursor]
  * @property {boolean} allow_upload
  * @property {boolean} allow_delete
  * @property {boolean} allow_search
  * @property {boolean} allow_archive
  * @property {boolean} auth
+ * @property {boolean} oidc
  * @property {string} user
  * @property {boolean} dir_exists
  * @property {string} editable
@@ -61,6 +63,15 @@ const IFRAME_FORMATS = [
 	".m4a",
 ];
 
+const ARCHIVE_FORMATS = [
+	".zip",
+	".tar",
+	".tar.gz",
+	".tgz",
+	".tar.zst",
+	".tzst",
+];
+
 const dirEmptyNote = PARAMS.q
 	? "No results"
 	: DATA.dir_exists
@@ -377,6 +388,10 @@ function setupIndexPage() {
 		$download.classList.remove("hidden");
 	}
 
+	if (DATA.allow_archive) {
+		setupDownloadSelected();
+	}
+
 	if (DATA.allow_upload) {
 		setupDropzone();
 		setupUploadFile();
@@ -397,6 +412,126 @@ function setupIndexPage() {
 
 	renderPathsTableHead();
 	renderPathsTableBody();
+
+	if (DATA.next_cursor) {
+		setupInfiniteScroll();
+	}
+
+	if (!PARAMS.q && window.EventSource) {
+		setupEvents();
+	}
+}
+
+/**
+ * Download the checked paths as one archive
+ */
+function setupDownloadSelected() {
+	const $control = document.querySelector(".download-selected");
+	$control.addEventListener("click", async () => {
+		const names = [...document.querySelectorAll(".select-path:checked")].map(
+			($input) => $input.dataset.name
+		);
+		if (!names.length) return;
+		try {
+			const res = await fetch(baseUrl() + "?archive=zip", {
+				method: "POST",
+				headers: { "Content-Type": "application/json" },
+				body: JSON.stringify(names),
+			});
+			await assertResOK(res);
+			const blob = await res.blob();
+			const $a = document.createElement("a");
+			$a.href = URL.createObjectURL(blob);
+			const dirName = decodeURIComponent(location.pathname).split("/").filter(Boolean).pop();
+			$a.download = `${dirName || "root"}.zip`;
+			$a.click();
+			URL.revokeObjectURL($a.href);
+		} catch (err) {
+			alert(`Cannot download selected paths, ${err.message}`);
+		}
+	});
+	$pathsTableBody.addEventListener("change", (e) => {
+		if (!e.target.classList.contains("select-path")) return;
+		const count = document.querySelectorAll(".select-path:checked").length;
+		$control.querySelector(".selected-count").textContent = count || "";
+		$control.classList.toggle("hidden", count === 0);
+	});
+}
+
+/**
+ * Load the following pages of the listing when scrolled to the bottom
+ */
+function setupInfiniteScroll() {
+	const limit = DATA.paths.length;
+	let loading = false;
+	const loadMore = async () => {
+		if (loading || !DATA.next_cursor) return;
+		if (window.innerHeight + window.scrollY < document.body.offsetHeight - 200) return;
+		loading = true;
+		let loaded = false;
+		try {
+			const params = new URLSearchParams({
+				...PARAMS,
+				json: "",
+				limit,
+				cursor: DATA.next_cursor,
+			});
+			const res = await fetch(baseUrl() + "?" + params.toString());
+			await assertResOK(res);
+			const data = await res.json();
+			for (const item of data.paths) {
+				DATA.paths.push(item);
+				addPath(item, DATA.paths.length - 1);
+			}
+			DATA.next_cursor = data.next_cursor;
+			loaded = true;
+		} catch (err) {
+			console.error(`Failed to load more paths, ${err.message}`);
+		} finally {
+			loading = false;
+		}
+		if (!DATA.next_cursor) {
+			window.removeEventListener("scroll", loadMore);
+		} else if (loaded) {
+			loadMore();
+		}
+	};
+	window.addEventListener("scroll", loadMore);
+	loadMore();
+}
+
+/**
+ * Keep the listing up to date with server-sent change events
+ */
+function setupEvents() {
+	const source = new EventSource(baseUrl() + "?events");
+	source.addEventListener("change", (e) => {
+		const { kind, name, item } = JSON.parse(e.data);
+		if (name.includes("/")) return;
+		const index = DATA.paths.findIndex((v) => v && v.name === name);
+		if (kind === "delete") {
+			if (index < 0) return;
+			document.getElementById(`addPath${index}`)?.remove();
+			DATA.paths[index] = null;
+			if (!DATA.paths.find((v) => !!v)) {
+				$pathsTable.classList.add("hidden");
+				$emptyFolder.textContent = dirEmptyNote;
+				$emptyFolder.classList.remove("hidden");
+			}
+		} else if (index >= 0) {
+			DATA.paths[index] = item;
+			const $row = document.getElementById(`addPath${index}`);
+			if (!$row) return;
+			$row.querySelector(".cell-mtime").textContent = formatMtime(item.mtime);
+			$row.querySelector(".cell-size").textContent = formatSize(item.size).join(" ");
+		} else {
+			DATA.paths.push(item);
+			addPath(item, DATA.paths.length - 1);
+			$pathsTable.classList.remove("hidden");
+			$emptyFolder.classList.add("hidden");
+		}
+	});
+	source.addEventListener("reset", () => location.reload());
 }
 
 /**
@@ -512,10 +647,20 @@ function addPath(file, index) {
 	if (!actionEdit && !isDir) {
 		actionView = `<a class="action-btn" title="View file" target="_blank" href="${url}?view">${ICONS.view}</a>`;
 	}
+	let actionBrowse = "";
+	if (!isDir && isArchiveFile(file.name)) {
+		actionBrowse = `<div onclick="browseArchive(${index})" class="action-btn" title="Browse archive">${ICONS.dir}</div>`;
+	}
+	let actionSelect = "";
+	if (DATA.allow_archive) {
+		actionSelect = `<input type="checkbox" class="action-btn select-path" data-name="${encodedName.replace(/"/g, "&quot;")}" title="Select">`;
+	}
 	let actionCell = `
   <td class="cell-actions">
+    ${actionSelect}
     ${actionDownload}
     ${actionView}
+    ${actionBrowse}
     ${actionMove}
     ${actionDelete}
     ${actionEdit}
@@ -612,10 +757,20 @@ function setupAuth() {
 	if (DATA.user) {
 		$userBtn.classList.remove("hidden");
 		$userName.textContent = DATA.user;
+		if (DATA.oidc) {
+			$userBtn.title = "Logout";
+			$userBtn.addEventListener("click", () => {
+				location.href = DATA.uri_prefix + "?logout";
+			});
+		}
 	} else {
 		const $loginBtn = document.querySelector(".login-btn");
 		$loginBtn.classList.remove("hidden");
 		$loginBtn.addEventListener("click", async () => {
+			if (DATA.oidc) {
+				oidcLogin();
+				return;
+			}
 			try {
 				await checkAuth();
 				location.reload();
@@ -626,6 +781,14 @@ function setupAuth() {
 	}
 }
 
+/**
+ * Go through the login page of the OpenID Connect provider, then back here
+ */
+function oidcLogin() {
+	const next = encodeURIComponent(location.pathname + location.search);
+	location.href = `${DATA.uri_prefix}?login&next=${next}`;
+}
+
 function setupSearch() {
 	const $searchbar = document.querySelector(".searchbar");
 	$searchbar.classList.remove("hidden");
@@ -767,6 +930,83 @@ async function setupEditorPage() {
 	}
 }
 
+/**
+ * Browse the members of an archive like folders, without extracting it
+ * @param {number} index
+ */
+async function browseArchive(index) {
+	const file = DATA.paths[index];
+	if (!file) return;
+	const url = newUrl(file.name);
+	try {
+		const res = await fetch(url + "?list");
+		await assertResOK(res);
+		const members = await res.json();
+		renderArchiveDir(file.name, url, members, "");
+	} catch (err) {
+		alert(`Cannot browse \`${file.name}\`, ${err.message}`);
+	}
+}
+
+/**
+ * Render the members of the archive directory `dir` in the paths table
+ * @param {string} archiveName
+ * @param {string} url
+ * @param {PathItem[]} members
+ * @param {string} dir
+ */
+function renderArchiveDir(archiveName, url, members, dir) {
+	const parentOf = (name) => name.split("/").slice(0, -1).join("/");
+	const children = members.filter((v) => parentOf(v.name) === dir);
+	document.title = `Browse ${archiveName}/${dir} - Dufs`;
+	$emptyFolder.classList.add("hidden");
+	$pathsTable.classList.remove("hidden");
+	$pathsTableBody.innerHTML = `
+      <tr>
+        <td class="path cell-icon">${ICONS.dir}</td>
+        <td class="path cell-name"><a href="#" class="archive-up">..</a></td>
+        <td class="cell-mtime"></td>
+        <td class="cell-size"></td>
+        <td class="cell-actions"></td>
+      </tr>`;
+	$pathsTableBody.querySelector(".archive-up").addEventListener("click", (e) => {
+		e.preventDefault();
+		if (dir) {
+			renderArchiveDir(archiveName, url, members, parentOf(dir));
+		} else {
+			location.reload();
+		}
+	});
+	for (const member of children) {
+		const name = member.name.split("/").pop();
+		const isDir = member.path_type.endsWith("Dir");
+		const memberUrl = `${url}?member=${encodeURIComponent(member.name)}`;
+		$pathsTableBody.insertAdjacentHTML(
+			"beforeend",
+			`
+      <tr>
+        <td class="path cell-icon">${getPathSvg(member.path_type)}</td>
+        <td class="path cell-name">
+          <a href="${isDir ? "#" : memberUrl}" ${isDir ? "" : `target="_blank"`}>${encodedStr(name)}</a>
+        </td>
+        <td class="cell-mtime">${formatMtime(member.mtime)}</td>
+        <td class="cell-size">${isDir ? "" : formatSize(member.size).join(" ")}</td>
+        <td class="cell-actions">
+          ${isDir ? "" : `<div class="action-btn"><a href="${memberUrl}" title="Download file" download>${ICONS.download}</a></div>`}
+        </td>
+      </tr>`
+		);
+		if (isDir) {
+			$pathsTableBody.lastElementChild
+				.querySelector(".cell-name a")
+				.addEventListener("click", (e) => {
+					e.preventDefault();
+					renderArchiveDir(archiveName, url, members, member.name);
+				});
+		}
+	}
+}
+
 /**
  * Delete path
  * @param {number} index
@@ -915,6 +1155,10 @@ async function checkAuth() {
 	const res = await fetch(baseUrl(), {
 		method: "WRITEABLE",
 	});
+	if (DATA.oidc && !DATA.user && (res.status === 401 || res.status === 403)) {
+		oidcLogin();
+		throw new Error("Login required");
+	}
 	await assertResOK(res);
 	document.querySelector(".login-btn").classList.add("hidden");
 	$userBtn.classList.remove("hidden");
@@ -995,6 +1239,11 @@ function baseName(url) {
 	);
 }
 
+function isArchiveFile(filename) {
+	const name = filename.toLowerCase();
+	return ARCHIVE_FORMATS.some((ext) => name.endsWith(ext));
+}
+
 function extName(filename) {
 	const dotIndex = filename.lastIndexOf(".");
 
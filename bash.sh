@@ -17,10 +17,13 @@ cat <<EOF > /etc/systemd/system/$APP_NAME.tld.service
 [Unit]
 Description=Upload System
 [Service]
+Type=notify
 User=$USERNAME
 Group=$USERNAME
 WorkingDirectory=$APP_DIRECTORY
 ExecStart=$APP_DIRECTORY/$EXECUTABLE  /opt/media/ --allow-all
+WatchdogSec=30
+FileDescriptorStoreMax=16
 [Install]
 WantedBy=multi-user.target
 EOF
@@ -0,0 +1,904 @@
+use crate::args::{AcmeChallenge, Args};
+use crate::utils::{get_cert_dns_names, load_certs, load_private_key};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::Utc;
+use reqwest::header::CONTENT_TYPE;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio_rustls::rustls::{crypto::ring::sign::any_supported_type, sign::CertifiedKey};
+
+pub const ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
+pub const HTTP_CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+const ACCOUNT_KEY_FILE: &str = "account.key";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+const RENEW_BEFORE: i64 = 30 * 86400; // 30 days
+const CHECK_INTERVAL: Duration = Duration::from_secs(43200);
+const RETRY_INTERVAL: Duration = Duration::from_secs(600);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Gets a certificate for the domains from an ACME (RFC 8555) CA, keeps it in the cache
+/// directory and renews it in the background. Challenges are answered by `Server::handle`
+/// for http-01, or by the TLS listener for tls-alpn-01.
+pub struct Acme {
+    domains: Vec<String>,
+    email: Option<String>,
+    directory_url: String,
+    cache_dir: PathBuf,
+    challenge: AcmeChallenge,
+    client: reqwest::Client,
+    http_tokens: RwLock<HashMap<String, String>>,
+    alpn_certs: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+    cert: RwLock<Option<(Arc<CertifiedKey>, i64)>>,
+}
+
+impl std::fmt::Debug for Acme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Acme")
+            .field("domains", &self.domains)
+            .field("directory_url", &self.directory_url)
+            .field("challenge", &self.challenge)
+            .finish()
+    }
+}
+
+impl Acme {
+    pub fn new(args: &Args) -> Result<Option<Self>> {
+        let Some(cache_dir) = &args.acme_cache else {
+            return Ok(None);
+        };
+        if args.acme_domains.is_empty() {
+            return Ok(None);
+        }
+        std::fs::create_dir_all(cache_dir)
+            .with_context(|| format!("Failed to create `{}`", cache_dir.display()))?;
+        let mut builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT);
+        if let Some(path) = &args.acme_ca_root {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("Failed to access `{}`", path.display()))?;
+            builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        let acme = Self {
+            domains: args
+                .acme_domains
+                .iter()
+                .map(|v| v.to_ascii_lowercase())
+                .collect(),
+            email: args.acme_email.clone(),
+            directory_url: args.acme_directory.clone(),
+            cache_dir: cache_dir.clone(),
+            challenge: args.acme_challenge,
+            client: builder.build()?,
+            http_tokens: Default::default(),
+            alpn_certs: Default::default(),
+            cert: Default::default(),
+        };
+        match acme.load_cert() {
+            Ok(Some(cert)) => *acme.cert.write().unwrap() = Some(cert),
+            Ok(None) => {}
+            Err(err) => warn!("Ignored the cached ACME certificate, {err}"),
+        }
+        Ok(Some(acme))
+    }
+
+    pub fn challenge(&self) -> AcmeChallenge {
+        self.challenge
+    }
+
+    /// The issued certificate, none until the first order completes.
+    pub fn cert(&self) -> Option<Arc<CertifiedKey>> {
+        self.cert
+            .read()
+            .unwrap()
+            .as_ref()
+            .map(|(key, _)| key.clone())
+    }
+
+    /// The key authorization of a pending http-01 challenge.
+    pub fn http_challenge(&self, token: &str) -> Option<String> {
+        self.http_tokens.read().unwrap().get(token).cloned()
+    }
+
+    /// The certificate of a pending tls-alpn-01 challenge.
+    pub fn alpn_cert(&self, domain: &str) -> Option<Arc<CertifiedKey>> {
+        let domain = domain.to_ascii_lowercase();
+        self.alpn_certs.read().unwrap().get(&domain).cloned()
+    }
+
+    /// Order the certificate when it's missing or expires within 30 days, and check again
+    /// twice a day.
+    pub fn spawn(self: Arc<Self>, running: Arc<AtomicBool>) {
+        tokio::spawn(async move {
+            while running.load(Ordering::SeqCst) {
+                let wait = if self.needs_renewal() {
+                    match self.issue().await {
+                        Ok(()) => {
+                            info!("Issued the certificate of {}", self.domains.join(","));
+                            CHECK_INTERVAL
+                        }
+                        Err(err) => {
+                            warn!("Failed to issue the certificate from ACME, {err}");
+                            RETRY_INTERVAL
+                        }
+                    }
+                } else {
+                    CHECK_INTERVAL
+                };
+                tokio::time::sleep(wait).await;
+            }
+        });
+    }
+
+    fn needs_renewal(&self) -> bool {
+        match &*self.cert.read().unwrap() {
+            Some((_, expires)) => *expires - Utc::now().timestamp() < RENEW_BEFORE,
+            None => true,
+        }
+    }
+
+    async fn issue(&self) -> Result<()> {
+        let directory: Directory = self
+            .client
+            .get(&self.directory_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .with_context(|| format!("Invalid ACME directory `{}`", self.directory_url))?;
+        let mut session = AcmeSession {
+            client: &self.client,
+            rng: SystemRandom::new(),
+            key: self.account_key()?,
+            kid: None,
+            nonce: None,
+            new_nonce: directory.new_nonce.clone(),
+        };
+
+        let contact: Vec<String> = self.email.iter().map(|v| format!("mailto:{v}")).collect();
+        let res = session
+            .post(
+                &directory.new_account,
+                Some(&json!({ "termsOfServiceAgreed": true, "contact": contact })),
+            )
+            .await?;
+        session.kid = Some(location(&res)?);
+
+        let identifiers: Vec<Value> = self
+            .domains
+            .iter()
+            .map(|v| json!({ "type": "dns", "value": v }))
+            .collect();
+        let res = session
+            .post(
+                &directory.new_order,
+                Some(&json!({ "identifiers": identifiers })),
+            )
+            .await?;
+        let order_url = location(&res)?;
+        let order: Order = res.json().await?;
+
+        let thumbprint = session.thumbprint();
+        for url in order.authorizations.iter() {
+            let ret = self.authorize(&mut session, url, &thumbprint).await;
+            self.http_tokens.write().unwrap().clear();
+            self.alpn_certs.write().unwrap().clear();
+            ret?;
+        }
+
+        let order = session
+            .poll::<Order>(&order_url, |v| v.status != "pending")
+            .await?;
+        if order.status != "ready" {
+            bail!(
+                "ACME order is {}, {}",
+                order.status,
+                problem_detail(&order.error)
+            );
+        }
+        let mut params = rcgen::CertificateParams::new(self.domains.clone());
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, self.domains[0].clone());
+        let cert = rcgen::Certificate::from_params(params)?;
+        let csr = URL_SAFE_NO_PAD.encode(cert.serialize_request_der()?);
+        session
+            .post(&order.finalize, Some(&json!({ "csr": csr })))
+            .await?;
+        let order = session
+            .poll::<Order>(&order_url, |v| {
+                v.status != "processing" && v.status != "ready"
+            })
+            .await?;
+        let Some(cert_url) = order.certificate.filter(|_| order.status == "valid") else {
+            bail!(
+                "ACME order is {}, {}",
+                order.status,
+                problem_detail(&order.error)
+            );
+        };
+        let chain = session.post(&cert_url, None).await?.text().await?;
+
+        // The key first, so a new certificate never comes with an old key
+        let key_path = self.cache_dir.join(KEY_FILE);
+        let cert_path = self.cache_dir.join(CERT_FILE);
+        write_private_file(&key_path, cert.serialize_private_key_pem().as_bytes())?;
+        write_private_file(&cert_path, chain.as_bytes())?;
+        let loaded = self
+            .load_cert()?
+            .ok_or_else(|| anyhow!("The issued certificate doesn't cover all the domains"))?;
+        *self.cert.write().unwrap() = Some(loaded);
+        Ok(())
+    }
+
+    async fn authorize(
+        &self,
+        session: &mut AcmeSession<'_>,
+        url: &str,
+        thumbprint: &str,
+    ) -> Result<()> {
+        let authorization: Authorization = session.post(url, None).await?.json().await?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let domain = authorization.identifier.value;
+        let Some(challenge) = authorization
+            .challenges
+            .iter()
+            .find(|v| v.kind == self.challenge.as_str())
+        else {
+            bail!("No {} challenge for `{domain}`", self.challenge.as_str());
+        };
+        let key_authorization = format!("{}.{thumbprint}", challenge.token);
+        match self.challenge {
+            AcmeChallenge::Http01 => {
+                self.http_tokens
+                    .write()
+                    .unwrap()
+                    .insert(challenge.token.clone(), key_authorization);
+            }
+            AcmeChallenge::TlsAlpn01 => {
+                let cert = alpn_cert(&domain, &key_authorization)?;
+                self.alpn_certs
+                    .write()
+                    .unwrap()
+                    .insert(domain.clone(), cert);
+            }
+        }
+        session.post(&challenge.url, Some(&json!({}))).await?;
+        let authorization = session
+            .poll::<Authorization>(url, |v| v.status != "pending")
+            .await?;
+        if authorization.status != "valid" {
+            let error = authorization
+                .challenges
+                .iter()
+                .find(|v| v.kind == self.challenge.as_str())
+                .and_then(|v| v.error.clone());
+            bail!("Failed to validate `{domain}`, {}", problem_detail(&error));
+        }
+        Ok(())
+    }
+
+    fn account_key(&self) -> Result<EcdsaKeyPair> {
+        let rng = SystemRandom::new();
+        let path = self.cache_dir.join(ACCOUNT_KEY_FILE);
+        let pkcs8 = match std::fs::read(&path) {
+            Ok(v) => v,
+            Err(_) => {
+                let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+                    .map_err(|_| anyhow!("Failed to generate the ACME account key"))?;
+                write_private_file(&path, pkcs8.as_ref())?;
+                pkcs8.as_ref().to_vec()
+            }
+        };
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &pkcs8, &rng)
+            .map_err(|err| anyhow!("Invalid ACME account key `{}`, {err}", path.display()))
+    }
+
+    /// The cached certificate with its expiry time, if it covers all the domains.
+    fn load_cert(&self) -> Result<Option<(Arc<CertifiedKey>, i64)>> {
+        let cert_path = self.cache_dir.join(CERT_FILE);
+        let key_path = self.cache_dir.join(KEY_FILE);
+        if !cert_path.exists() || !key_path.exists() {
+            return Ok(None);
+        }
+        let certs = load_certs(&cert_path)?;
+        let key = load_private_key(&key_path)?;
+        let names = get_cert_dns_names(&certs[0]);
+        if !self.domains.iter().all(|v| names.contains(v)) {
+            return Ok(None);
+        }
+        let expires = cert_expires(&certs[0], &cert_path)?;
+        let signing_key = any_supported_type(&key)?;
+        let cert = CertifiedKey::new(certs, signing_key);
+        cert.keys_match().map_err(|err| {
+            anyhow!(
+                "The key `{}` doesn't match the certificate, {err}",
+                key_path.display()
+            )
+        })?;
+        Ok(Some((Arc::new(cert), expires)))
+    }
+}
+
+/// Write a file only the owner can read. It is written aside and renamed, so a crash
+/// never leaves half of it behind.
+fn write_private_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    let _ = std::fs::remove_file(&tmp_path);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let ret = options
+        .open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmp_path, path));
+    ret.map_err(|err| {
+        let _ = std::fs::remove_file(&tmp_path);
+        anyhow!("Failed to write `{}`, {err}", path.display())
+    })
+}
+
+/// Signs the requests of one order with the account key.
+struct AcmeSession<'a> {
+    client: &'a reqwest::Client,
+    rng: SystemRandom,
+    key: EcdsaKeyPair,
+    kid: Option<String>,
+    nonce: Option<String>,
+    new_nonce: String,
+}
+
+impl AcmeSession<'_> {
+    /// A POST with a JWS body, or a POST-as-GET without `payload`.
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<reqwest::Response> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(v) => v,
+                None => self.fetch_nonce().await?,
+            };
+            let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+            match &self.kid {
+                Some(kid) => protected["kid"] = json!(kid),
+                None => protected["jwk"] = self.jwk(),
+            }
+            let protected = URL_SAFE_NO_PAD.encode(protected.to_string());
+            let payload = payload
+                .map(|v| URL_SAFE_NO_PAD.encode(v.to_string()))
+                .unwrap_or_default();
+            let signature = self
+                .key
+                .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+                .map_err(|_| anyhow!("Failed to sign the ACME request"))?;
+            let body = json!({
+                "protected": protected,
+                "payload": payload,
+                "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+            });
+            let res = self
+                .client
+                .post(url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .await?;
+            self.nonce = res
+                .headers()
+                .get("replay-nonce")
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string());
+            if res.status().is_success() {
+                return Ok(res);
+            }
+            let problem: Option<Value> = res.json().await.ok();
+            let bad_nonce = problem
+                .as_ref()
+                .is_some_and(|v| v["type"] == "urn:ietf:params:acme:error:badNonce");
+            if bad_nonce && !retried {
+                retried = true;
+                continue;
+            }
+            bail!(
+                "ACME request to `{url}` failed, {}",
+                problem_detail(&problem)
+            );
+        }
+    }
+
+    async fn poll<T>(&mut self, url: &str, done: impl Fn(&T) -> bool) -> Result<T>
+    where
+        T: for<'de> Deserialize<'de>,
+    {
+        for _ in 0..POLL_ATTEMPTS {
+            let value: T = self.post(url, None).await?.json().await?;
+            if done(&value) {
+                return Ok(value);
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        bail!("Timeout waiting for `{url}`")
+    }
+
+    async fn fetch_nonce(&self) -> Result<String> {
+        let res = self.client.head(&self.new_nonce).send().await?;
+        res.headers()
+            .get("replay-nonce")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+            .ok_or_else(|| anyhow!("No nonce from `{}`", self.new_nonce))
+    }
+
+    fn jwk(&self) -> Value {
+        // An uncompressed point, 0x04 then the coordinates
+        let point = self.key.public_key().as_ref();
+        json!({
+            "crv": "P-256",
+            "kty": "EC",
+            "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+            "y": URL_SAFE_NO_PAD.encode(&point[33..65]),
+        })
+    }
+
+    /// The RFC 7638 thumbprint of the account key, its members sorted without spaces.
+    fn thumbprint(&self) -> String {
+        let jwk = self.jwk();
+        let value = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":{},"y":{}}}"#,
+            jwk["x"], jwk["y"]
+        );
+        URL_SAFE_NO_PAD.encode(Sha256::digest(value.as_bytes()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    new_account: String,
+    new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    status: String,
+    #[serde(default)]
+    authorizations: Vec<String>,
+    finalize: String,
+    certificate: Option<String>,
+    error: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Authorization {
+    identifier: Identifier,
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Identifier {
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: String,
+    token: String,
+    error: Option<Value>,
+}
+
+fn location(res: &reqwest::Response) -> Result<String> {
+    res.headers()
+        .get("location")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+        .ok_or_else(|| anyhow!("No location from `{}`", res.url()))
+}
+
+fn problem_detail(problem: &Option<Value>) -> String {
+    match problem {
+        Some(v) => match (v["type"].as_str(), v["detail"].as_str()) {
+            (Some(kind), Some(detail)) => format!("{kind}: {detail}"),
+            (Some(kind), None) => kind.to_string(),
+            _ => v.to_string(),
+        },
+        None => "no details".to_string(),
+    }
+}
+
+/// A self-signed certificate for `domain` with the acmeIdentifier extension of RFC 8737.
+fn alpn_cert(domain: &str, key_authorization: &str) -> Result<Arc<CertifiedKey>> {
+    let mut params = rcgen::CertificateParams::new(vec![domain.to_string()]);
+    let digest = Sha256::digest(key_authorization.as_bytes());
+    params.custom_extensions = vec![rcgen::CustomExtension::new_acme_identifier(&digest)];
+    let cert = rcgen::Certificate::from_params(params)?;
+    let key = PrivateKeyDer::Pkcs8(cert.serialize_private_key_der().into());
+    let signing_key = any_supported_type(&key)?;
+    let cert = CertificateDer::from(cert.serialize_der()?);
+    Ok(Arc::new(CertifiedKey::new(vec![cert], signing_key)))
+}
+
+fn cert_expires(cert: &CertificateDer, path: &Path) -> Result<i64> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert)
+        .map_err(|_| anyhow!("Invalid certificate `{}`", path.display()))?;
+    Ok(cert.validity().not_after.timestamp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acme_session() {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let client = reqwest::Client::new();
+        let session = AcmeSession {
+            client: &client,
+            key: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                .unwrap(),
+            rng,
+            kid: None,
+            nonce: None,
+            new_nonce: String::new(),
+        };
+        let jwk = session.jwk();
+        assert_eq!(jwk["x"].as_str().unwrap().len(), 43);
+        assert_eq!(jwk["y"].as_str().unwrap().len(), 43);
+        let canonical = format!(
+            r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#,
+            jwk["x"].as_str().unwrap(),
+            jwk["y"].as_str().unwrap()
+        );
+        assert_eq!(
+            session.thumbprint(),
+            URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes()))
+        );
+
+        let cert = alpn_cert("example.com", "token.thumbprint").unwrap();
+        let (_, parsed) = x509_parser::parse_x509_certificate(&cert.cert[0]).unwrap();
+        let oid = x509_parser::oid_registry::Oid::from(&[1, 3, 6, 1, 5, 5, 7, 1, 31]).unwrap();
+        let ext = parsed.get_extension_unique(&oid).unwrap().unwrap();
+        assert!(ext.critical);
+        assert!(ext.value.ends_with(&Sha256::digest(b"token.thumbprint")));
+    }
+
+    #[test]
+    fn test_write_private_file() {
+        let tmpdir = assert_fs::TempDir::new().unwrap();
+        let path = tmpdir.path().join(KEY_FILE);
+        std::fs::write(&path, "old").unwrap();
+        write_private_file(&path, b"new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert!(!tmpdir.path().join("key.pem.tmp").exists());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+    }
+
+    const DOMAIN: &str = "example.com";
+    const TOKEN: &str = "token1";
+
+    /// An ACME server with one order of `DOMAIN`, whose challenge passes if `acme` has the
+    /// right answer ready, and whose certificates are signed by its own CA.
+    struct MockAcme {
+        base: String,
+        challenge: AcmeChallenge,
+        acme: std::sync::OnceLock<Arc<Acme>>,
+        ca: rcgen::Certificate,
+        state: std::sync::Mutex<MockState>,
+    }
+
+    #[derive(Default)]
+    struct MockState {
+        nonces: std::collections::HashSet<String>,
+        next_nonce: usize,
+        jwk: Option<Value>,
+        valid: bool,
+        chain: Option<String>,
+    }
+
+    /// The public key of a CSR, to sign a certificate for it.
+    struct CsrKey(Vec<u8>);
+
+    impl rcgen::RemoteKeyPair for CsrKey {
+        fn public_key(&self) -> &[u8] {
+            &self.0
+        }
+
+        fn sign(&self, _msg: &[u8]) -> Result<Vec<u8>, rcgen::Error> {
+            Err(rcgen::Error::RemoteKeyError)
+        }
+
+        fn algorithm(&self) -> &'static rcgen::SignatureAlgorithm {
+            &rcgen::PKCS_ECDSA_P256_SHA256
+        }
+    }
+
+    impl MockAcme {
+        async fn serve(challenge: AcmeChallenge) -> Arc<Self> {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let mut ca_params = rcgen::CertificateParams::new(vec![]);
+            ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+            let mock = Arc::new(Self {
+                base: format!("http://{}", listener.local_addr().unwrap()),
+                challenge,
+                acme: Default::default(),
+                ca: rcgen::Certificate::from_params(ca_params).unwrap(),
+                state: Default::default(),
+            });
+            let server = mock.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let server = server.clone();
+                    let service = hyper::service::service_fn(move |req: hyper::Request<_>| {
+                        let server = server.clone();
+                        async move {
+                            let (parts, body) = req.into_parts();
+                            let body = http_body_util::BodyExt::collect(body).await?.to_bytes();
+                            Ok::<_, hyper::Error>(server.handle(
+                                &parts.method,
+                                parts.uri.path(),
+                                &body,
+                            ))
+                        }
+                    });
+                    tokio::spawn(
+                        hyper::server::conn::http1::Builder::new()
+                            .serve_connection(hyper_util::rt::TokioIo::new(stream), service),
+                    );
+                }
+            });
+            mock
+        }
+
+        fn acme(&self, cache_dir: &Path) -> Acme {
+            let args = Args {
+                acme_domains: vec![DOMAIN.to_string()],
+                acme_cache: Some(cache_dir.to_path_buf()),
+                acme_directory: format!("{}/dir", self.base),
+                acme_challenge: self.challenge,
+                ..Default::default()
+            };
+            Acme::new(&args).unwrap().unwrap()
+        }
+
+        fn handle(
+            &self,
+            method: &hyper::Method,
+            path: &str,
+            body: &[u8],
+        ) -> hyper::Response<http_body_util::Full<bytes::Bytes>> {
+            let mut state = self.state.lock().unwrap();
+            let base = &self.base;
+            let (status, location, body) = match (method.as_str(), path) {
+                ("GET", "/dir") => {
+                    let directory = json!({
+                        "newNonce": format!("{base}/nonce"),
+                        "newAccount": format!("{base}/account"),
+                        "newOrder": format!("{base}/order"),
+                    });
+                    (200, None, directory.to_string())
+                }
+                ("HEAD", "/nonce") => (200, None, String::new()),
+                ("POST", _) => {
+                    let payload = self.verify(&mut state, path, body);
+                    match path {
+                        "/account" => (
+                            201,
+                            Some(format!("{base}/account/1")),
+                            json!({ "status": "valid" }).to_string(),
+                        ),
+                        "/order" => (
+                            201,
+                            Some(format!("{base}/order/1")),
+                            self.order(&state).to_string(),
+                        ),
+                        "/order/1" => (200, None, self.order(&state).to_string()),
+                        "/authz/1" => (200, None, self.authorization(&state).to_string()),
+                        "/chall/1" => {
+                            state.valid = self.answered(state.jwk.as_ref().unwrap());
+                            (200, None, "{}".to_string())
+                        }
+                        "/finalize/1" => {
+                            assert!(state.valid);
+                            let csr = payload.unwrap()["csr"].as_str().unwrap().to_string();
+                            state.chain = Some(self.sign(&csr));
+                            (200, None, self.order(&state).to_string())
+                        }
+                        "/cert/1" => (200, None, state.chain.clone().unwrap()),
+                        _ => (404, None, String::new()),
+                    }
+                }
+                _ => (404, None, String::new()),
+            };
+            state.next_nonce += 1;
+            let nonce = format!("nonce{}", state.next_nonce);
+            state.nonces.insert(nonce.clone());
+            let mut builder = hyper::Response::builder()
+                .status(status)
+                .header("replay-nonce", nonce);
+            if let Some(location) = location {
+                builder = builder.header("location", location);
+            }
+            builder.body(body.into()).unwrap()
+        }
+
+        /// Check the signature, url and nonce of a JWS request, and return its payload.
+        fn verify(&self, state: &mut MockState, path: &str, body: &[u8]) -> Option<Value> {
+            let decode = |v: &Value| URL_SAFE_NO_PAD.decode(v.as_str().unwrap()).unwrap();
+            let jws: Value = serde_json::from_slice(body).unwrap();
+            let protected: Value = serde_json::from_slice(&decode(&jws["protected"])).unwrap();
+            assert_eq!(protected["url"], format!("{}{path}", self.base));
+            assert!(state.nonces.remove(protected["nonce"].as_str().unwrap()));
+            if path == "/account" {
+                state.jwk = Some(protected["jwk"].clone());
+            } else {
+                assert_eq!(protected["kid"], format!("{}/account/1", self.base));
+            }
+            let jwk = state.jwk.as_ref().unwrap();
+            let point = [vec![4], decode(&jwk["x"]), decode(&jwk["y"])].concat();
+            let message = format!(
+                "{}.{}",
+                jws["protected"].as_str().unwrap(),
+                jws["payload"].as_str().unwrap()
+            );
+            ring::signature::UnparsedPublicKey::new(
+                &ring::signature::ECDSA_P256_SHA256_FIXED,
+                point,
+            )
+            .verify(message.as_bytes(), &decode(&jws["signature"]))
+            .unwrap();
+            let payload = decode(&jws["payload"]);
+            (!payload.is_empty()).then(|| serde_json::from_slice(&payload).unwrap())
+        }
+
+        fn order(&self, state: &MockState) -> Value {
+            let status = match (&state.chain, state.valid) {
+                (Some(_), _) => "valid",
+                (None, true) => "ready",
+                (None, false) => "pending",
+            };
+            json!({
+                "status": status,
+                "authorizations": [format!("{}/authz/1", self.base)],
+                "finalize": format!("{}/finalize/1", self.base),
+                "certificate": state.chain.as_ref().map(|_| format!("{}/cert/1", self.base)),
+            })
+        }
+
+        fn authorization(&self, state: &MockState) -> Value {
+            json!({
+                "identifier": { "type": "dns", "value": DOMAIN },
+                "status": if state.valid { "valid" } else { "pending" },
+                "challenges": [{
+                    "type": self.challenge.as_str(),
+                    "url": format!("{}/chall/1", self.base),
+                    "token": TOKEN,
+                }],
+            })
+        }
+
+        /// Whether `acme` answers the challenge with the key authorization of `jwk`.
+        fn answered(&self, jwk: &Value) -> bool {
+            let value = format!(
+                r#"{{"crv":"P-256","kty":"EC","x":{},"y":{}}}"#,
+                jwk["x"], jwk["y"]
+            );
+            let thumbprint = URL_SAFE_NO_PAD.encode(Sha256::digest(value.as_bytes()));
+            let key_authorization = format!("{TOKEN}.{thumbprint}");
+            let acme = self.acme.get().unwrap();
+            match self.challenge {
+                AcmeChallenge::Http01 => acme.http_challenge(TOKEN) == Some(key_authorization),
+                AcmeChallenge::TlsAlpn01 => acme.alpn_cert(DOMAIN).is_some_and(|cert| {
+                    let (_, parsed) = x509_parser::parse_x509_certificate(&cert.cert[0]).unwrap();
+                    let oid =
+                        x509_parser::oid_registry::Oid::from(&[1, 3, 6, 1, 5, 5, 7, 1, 31]).unwrap();
+                    let digest = Sha256::digest(key_authorization.as_bytes());
+                    matches!(parsed.get_extension_unique(&oid), Ok(Some(ext)) if ext.value.ends_with(&digest))
+                }),
+            }
+        }
+
+        fn sign(&self, csr: &str) -> String {
+            use x509_parser::prelude::FromDer;
+            let der = URL_SAFE_NO_PAD.decode(csr).unwrap();
+            let (_, csr) =
+                x509_parser::certification_request::X509CertificationRequest::from_der(&der)
+                    .unwrap();
+            let key = csr
+                .certification_request_info
+                .subject_pki
+                .subject_public_key;
+            let mut params = rcgen::CertificateParams::new(vec![DOMAIN.to_string()]);
+            params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+            params.key_pair =
+                Some(rcgen::KeyPair::from_remote(Box::new(CsrKey(key.data.to_vec()))).unwrap());
+            let cert = rcgen::Certificate::from_params(params).unwrap();
+            format!(
+                "{}{}",
+                cert.serialize_pem_with_signer(&self.ca).unwrap(),
+                self.ca.serialize_pem().unwrap()
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_issue() {
+        for challenge in [AcmeChallenge::Http01, AcmeChallenge::TlsAlpn01] {
+            let tmpdir = assert_fs::TempDir::new().unwrap();
+            let mock = MockAcme::serve(challenge).await;
+            let acme = Arc::new(mock.acme(tmpdir.path()));
+            mock.acme.set(acme.clone()).unwrap();
+            assert!(acme.needs_renewal());
+            acme.issue().await.unwrap();
+
+            let cert = acme.cert().unwrap();
+            assert_eq!(cert.cert.len(), 2);
+            assert_eq!(get_cert_dns_names(&cert.cert[0]), [DOMAIN]);
+            assert!(!acme.needs_renewal());
+            assert!(acme.http_challenge(TOKEN).is_none());
+            assert!(acme.alpn_cert(DOMAIN).is_none());
+            // The next start picks up the cached certificate
+            assert!(mock.acme(tmpdir.path()).cert().is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_issue_mismatched_key() {
+        let tmpdir = assert_fs::TempDir::new().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec![DOMAIN.to_string()]).unwrap();
+        let other = rcgen::generate_simple_self_signed(vec![DOMAIN.to_string()]).unwrap();
+        let cert_path = tmpdir.path().join(CERT_FILE);
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(
+            tmpdir.path().join(KEY_FILE),
+            other.serialize_private_key_pem(),
+        )
+        .unwrap();
+
+        let mock = MockAcme::serve(AcmeChallenge::Http01).await;
+        let acme = Arc::new(mock.acme(tmpdir.path()));
+        mock.acme.set(acme.clone()).unwrap();
+        assert!(acme.cert().is_none());
+        assert!(acme.needs_renewal());
+        acme.issue().await.unwrap();
+
+        assert!(acme.cert().is_some());
+        assert!(acme.load_cert().unwrap().is_some());
+        assert_ne!(
+            std::fs::read_to_string(&cert_path).unwrap(),
+            cert.serialize_pem().unwrap()
+        );
+    }
+}
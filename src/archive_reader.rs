@@ -0,0 +1,267 @@
+use anyhow::Result;
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use async_zip::tokio::read::seek::ZipFileReader;
+use async_zip::Compression;
+use chrono::LocalResult;
+use futures_util::TryStreamExt;
+use std::collections::HashSet;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::pin::Pin;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, BufReader};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+const ZIP_LOCAL_HEADER_SIZE: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+/// Detect the archive kind of a file from its magic bytes.
+pub async fn detect_archive(path: &Path) -> Result<Option<ArchiveKind>> {
+    let mut head = vec![];
+    File::open(path)
+        .await?
+        .take(512)
+        .read_to_end(&mut head)
+        .await?;
+    let kind = if head.starts_with(b"PK\x03\x04") || head.starts_with(b"PK\x05\x06") {
+        Some(ArchiveKind::Zip)
+    } else if head.starts_with(b"\x1f\x8b") {
+        Some(ArchiveKind::TarGz)
+    } else if head.starts_with(b"\x28\xb5\x2f\xfd") {
+        Some(ArchiveKind::TarZst)
+    } else if head.len() == 512 && &head[257..262] == b"ustar" {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    };
+    Ok(kind)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MemberKind {
+    Dir,
+    File,
+    Other,
+}
+
+/// An archive member, `mtime` is in milliseconds.
+#[derive(Debug)]
+pub struct Member {
+    pub name: String,
+    pub kind: MemberKind,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+/// The data of a file member.
+pub enum MemberData {
+    /// Stored as is at `offset` in the archive, so it can be served by ranges.
+    Raw { offset: u64, size: u64 },
+    /// Only readable by decoding the archive up to the member.
+    Stream {
+        reader: Pin<Box<dyn AsyncRead + Send>>,
+        size: u64,
+    },
+}
+
+/// List the members of an archive, adding the directories that only appear in paths.
+pub async fn list_members(path: &Path, kind: ArchiveKind) -> Result<Vec<Member>> {
+    let mut members = vec![];
+    let reader = BufReader::new(File::open(path).await?);
+    match kind {
+        ArchiveKind::Zip => {
+            let zip = ZipFileReader::with_tokio(reader).await?;
+            for entry in zip.file().entries() {
+                let Some(name) = zip_entry_name(entry) else {
+                    continue;
+                };
+                let kind = if entry.dir().unwrap_or_default() {
+                    MemberKind::Dir
+                } else if is_zip_symlink(entry) {
+                    MemberKind::Other
+                } else {
+                    MemberKind::File
+                };
+                let mtime = match entry.last_modification_date().as_chrono() {
+                    LocalResult::Single(v) => v.timestamp_millis().max(0) as u64,
+                    _ => 0,
+                };
+                members.push(Member {
+                    name,
+                    kind,
+                    size: entry.uncompressed_size(),
+                    mtime,
+                });
+            }
+        }
+        ArchiveKind::Tar => list_tar_members(reader, &mut members).await?,
+        ArchiveKind::TarGz => list_tar_members(GzipDecoder::new(reader), &mut members).await?,
+        ArchiveKind::TarZst => list_tar_members(ZstdDecoder::new(reader), &mut members).await?,
+    }
+    let names: HashSet<String> = members.iter().map(|v| v.name.clone()).collect();
+    let mut parents = HashSet::new();
+    for member in members.iter() {
+        let mut name = member.name.as_str();
+        while let Some((parent, _)) = name.rsplit_once('/') {
+            if !names.contains(parent) {
+                parents.insert(parent.to_string());
+            }
+            name = parent;
+        }
+    }
+    members.extend(parents.into_iter().map(|name| Member {
+        name,
+        kind: MemberKind::Dir,
+        size: 0,
+        mtime: 0,
+    }));
+    Ok(members)
+}
+
+/// Open the file member named `name`, returns `None` if there is no such file.
+pub async fn open_member(path: &Path, kind: ArchiveKind, name: &str) -> Result<Option<MemberData>> {
+    let reader = BufReader::new(File::open(path).await?);
+    match kind {
+        ArchiveKind::Zip => {
+            let zip = ZipFileReader::with_tokio(reader).await?;
+            let Some(index) = zip.file().entries().iter().position(|entry| {
+                !entry.dir().unwrap_or_default()
+                    && !is_zip_symlink(entry)
+                    && zip_entry_name(entry).as_deref() == Some(name)
+            }) else {
+                return Ok(None);
+            };
+            let entry = &zip.file().entries()[index];
+            let size = entry.uncompressed_size();
+            if entry.compression() == Compression::Stored {
+                let offset = zip_data_offset(path, entry.header_offset()).await?;
+                return Ok(Some(MemberData::Raw { offset, size }));
+            }
+            let reader = zip.into_entry(index).await?.compat();
+            Ok(Some(MemberData::Stream {
+                reader: Box::pin(reader),
+                size,
+            }))
+        }
+        ArchiveKind::Tar => open_tar_member(reader, name, true).await,
+        ArchiveKind::TarGz => open_tar_member(GzipDecoder::new(reader), name, false).await,
+        ArchiveKind::TarZst => open_tar_member(ZstdDecoder::new(reader), name, false).await,
+    }
+}
+
+async fn list_tar_members<R: AsyncRead + Unpin + Send>(
+    reader: R,
+    members: &mut Vec<Member>,
+) -> Result<()> {
+    let mut archive = tokio_tar::Archive::new(reader);
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.try_next().await? {
+        let Some(name) = normalize_name(&String::from_utf8_lossy(&entry.path_bytes())) else {
+            continue;
+        };
+        let header = entry.header();
+        members.push(Member {
+            name,
+            kind: tar_member_kind(header.entry_type()),
+            size: header.size().unwrap_or_default(),
+            mtime: header.mtime().unwrap_or_default() * 1000,
+        });
+    }
+    Ok(())
+}
+
+/// Find a tar member, its data is raw if the tar is not compressed.
+async fn open_tar_member<R: AsyncRead + Unpin + Send + 'static>(
+    reader: R,
+    name: &str,
+    raw: bool,
+) -> Result<Option<MemberData>> {
+    let mut archive = tokio_tar::Archive::new(reader);
+    let mut entries = archive.entries()?;
+    while let Some(entry) = entries.try_next().await? {
+        let header = entry.header();
+        if tar_member_kind(header.entry_type()) != MemberKind::File
+            || normalize_name(&String::from_utf8_lossy(&entry.path_bytes())).as_deref()
+                != Some(name)
+        {
+            continue;
+        }
+        let size = header.size()?;
+        if raw {
+            return Ok(Some(MemberData::Raw {
+                offset: entry.raw_file_position(),
+                size,
+            }));
+        }
+        return Ok(Some(MemberData::Stream {
+            reader: Box::pin(entry),
+            size,
+        }));
+    }
+    Ok(None)
+}
+
+fn tar_member_kind(entry_type: tokio_tar::EntryType) -> MemberKind {
+    if entry_type.is_dir() {
+        MemberKind::Dir
+    } else if entry_type.is_file() || entry_type.is_contiguous() {
+        MemberKind::File
+    } else {
+        MemberKind::Other
+    }
+}
+
+fn zip_entry_name(entry: &async_zip::ZipEntry) -> Option<String> {
+    let name = match entry.filename().as_str() {
+        Ok(v) => v.to_string(),
+        Err(_) => String::from_utf8_lossy(entry.filename().as_bytes()).to_string(),
+    };
+    normalize_name(&name)
+}
+
+fn is_zip_symlink(entry: &async_zip::ZipEntry) -> bool {
+    entry
+        .unix_permissions()
+        .map(|v| v & 0o170000 == 0o120000)
+        .unwrap_or_default()
+}
+
+/// The local header may have other extra fields than the central one, so read it.
+async fn zip_data_offset(path: &Path, header_offset: u64) -> Result<u64> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(header_offset)).await?;
+    let mut header = [0u8; ZIP_LOCAL_HEADER_SIZE as usize];
+    file.read_exact(&mut header).await?;
+    let name_len = u16::from_le_bytes([header[26], header[27]]) as u64;
+    let extra_len = u16::from_le_bytes([header[28], header[29]]) as u64;
+    Ok(header_offset + ZIP_LOCAL_HEADER_SIZE + name_len + extra_len)
+}
+
+/// Normalize a member name to a relative `a/b/c` path, returns `None` for absolute names
+/// and names with `..` that could escape the directory they are extracted to.
+pub fn normalize_name(name: &str) -> Option<String> {
+    let name = name.replace('\\', "/");
+    if name.starts_with('/') || name.contains('\0') {
+        return None;
+    }
+    let mut parts = vec![];
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            _ if cfg!(windows) && part.contains(':') => return None,
+            _ => parts.push(part),
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(parts.join("/"))
+}
@@ -8,11 +8,18 @@ use smart_default::SmartDefault;
 use std::env;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::auth::AccessControl;
+use crate::auth_provider::{CommandProvider, HtpasswdProvider, UsersFileProvider};
 use crate::http_logger::HttpLogger;
+use crate::ip_filter::{parse_cidrs, IpFilter};
+use crate::oidc::{OidcRule, Sessions};
+use crate::token::{ApiToken, TokenStore};
 use crate::utils::encode_uri;
 
+const LETS_ENCRYPT_DIRECTORY: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
 pub fn build_cli() -> Command {
     let app = Command::new(env!("CARGO_CRATE_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
@@ -99,6 +106,154 @@ pub fn build_cli() -> Command {
                 .default_value("digest")
                 .value_name("value"),
         )
+        .arg(
+            Arg::new("auth-htpasswd")
+                .env("DUFS_AUTH_HTPASSWD")
+                .hide_env(true)
+                .long("auth-htpasswd")
+                .value_parser(value_parser!(PathBuf))
+                .help("Check users with an htpasswd file")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("auth-users-file")
+                .env("DUFS_AUTH_USERS_FILE")
+                .hide_env(true)
+                .long("auth-users-file")
+                .value_parser(value_parser!(PathBuf))
+                .help("Check users with a JSON or YAML users file, reloaded on change")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("auth-command")
+                .env("DUFS_AUTH_COMMAND")
+                .hide_env(true)
+                .long("auth-command")
+                .help("Check users with an external program")
+                .value_name("program"),
+        )
+        .arg(
+            Arg::new("auth-max-failures")
+                .env("DUFS_AUTH_MAX_FAILURES")
+                .hide_env(true)
+                .long("auth-max-failures")
+                .value_parser(value_parser!(u32))
+                .help("Lock out a client or user after failed logins, 0 to disable [default: 5]")
+                .value_name("count"),
+        )
+        .arg(
+            Arg::new("auth-lockout")
+                .env("DUFS_AUTH_LOCKOUT")
+                .hide_env(true)
+                .long("auth-lockout")
+                .value_parser(value_parser!(u64))
+                .help("The longest lockout, doubled from 1s on each failure [default: 900]")
+                .value_name("secs"),
+        )
+        .arg(
+            Arg::new("allow-ip")
+                .env("DUFS_ALLOW_IP")
+                .hide_env(true)
+                .long("allow-ip")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Only serve clients from these networks, e.g. 10.0.0.0/8,192.168.1.5")
+                .value_name("cidrs"),
+        )
+        .arg(
+            Arg::new("deny-ip")
+                .env("DUFS_DENY_IP")
+                .hide_env(true)
+                .long("deny-ip")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Refuse clients from these networks")
+                .value_name("cidrs"),
+        )
+        .arg(
+            Arg::new("trusted-proxy")
+                .env("DUFS_TRUSTED_PROXY")
+                .hide_env(true)
+                .long("trusted-proxy")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Take the client ip from Forwarded/X-Forwarded-For behind these proxies")
+                .value_name("cidrs"),
+        )
+        .arg(
+            Arg::new("share-secret")
+                .env("DUFS_SHARE_SECRET")
+                .hide_env(true)
+                .long("share-secret")
+                .help("Sign share links with a secret, so they work after restarts")
+                .value_name("secret"),
+        )
+        .arg(
+            Arg::new("token-file")
+                .env("DUFS_TOKEN_FILE")
+                .hide_env(true)
+                .long("token-file")
+                .value_parser(value_parser!(PathBuf))
+                .help("Save the API tokens created by admins to a file")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("allow-query-token")
+                .env("DUFS_ALLOW_QUERY_TOKEN")
+                .hide_env(true)
+                .long("allow-query-token")
+                .action(ArgAction::SetTrue)
+                .help("Accept API tokens in the `?token=` query parameter of downloads"),
+        )
+        .arg(
+            Arg::new("oidc-issuer")
+                .env("DUFS_OIDC_ISSUER")
+                .hide_env(true)
+                .long("oidc-issuer")
+                .help("Log users in with an OpenID Connect provider")
+                .value_name("url"),
+        )
+        .arg(
+            Arg::new("oidc-client-id")
+                .env("DUFS_OIDC_CLIENT_ID")
+                .hide_env(true)
+                .long("oidc-client-id")
+                .help("Client id registered at the OpenID Connect provider")
+                .value_name("id"),
+        )
+        .arg(
+            Arg::new("oidc-client-secret")
+                .env("DUFS_OIDC_CLIENT_SECRET")
+                .hide_env(true)
+                .long("oidc-client-secret")
+                .help("Client secret, unless the client is public")
+                .value_name("secret"),
+        )
+        .arg(
+            Arg::new("oidc-redirect-url")
+                .env("DUFS_OIDC_REDIRECT_URL")
+                .hide_env(true)
+                .long("oidc-redirect-url")
+                .help("Override the redirect url, defaults to <origin><path-prefix>/?login")
+                .value_name("url"),
+        )
+        .arg(
+            Arg::new("oidc-rule")
+                .env("DUFS_OIDC_RULE")
+                .hide_env(true)
+                .long("oidc-rule")
+                .help("Grant paths by claim, e.g. groups=admins@/:rw or *@/dir1")
+                .action(ArgAction::Append)
+                .value_name("rule"),
+        )
+        .arg(
+            Arg::new("session-secret")
+                .env("DUFS_SESSION_SECRET")
+                .hide_env(true)
+                .long("session-secret")
+                .help("Sign login sessions with a secret, so they survive restarts")
+                .value_name("secret"),
+        )
         .arg(
             Arg::new("allow-all")
                 .env("DUFS_ALLOW_ALL")
@@ -148,6 +303,24 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Allow zip archive generation"),
         )
+        .arg(
+            Arg::new("extract-max-size")
+                .env("DUFS_EXTRACT_MAX_SIZE")
+                .hide_env(true)
+                .long("extract-max-size")
+                .value_parser(value_parser!(u64))
+                .help("Limit the total bytes extracted from an uploaded archive")
+                .value_name("bytes"),
+        )
+        .arg(
+            Arg::new("extract-max-upload")
+                .env("DUFS_EXTRACT_MAX_UPLOAD")
+                .hide_env(true)
+                .long("extract-max-upload")
+                .value_parser(value_parser!(u64))
+                .help("Limit the bytes of an archive uploaded for extraction")
+                .value_name("bytes"),
+        )
         .arg(
             Arg::new("enable-cors")
                 .env("DUFS_ENABLE_CORS")
@@ -156,6 +329,23 @@ pub fn build_cli() -> Command {
                 .action(ArgAction::SetTrue)
                 .help("Enable CORS, sets `Access-Control-Allow-Origin: *`"),
         )
+        .arg(
+            Arg::new("watch")
+                .env("DUFS_WATCH")
+                .hide_env(true)
+                .long("watch")
+                .action(ArgAction::SetTrue)
+                .help("Watch the serve path for changes made outside dufs"),
+        )
+        .arg(
+            Arg::new("search-index")
+                .env("DUFS_SEARCH_INDEX")
+                .hide_env(true)
+                .long("search-index")
+                .help("Keep a persistent search index in the file, implies --watch")
+                .value_parser(value_parser!(PathBuf))
+                .value_name("file"),
+        )
         .arg(
             Arg::new("render-index")
                 .env("DUFS_RENDER_INDEX")
@@ -204,7 +394,16 @@ pub fn build_cli() -> Command {
                 .value_parser(clap::builder::EnumValueParser::<Compress>::new())
                 .long("compress")
                 .value_name("level")
-                .help("Set zip compress level [default: low]")
+                .help("Set archive compress level [default: low]")
+        )
+        .arg(
+            Arg::new("shutdown-timeout")
+                .env("DUFS_SHUTDOWN_TIMEOUT")
+                .hide_env(true)
+                .long("shutdown-timeout")
+                .value_parser(value_parser!(u64))
+                .help("Wait for in-flight requests on shutdown before exiting [default: 30]")
+                .value_name("secs"),
         )
         .arg(
             Arg::new("completions")
@@ -233,7 +432,8 @@ pub fn build_cli() -> Command {
                 .long("tls-cert")
                 .value_name("path")
                 .value_parser(value_parser!(PathBuf))
-                .help("Path to an SSL/TLS certificate to serve with HTTPS"),
+                .action(ArgAction::Append)
+                .help("Path to an SSL/TLS certificate to serve with HTTPS, repeat for SNI"),
         )
         .arg(
             Arg::new("tls-key")
@@ -242,9 +442,91 @@ pub fn build_cli() -> Command {
                 .long("tls-key")
                 .value_name("path")
                 .value_parser(value_parser!(PathBuf))
-                .help("Path to the SSL/TLS certificate's private key"),
+                .action(ArgAction::Append)
+                .help("Path to the SSL/TLS certificate's private key, one for each tls-cert"),
+        )
+        .arg(
+            Arg::new("tls-client-ca")
+                .env("DUFS_TLS_CLIENT_CA")
+                .hide_env(true)
+                .long("tls-client-ca")
+                .value_name("path")
+                .value_parser(value_parser!(PathBuf))
+                .help("Verify client certificates with a CA bundle, logging their users in"),
+        )
+        .arg(
+            Arg::new("acme-domain")
+                .env("DUFS_ACME_DOMAIN")
+                .hide_env(true)
+                .long("acme-domain")
+                .action(ArgAction::Append)
+                .value_delimiter(',')
+                .help("Serve HTTPS with a certificate from an ACME CA for these domains")
+                .value_name("domain"),
+        )
+        .arg(
+            Arg::new("acme-email")
+                .env("DUFS_ACME_EMAIL")
+                .hide_env(true)
+                .long("acme-email")
+                .help("Contact email of the ACME account")
+                .value_name("email"),
+        )
+        .arg(
+            Arg::new("acme-cache")
+                .env("DUFS_ACME_CACHE")
+                .hide_env(true)
+                .long("acme-cache")
+                .value_parser(value_parser!(PathBuf))
+                .help("Keep the ACME account and certificates in a directory")
+                .value_name("dir"),
+        )
+        .arg(
+            Arg::new("acme-directory")
+                .env("DUFS_ACME_DIRECTORY")
+                .hide_env(true)
+                .long("acme-directory")
+                .help("Directory url of the ACME CA [default: Let's Encrypt]")
+                .value_name("url"),
+        )
+        .arg(
+            Arg::new("acme-ca-root")
+                .env("DUFS_ACME_CA_ROOT")
+                .hide_env(true)
+                .long("acme-ca-root")
+                .value_parser(value_parser!(PathBuf))
+                .help("Trust a CA certificate for the ACME directory, e.g. for a test CA")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("acme-challenge")
+                .env("DUFS_ACME_CHALLENGE")
+                .hide_env(true)
+                .long("acme-challenge")
+                .value_parser(clap::builder::EnumValueParser::<AcmeChallenge>::new())
+                .help("How to prove control of the domains [default: tls-alpn-01]")
+                .value_name("type"),
+        )
+        .arg(
+            Arg::new("acme-http-port")
+                .env("DUFS_ACME_HTTP_PORT")
+                .hide_env(true)
+                .long("acme-http-port")
+                .value_parser(value_parser!(u16))
+                .help("Port for http-01 challenges, redirecting the rest to HTTPS [default: 80]")
+                .value_name("port"),
         );
 
+    #[cfg(feature = "http3")]
+    let app = app.arg(
+        Arg::new("http3")
+            .env("DUFS_HTTP3")
+            .hide_env(true)
+            .long("http3")
+            .action(ArgAction::SetTrue)
+            .help("Also serve HTTP/3 over QUIC on the UDP port of the HTTPS listeners"),
+    );
+
     app
 }
 
@@ -276,23 +558,67 @@ pub struct Args {
     pub hidden: Vec<String>,
     #[serde(deserialize_with = "deserialize_access_control")]
     pub auth: AccessControl,
+    pub auth_htpasswd: Option<PathBuf>,
+    pub auth_users_file: Option<PathBuf>,
+    pub auth_command: Option<String>,
+    #[default(5)]
+    pub auth_max_failures: u32,
+    #[default(900)]
+    pub auth_lockout: u64,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub allow_ips: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub deny_ips: Vec<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub trusted_proxies: Vec<String>,
+    pub tokens: Vec<ApiToken>,
+    pub token_file: Option<PathBuf>,
+    pub allow_query_token: bool,
+    pub share_secret: Option<String>,
+    pub oidc_issuer: Option<String>,
+    pub oidc_client_id: Option<String>,
+    pub oidc_client_secret: Option<String>,
+    pub oidc_redirect_url: Option<String>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub oidc_rules: Vec<String>,
+    pub session_secret: Option<String>,
     pub allow_all: bool,
     pub allow_upload: bool,
     pub allow_delete: bool,
     pub allow_search: bool,
     pub allow_symlink: bool,
     pub allow_archive: bool,
+    pub extract_max_size: Option<u64>,
+    pub extract_max_upload: Option<u64>,
     pub render_index: bool,
     pub render_spa: bool,
     pub render_try_index: bool,
     pub enable_cors: bool,
+    pub watch: bool,
+    pub search_index: Option<PathBuf>,
     pub assets: Option<PathBuf>,
     #[serde(deserialize_with = "deserialize_log_http")]
     #[serde(rename = "log-format")]
     pub http_logger: HttpLogger,
     pub compress: Compress,
-    pub tls_cert: Option<PathBuf>,
-    pub tls_key: Option<PathBuf>,
+    #[default(30)]
+    pub shutdown_timeout: u64,
+    #[serde(deserialize_with = "deserialize_paths")]
+    pub tls_cert: Vec<PathBuf>,
+    #[serde(deserialize_with = "deserialize_paths")]
+    pub tls_key: Vec<PathBuf>,
+    pub tls_client_ca: Option<PathBuf>,
+    #[serde(deserialize_with = "deserialize_string_or_vec")]
+    pub acme_domains: Vec<String>,
+    pub acme_email: Option<String>,
+    pub acme_cache: Option<PathBuf>,
+    #[default(LETS_ENCRYPT_DIRECTORY.to_string())]
+    pub acme_directory: String,
+    pub acme_ca_root: Option<PathBuf>,
+    pub acme_challenge: AcmeChallenge,
+    #[default(80)]
+    pub acme_http_port: u16,
+    pub http3: bool,
     pub domain: String,
 }
 
@@ -362,6 +688,102 @@ impl Args {
             args.auth = AccessControl::new(&rules)?;
         }
 
+        if let Some(path) = matches.get_one::<PathBuf>("auth-htpasswd") {
+            args.auth_htpasswd = Some(path.clone());
+        }
+        if let Some(path) = &args.auth_htpasswd {
+            args.auth
+                .add_provider(Arc::new(HtpasswdProvider::new(path)?));
+        }
+
+        if let Some(path) = matches.get_one::<PathBuf>("auth-users-file") {
+            args.auth_users_file = Some(path.clone());
+        }
+        if let Some(path) = &args.auth_users_file {
+            args.auth
+                .add_provider(Arc::new(UsersFileProvider::new(path)?));
+        }
+
+        if let Some(program) = matches.get_one::<String>("auth-command") {
+            args.auth_command = Some(program.clone());
+        }
+        if let Some(program) = &args.auth_command {
+            args.auth
+                .add_provider(Arc::new(CommandProvider::new(program)?));
+        }
+
+        if let Some(count) = matches.get_one::<u32>("auth-max-failures") {
+            args.auth_max_failures = *count;
+        }
+        if let Some(secs) = matches.get_one::<u64>("auth-lockout") {
+            args.auth_lockout = *secs;
+        }
+
+        if let Some(values) = matches.get_many::<String>("allow-ip") {
+            args.allow_ips = values.cloned().collect();
+        }
+        if let Some(values) = matches.get_many::<String>("deny-ip") {
+            args.deny_ips = values.cloned().collect();
+        }
+        args.auth
+            .set_ip_filter(IpFilter::new(&args.allow_ips, &args.deny_ips)?);
+        if let Some(values) = matches.get_many::<String>("trusted-proxy") {
+            args.trusted_proxies = values.cloned().collect();
+        }
+        parse_cidrs(&args.trusted_proxies)?;
+
+        if let Some(path) = matches.get_one::<PathBuf>("token-file") {
+            args.token_file = Some(path.clone());
+        }
+        if !args.allow_query_token {
+            args.allow_query_token = matches.get_flag("allow-query-token");
+        }
+        if !args.tokens.is_empty() || args.token_file.is_some() {
+            let tokens = TokenStore::new(&args.tokens, args.token_file.as_deref())?;
+            args.auth.set_tokens(Arc::new(tokens));
+        }
+
+        if let Some(secret) = matches.get_one::<String>("share-secret") {
+            args.share_secret = Some(secret.clone());
+        }
+
+        if let Some(issuer) = matches.get_one::<String>("oidc-issuer") {
+            args.oidc_issuer = Some(issuer.clone());
+        }
+        if let Some(client_id) = matches.get_one::<String>("oidc-client-id") {
+            args.oidc_client_id = Some(client_id.clone());
+        }
+        if let Some(secret) = matches.get_one::<String>("oidc-client-secret") {
+            args.oidc_client_secret = Some(secret.clone());
+        }
+        if let Some(url) = matches.get_one::<String>("oidc-redirect-url") {
+            args.oidc_redirect_url = Some(url.clone());
+        }
+        if let Some(rules) = matches.get_many::<String>("oidc-rule") {
+            args.oidc_rules = rules.cloned().collect();
+        }
+        if let Some(secret) = matches.get_one::<String>("session-secret") {
+            args.session_secret = Some(secret.clone());
+        }
+        if args.oidc_issuer.is_some() {
+            if args.oidc_client_id.is_none() {
+                bail!("No oidc-client-id set");
+            }
+            for rule in &args.oidc_rules {
+                OidcRule::parse(rule)?;
+            }
+            let sessions = Sessions::new(args.session_secret.as_deref());
+            args.auth.set_sessions(Arc::new(sessions));
+        }
+
+        if !args.watch {
+            args.watch = matches.get_flag("watch");
+        }
+
+        if let Some(search_index) = matches.get_one::<PathBuf>("search-index") {
+            args.search_index = Some(search_index.clone());
+        }
+
         if !args.allow_all {
             args.allow_all = matches.get_flag("allow-all");
         }
@@ -383,6 +805,12 @@ impl Args {
         if !args.allow_archive {
             args.allow_archive = allow_all || matches.get_flag("allow-archive");
         }
+        if let Some(extract_max_size) = matches.get_one::<u64>("extract-max-size") {
+            args.extract_max_size = Some(*extract_max_size);
+        }
+        if let Some(extract_max_upload) = matches.get_one::<u64>("extract-max-upload") {
+            args.extract_max_upload = Some(*extract_max_upload);
+        }
         if !args.render_index {
             args.render_index = matches.get_flag("render-index");
         }
@@ -411,32 +839,91 @@ impl Args {
             args.compress = *compress;
         }
 
+        if let Some(secs) = matches.get_one::<u64>("shutdown-timeout") {
+            args.shutdown_timeout = *secs;
+        }
+
         #[cfg(feature = "tls")]
         {
-            if let Some(tls_cert) = matches.get_one::<PathBuf>("tls-cert") {
-                args.tls_cert = Some(tls_cert.clone())
+            if let Some(tls_cert) = matches.get_many::<PathBuf>("tls-cert") {
+                args.tls_cert = tls_cert.cloned().collect()
+            }
+
+            if let Some(tls_key) = matches.get_many::<PathBuf>("tls-key") {
+                args.tls_key = tls_key.cloned().collect()
             }
 
-            if let Some(tls_key) = matches.get_one::<PathBuf>("tls-key") {
-                args.tls_key = Some(tls_key.clone())
+            match (args.tls_cert.len(), args.tls_key.len()) {
+                (0, 0) => {}
+                (_, 0) => bail!("No tls-key set"),
+                (0, _) => bail!("No tls-cert set"),
+                (certs, keys) if certs != keys => bail!("Each tls-cert needs its own tls-key"),
+                _ => {}
             }
 
-            match (&args.tls_cert, &args.tls_key) {
-                (Some(_), Some(_)) => {}
-                (Some(_), _) => bail!("No tls-key set"),
-                (_, Some(_)) => bail!("No tls-cert set"),
-                (None, None) => {}
+            if let Some(tls_client_ca) = matches.get_one::<PathBuf>("tls-client-ca") {
+                args.tls_client_ca = Some(tls_client_ca.clone())
+            }
+
+            if let Some(domains) = matches.get_many::<String>("acme-domain") {
+                args.acme_domains = domains.cloned().collect();
+            }
+            if let Some(email) = matches.get_one::<String>("acme-email") {
+                args.acme_email = Some(email.clone());
+            }
+            if let Some(dir) = matches.get_one::<PathBuf>("acme-cache") {
+                args.acme_cache = Some(dir.clone());
+            }
+            if let Some(url) = matches.get_one::<String>("acme-directory") {
+                args.acme_directory = url.clone();
+            }
+            if let Some(path) = matches.get_one::<PathBuf>("acme-ca-root") {
+                args.acme_ca_root = Some(path.clone());
+            }
+            if let Some(challenge) = matches.get_one::<AcmeChallenge>("acme-challenge") {
+                args.acme_challenge = *challenge;
+            }
+            if let Some(port) = matches.get_one::<u16>("acme-http-port") {
+                args.acme_http_port = *port;
+            }
+            if !args.acme_domains.is_empty() && args.acme_cache.is_none() {
+                bail!("No acme-cache set, certificates have to be kept across restarts")
+            }
+
+            if args.tls_client_ca.is_some() && !args.tls() {
+                bail!("No tls-cert set, client certificates need TLS")
             }
         }
         #[cfg(not(feature = "tls"))]
         {
-            args.tls_cert = None;
-            args.tls_key = None;
+            args.tls_cert = vec![];
+            args.tls_key = vec![];
+            args.tls_client_ca = None;
+            args.acme_domains = vec![];
+        }
+
+        #[cfg(feature = "http3")]
+        {
+            if !args.http3 {
+                args.http3 = matches.get_flag("http3");
+            }
+            if args.http3 && !args.tls() {
+                bail!("No tls-cert set, HTTP/3 needs TLS")
+            }
+        }
+        #[cfg(not(feature = "http3"))]
+        {
+            args.http3 = false;
         }
 
         Ok(args)
     }
 
+    /// Whether the listeners serve HTTPS, with certificate files or from ACME.
+    pub fn tls(&self) -> bool {
+        !self.tls_cert.is_empty() || !self.acme_domains.is_empty()
+    }
+
     fn sanitize_path<P: AsRef<Path>>(path: P) -> Result<PathBuf> {
         let path = path.as_ref();
         if !path.exists() {
@@ -491,6 +978,37 @@ impl BindAddr {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum AcmeChallenge {
+    #[serde(rename = "http-01")]
+    Http01,
+    #[default]
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+}
+
+impl ValueEnum for AcmeChallenge {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Http01, Self::TlsAlpn01]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        Some(match self {
+            AcmeChallenge::Http01 => PossibleValue::new("http-01"),
+            AcmeChallenge::TlsAlpn01 => PossibleValue::new("tls-alpn-01"),
+        })
+    }
+}
+
+impl AcmeChallenge {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AcmeChallenge::Http01 => "http-01",
+            AcmeChallenge::TlsAlpn01 => "tls-alpn-01",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Compress {
@@ -530,6 +1048,25 @@ impl Compress {
             Compress::High => Compression::Xz,
         }
     }
+
+    /// The gzip level, `None` for `none`, whose stream is written in deflate stored
+    /// blocks instead.
+    pub fn to_gzip_level(self) -> Option<async_compression::Level> {
+        match self {
+            Compress::None => None,
+            _ => Some(self.to_zstd_level()),
+        }
+    }
+
+    /// Zstd has no stored mode, so `none` picks its fastest level.
+    pub fn to_zstd_level(self) -> async_compression::Level {
+        match self {
+            Compress::None => async_compression::Level::Fastest,
+            Compress::Low => async_compression::Level::Fastest,
+            Compress::Medium => async_compression::Level::Default,
+            Compress::High => async_compression::Level::Best,
+        }
+    }
 }
 
 fn deserialize_bind_addrs<'de, D>(deserializer: D) -> Result<Vec<BindAddr>, D::Error>
@@ -565,6 +1102,14 @@ where
     deserializer.deserialize_any(StringOrVec)
 }
 
+fn deserialize_paths<'de, D>(deserializer: D) -> Result<Vec<PathBuf>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let paths = deserialize_string_or_vec(deserializer)?;
+    Ok(paths.into_iter().map(PathBuf::from).collect())
+}
+
 fn deserialize_string_or_vec<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
     D: Deserializer<'de>,
@@ -1,4 +1,12 @@
-use crate::{args::Args, server::Response, utils::unix_now};
+use crate::{
+    args::Args,
+    auth_provider::AuthProvider,
+    ip_filter::IpFilter,
+    oidc::{Session, Sessions},
+    server::Response,
+    token::TokenStore,
+    utils::unix_now,
+};
 
 use anyhow::{anyhow, bail, Result};
 use base64::{engine::general_purpose, Engine as _};
@@ -9,12 +17,17 @@ use lazy_static::lazy_static;
 use md5::Context;
 use std::{
     collections::HashMap,
+    net::IpAddr,
     path::{Path, PathBuf},
+    sync::{Arc, Mutex},
 };
 use uuid::Uuid;
 
 const REALM: &str = "DUFS";
 const DIGEST_AUTH_TIMEOUT: u32 = 604800; // 7 days
+const DIGEST_MAX_NONCES: usize = 65536;
+const NONCE_COUNT_WINDOW: u32 = 64;
+const PROVIDER_USER_PATHS: &str = "/:rw";
 
 lazy_static! {
     static ref NONCESTARTHASH: Context = {
@@ -23,13 +36,33 @@ lazy_static! {
         h.consume(std::process::id().to_be_bytes());
         h
     };
+    static ref NONCE_COUNTS: Mutex<HashMap<Vec<u8>, NonceCount>> = Mutex::new(HashMap::new());
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// The nonce counts accepted for a nonce, with the time it was issued. Bit `n` of `seen`
+/// is set once `highest - n` was accepted.
+#[derive(Debug)]
+struct NonceCount {
+    issued: u32,
+    highest: u32,
+    seen: u64,
+}
+
+#[derive(Debug, Clone)]
 pub struct AccessControl {
     use_hashed_password: bool,
     users: IndexMap<String, (String, AccessPaths)>,
+    provider_users: IndexMap<String, AccessPaths>,
+    groups: IndexMap<String, String>,
+    memberships: IndexMap<String, Vec<String>>,
     anonymous: Option<AccessPaths>,
+    anonymous_ips: IpFilter,
+    user_ips: IndexMap<String, IpFilter>,
+    ip_filter: IpFilter,
+    providers: Vec<Arc<dyn AuthProvider>>,
+    tokens: Option<Arc<TokenStore>>,
+    sessions: Option<Arc<Sessions>>,
+    has_rules: bool,
 }
 
 impl Default for AccessControl {
@@ -37,33 +70,103 @@ impl Default for AccessControl {
         AccessControl {
             use_hashed_password: false,
             users: IndexMap::new(),
+            provider_users: IndexMap::new(),
+            groups: IndexMap::new(),
+            memberships: IndexMap::new(),
             anonymous: Some(AccessPaths::new(AccessPerm::ReadWrite)),
+            anonymous_ips: IpFilter::default(),
+            user_ips: IndexMap::new(),
+            ip_filter: IpFilter::default(),
+            providers: vec![],
+            tokens: None,
+            sessions: None,
+            has_rules: false,
         }
     }
 }
 
+impl PartialEq for AccessControl {
+    fn eq(&self, other: &Self) -> bool {
+        self.use_hashed_password == other.use_hashed_password
+            && self.users == other.users
+            && self.provider_users == other.provider_users
+            && self.groups == other.groups
+            && self.memberships == other.memberships
+            && self.anonymous == other.anonymous
+            && self.anonymous_ips == other.anonymous_ips
+            && self.user_ips == other.user_ips
+            && self.ip_filter == other.ip_filter
+            && self.has_rules == other.has_rules
+            && self.providers.len() == other.providers.len()
+            && self
+                .providers
+                .iter()
+                .zip(other.providers.iter())
+                .all(|(a, b)| Arc::ptr_eq(a, b))
+            && match (&self.tokens, &other.tokens) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+            && match (&self.sessions, &other.sessions) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+    }
+}
+
 impl AccessControl {
     pub fn new(raw_rules: &[&str]) -> Result<Self> {
         if raw_rules.is_empty() {
             return Ok(Default::default());
         }
         let new_raw_rules = split_rules(raw_rules);
-        let mut use_hashed_password = false;
         let mut annoy_paths = None;
+        let mut anonymous_ips = IpFilter::default();
+        let mut user_ips = IndexMap::new();
         let mut account_paths_pairs = vec![];
+        let mut provider_user_paths_pairs = vec![];
+        let mut groups = IndexMap::new();
+        let mut memberships: IndexMap<String, Vec<String>> = IndexMap::new();
         for rule in &new_raw_rules {
             let (account, paths) =
                 split_account_paths(rule).ok_or_else(|| anyhow!("Invalid auth `{rule}`"))?;
+            let (paths, ips) = split_paths_ips(paths);
             if account.is_empty() {
                 if annoy_paths.is_some() {
                     bail!("Invalid auth, no duplicate anonymous rules");
                 }
-                annoy_paths = Some(paths)
+                annoy_paths = Some(paths);
+                anonymous_ips = ips.unwrap_or_default();
+            } else if let Some(group) = account.strip_prefix('+') {
+                if ips.is_some() {
+                    bail!("Invalid auth `{rule}`, groups have no ip list");
+                }
+                let (name, members) = group.split_once('=').unwrap_or((group, ""));
+                if name.is_empty() || AccessPaths::default().merge(paths).is_none() {
+                    bail!("Invalid auth `{rule}`");
+                }
+                if groups.insert(name.to_string(), paths.to_string()).is_some() {
+                    bail!("Invalid auth, no duplicate group `{name}`");
+                }
+                for member in members.split(',').filter(|v| !v.is_empty()) {
+                    memberships
+                        .entry(member.to_string())
+                        .or_default()
+                        .push(name.to_string());
+                }
             } else if let Some((user, pass)) = account.split_once(':') {
                 if user.is_empty() || pass.is_empty() {
                     bail!("Invalid auth `{rule}`");
                 }
+                if let Some(ips) = ips {
+                    user_ips.insert(user.to_string(), ips);
+                }
                 account_paths_pairs.push((user, pass, paths));
+            } else {
+                if let Some(ips) = ips {
+                    user_ips.insert(account.to_string(), ips);
+                }
+                provider_user_paths_pairs.push((account, paths));
             }
         }
         let mut anonymous = None;
@@ -72,62 +175,309 @@ impl AccessControl {
             access_paths.merge(paths);
             anonymous = Some(access_paths);
         }
-        let mut users = IndexMap::new();
+        let mut this = Self {
+            use_hashed_password: false,
+            users: IndexMap::new(),
+            provider_users: IndexMap::new(),
+            groups,
+            memberships,
+            anonymous,
+            anonymous_ips,
+            user_ips,
+            ip_filter: IpFilter::default(),
+            providers: vec![],
+            tokens: None,
+            sessions: None,
+            has_rules: true,
+        };
         for (user, pass, paths) in account_paths_pairs.into_iter() {
-            let mut access_paths = anonymous.clone().unwrap_or_default();
-            access_paths
-                .merge(paths)
+            let access_paths = this
+                .member_paths(user, paths, &[])
                 .ok_or_else(|| anyhow!("Invalid auth `{user}:{pass}@{paths}"))?;
             if pass.starts_with("$6$") {
-                use_hashed_password = true;
+                this.use_hashed_password = true;
             }
-            users.insert(user.to_string(), (pass.to_string(), access_paths));
+            this.users
+                .insert(user.to_string(), (pass.to_string(), access_paths));
+        }
+        for (user, paths) in provider_user_paths_pairs.into_iter() {
+            let access_paths = this
+                .member_paths(user, paths, &[])
+                .ok_or_else(|| anyhow!("Invalid auth `{user}@{paths}`"))?;
+            this.provider_users.insert(user.to_string(), access_paths);
         }
 
-        Ok(Self {
-            use_hashed_password,
-            users,
-            anonymous,
-        })
+        Ok(this)
+    }
+
+    /// Check the users unknown to the rules with `provider`. They get the paths of a
+    /// `user@/path` rule if there is one, otherwise the paths the provider gives or `/:rw`.
+    pub fn add_provider(&mut self, provider: Arc<dyn AuthProvider>) {
+        if !self.has_rules {
+            self.anonymous = None;
+        }
+        // Providers need the password itself, so only basic auth works with them. Clients
+        // are then only offered basic auth, the users of the rules included.
+        self.use_hashed_password = true;
+        self.providers.push(provider);
+    }
+
+    /// Accept the API tokens of `tokens` as `Authorization: Bearer <token>`.
+    pub fn set_tokens(&mut self, tokens: Arc<TokenStore>) {
+        self.tokens = Some(tokens);
+    }
+
+    pub fn tokens(&self) -> Option<&Arc<TokenStore>> {
+        self.tokens.as_ref()
+    }
+
+    /// Accept the sessions of `sessions` as `Authorization: Session <cookie>`. Session users
+    /// get the paths of a `user@/path` rule if there is one, otherwise the paths their
+    /// claims were granted.
+    pub fn set_sessions(&mut self, sessions: Arc<Sessions>) {
+        if !self.has_rules {
+            self.anonymous = None;
+        }
+        self.sessions = Some(sessions);
+    }
+
+    pub fn sessions(&self) -> Option<&Arc<Sessions>> {
+        self.sessions.as_ref()
     }
 
     pub fn exist(&self) -> bool {
-        !self.users.is_empty()
+        self.has_passwords() || self.sessions.is_some()
     }
 
-    pub fn guard(
+    /// Whether some users log in with a password, so the clients should be asked for one.
+    pub fn has_passwords(&self) -> bool {
+        !self.users.is_empty() || !self.providers.is_empty()
+    }
+
+    /// Only let the clients of `filter` in, whoever they are.
+    pub fn set_ip_filter(&mut self, filter: IpFilter) {
+        self.ip_filter = filter;
+    }
+
+    pub fn allows_ip(&self, ip: Option<IpAddr>) -> bool {
+        self.ip_filter.allows(ip)
+    }
+
+    /// The user a client certificate logs in, the first of its `names` with a rule.
+    pub fn cert_user(&self, names: &[String]) -> Option<String> {
+        names
+            .iter()
+            .find(|v| {
+                self.users.contains_key(*v)
+                    || self.provider_users.contains_key(*v)
+                    || self.memberships.contains_key(*v)
+            })
+            .cloned()
+    }
+
+    /// Check the credentials of a request, once, before its paths are guarded.
+    pub fn authenticate(&self, method: &Method, authorization: Option<&HeaderValue>) -> Identity {
+        let Some(authorization) = authorization else {
+            return Identity {
+                user: None,
+                paths: self.anonymous.clone(),
+                rejected: false,
+            };
+        };
+        let (user, paths) = self.authenticate_impl(method, authorization);
+        Identity {
+            rejected: user.is_none(),
+            user,
+            paths,
+        }
+    }
+
+    fn authenticate_impl(
         &self,
-        path: &str,
         method: &Method,
-        authorization: Option<&HeaderValue>,
+        authorization: &HeaderValue,
     ) -> (Option<String>, Option<AccessPaths>) {
-        if let Some(authorization) = authorization {
-            if let Some(user) = get_auth_user(authorization) {
-                if let Some((pass, paths)) = self.users.get(&user) {
-                    if method == Method::OPTIONS {
-                        return (Some(user), Some(AccessPaths::new(AccessPerm::ReadOnly)));
-                    }
-                    if check_auth(authorization, method.as_str(), &user, pass).is_some() {
-                        return (Some(user), paths.find(path, !is_readonly_method(method)));
-                    }
+        if let Some(value) = get_session(authorization) {
+            let Some(session) = self.sessions.as_ref().and_then(|v| v.verify(value)) else {
+                return (None, None);
+            };
+            let Some(paths) = self.session_paths(&session) else {
+                return (None, None);
+            };
+            return (Some(session.user), Some(paths));
+        }
+        if let Some(user) = get_cert_user(authorization) {
+            let Some(paths) = self.cert_paths(user) else {
+                return (None, None);
+            };
+            return (Some(user.to_string()), Some(paths));
+        }
+        if let Some(secret) = get_bearer_token(authorization) {
+            let Some((label, paths)) = self.tokens.as_ref().and_then(|v| v.verify(secret)) else {
+                return (None, None);
+            };
+            return (Some(format!("token:{label}")), Some(paths));
+        }
+        if let Some(user) = get_auth_user(authorization) {
+            if let Some((pass, paths)) = self.users.get(&user) {
+                // Preflight requests carry no password, the user only gets to read
+                if method == Method::OPTIONS {
+                    return (Some(user), None);
+                }
+                if check_auth(authorization, method.as_str(), &user, pass).is_some() {
+                    return (Some(user), Some(paths.clone()));
                 }
+            } else if let Some(paths) = self.verify_provider_user(authorization, &user) {
+                return (Some(user), Some(paths));
             }
+        }
+        (None, None)
+    }
 
-            return (None, None);
+    pub fn guard(
+        &self,
+        path: &str,
+        method: &Method,
+        identity: &Identity,
+        ip: Option<IpAddr>,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        let guard = self.guard_impl(path, method, identity, !is_readonly_method(method));
+        self.guard_ip(guard, ip)
+    }
+
+    /// Same as `guard`, but only requires read permission whatever the method is.
+    pub fn guard_read(
+        &self,
+        path: &str,
+        method: &Method,
+        identity: &Identity,
+        ip: Option<IpAddr>,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        let guard = self.guard_impl(path, method, identity, false);
+        self.guard_ip(guard, ip)
+    }
+
+    /// Take the paths away from a user, or the anonymous user, whose rule doesn't
+    /// allow the client ip.
+    fn guard_ip(
+        &self,
+        (user, paths): (Option<String>, Option<AccessPaths>),
+        ip: Option<IpAddr>,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        let filter = match &user {
+            Some(user) => self.user_ips.get(user),
+            None => Some(&self.anonymous_ips),
+        };
+        match filter {
+            Some(filter) if !filter.allows(ip) => (user, None),
+            _ => (user, paths),
         }
+    }
 
+    fn guard_impl(
+        &self,
+        path: &str,
+        method: &Method,
+        identity: &Identity,
+        writable: bool,
+    ) -> (Option<String>, Option<AccessPaths>) {
+        if identity.rejected {
+            return (None, None);
+        }
+        let user = identity.user.clone();
         if method == Method::OPTIONS {
-            return (None, Some(AccessPaths::new(AccessPerm::ReadOnly)));
+            return (user, Some(AccessPaths::new(AccessPerm::ReadOnly)));
         }
+        let paths = identity.paths.as_ref().and_then(|v| v.find(path, writable));
+        (user, paths)
+    }
 
-        if let Some(paths) = self.anonymous.as_ref() {
-            return (None, paths.find(path, !is_readonly_method(method)));
+    fn verify_provider_user(&self, authorization: &HeaderValue, user: &str) -> Option<AccessPaths> {
+        if self.providers.is_empty() {
+            return None;
+        }
+        let (basic_user, pass) = get_basic_credentials(authorization)?;
+        if basic_user != user || user.is_empty() || pass.is_empty() {
+            return None;
+        }
+        let auth_user = self
+            .providers
+            .iter()
+            .find_map(|provider| provider.verify(user, &pass))?;
+        if let Some(paths) = self.provider_users.get(user) {
+            return Some(paths.clone());
         }
+        let paths = match (&auth_user.paths, auth_user.groups.is_empty()) {
+            (Some(paths), _) => paths.as_str(),
+            (None, true) if !self.memberships.contains_key(user) => PROVIDER_USER_PATHS,
+            (None, _) => "",
+        };
+        self.member_paths(user, paths, &auth_user.groups)
+    }
 
-        (None, None)
+    fn session_paths(&self, session: &Session) -> Option<AccessPaths> {
+        if let Some(paths) = self.provider_users.get(&session.user) {
+            return Some(paths.clone());
+        }
+        self.member_paths(&session.user, &session.paths, &[])
+    }
+
+    /// Certificate users get the paths of their rule, without a password.
+    fn cert_paths(&self, user: &str) -> Option<AccessPaths> {
+        if let Some((_, paths)) = self.users.get(user) {
+            return Some(paths.clone());
+        }
+        if let Some(paths) = self.provider_users.get(user) {
+            return Some(paths.clone());
+        }
+        if self.memberships.contains_key(user) {
+            return self.member_paths(user, "", &[]);
+        }
+        None
+    }
+
+    /// The anonymous paths, overridden by the paths of the groups `user` is in, then by
+    /// `paths`. The most specific path wins, the rules of several groups for the same path
+    /// are combined, and a deny always wins.
+    fn member_paths(&self, user: &str, paths: &str, groups: &[String]) -> Option<AccessPaths> {
+        let mut group_paths = AccessPaths::default();
+        let groups = self
+            .memberships
+            .get(user)
+            .into_iter()
+            .flatten()
+            .chain(groups.iter());
+        for group in groups {
+            if let Some(paths) = self.groups.get(group) {
+                group_paths.merge(paths)?;
+            }
+        }
+        let mut own_paths = AccessPaths::default();
+        if !paths.is_empty() {
+            own_paths.merge(paths)?;
+        }
+        let mut access_paths = self.anonymous.clone().unwrap_or_default();
+        access_paths.overlay(&group_paths);
+        access_paths.overlay(&own_paths);
+        Some(access_paths)
     }
 }
 
+/// The user of a verified client certificate, kept in the request extensions. The server
+/// turns it into an `Authorization: Certificate <user>` for requests without credentials.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CertUser(pub String);
+
+/// Who a request was authenticated as. The credentials are only checked once, by
+/// `AccessControl::authenticate`, however many paths the request touches.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    user: Option<String>,
+    paths: Option<AccessPaths>,
+    /// Credentials were sent, but not accepted.
+    rejected: bool,
+}
+
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct AccessPaths {
     perm: AccessPerm,
@@ -146,9 +496,28 @@ impl AccessPaths {
         self.perm
     }
 
+    /// Keep the union of the capabilities, a deny always wins.
     pub fn set_perm(&mut self, perm: AccessPerm) {
-        if !perm.indexonly() {
+        if perm.indexonly() || self.perm.denied() {
+            return;
+        }
+        if perm.denied() {
             self.perm = perm;
+        } else {
+            self.perm = AccessPerm::from_caps(self.perm.caps() | perm.caps());
+        }
+    }
+
+    /// Take the perms `other` sets over these, unless they deny the path.
+    fn overlay(&mut self, other: &AccessPaths) {
+        if !other.perm.indexonly() && !self.perm.denied() {
+            self.perm = other.perm;
+        }
+        for (name, child) in other.children.iter() {
+            self.children
+                .entry(name.clone())
+                .or_default()
+                .overlay(child);
         }
     }
 
@@ -156,9 +525,11 @@ impl AccessPaths {
         for item in paths.trim_matches(',').split(',') {
             let (path, perm) = match item.split_once(':') {
                 None => (item, AccessPerm::ReadOnly),
-                Some((path, "ro")) => (path, AccessPerm::ReadOnly),
-                Some((path, "rw")) => (path, AccessPerm::ReadWrite),
-                _ => return None,
+                Some((path, "ro" | "viewer")) => (path, AccessPerm::ReadOnly),
+                Some((path, "up" | "uploader")) => (path, AccessPerm::Upload),
+                Some((path, "rw" | "editor")) => (path, AccessPerm::ReadWrite),
+                Some((path, "deny")) => (path, AccessPerm::Deny),
+                Some((path, caps)) => (path, AccessPerm::from_caps(AccessCaps::parse(caps)?)),
             };
             self.add(path, perm);
         }
@@ -192,13 +563,16 @@ impl AccessPaths {
             .filter(|v| !v.is_empty())
             .collect();
         let target = self.find_impl(&parts, self.perm)?;
-        if writable && !target.perm().readwrite() {
+        if writable && !target.perm().writable() {
             return None;
         }
         Some(target)
     }
 
     fn find_impl(&self, parts: &[&str], perm: AccessPerm) -> Option<AccessPaths> {
+        if self.perm.denied() {
+            return None;
+        }
         let perm = if !self.perm.indexonly() {
             self.perm
         } else {
@@ -208,7 +582,11 @@ impl AccessPaths {
             if perm.indexonly() {
                 return Some(self.clone());
             } else {
-                return Some(AccessPaths::new(perm));
+                // Keep the denied paths below, so listings can leave them out
+                return Some(AccessPaths {
+                    perm,
+                    children: self.denied_children(),
+                });
             }
         }
         let child = match self.children.get(parts[0]) {
@@ -225,7 +603,74 @@ impl AccessPaths {
     }
 
     pub fn child_names(&self) -> Vec<&String> {
-        self.children.keys().collect()
+        self.children
+            .iter()
+            .filter(|(_, child)| !child.perm.denied())
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    /// Whether `path`, relative to these paths, is or is inside a denied path.
+    pub fn denied(&self, path: &Path) -> bool {
+        let mut node = self;
+        for part in path.components() {
+            let Some(child) = node
+                .children
+                .get(part.as_os_str().to_string_lossy().as_ref())
+            else {
+                return false;
+            };
+            if child.perm.denied() {
+                return true;
+            }
+            node = child;
+        }
+        false
+    }
+
+    /// The denied paths below, e.g. `dir1/secret`.
+    pub fn denied_paths(&self) -> Vec<String> {
+        let mut output = vec![];
+        self.denied_paths_impl(&mut output, "");
+        output
+    }
+
+    fn denied_paths_impl(&self, output: &mut Vec<String>, base: &str) {
+        for (name, child) in self.children.iter() {
+            let path = match base.is_empty() {
+                true => name.clone(),
+                false => format!("{base}/{name}"),
+            };
+            if child.perm.denied() {
+                output.push(path);
+            } else {
+                child.denied_paths_impl(output, &path);
+            }
+        }
+    }
+
+    /// Deny `path` and everything below it.
+    pub fn deny(&mut self, path: &str) {
+        self.add(path, AccessPerm::Deny);
+    }
+
+    fn denied_children(&self) -> IndexMap<String, AccessPaths> {
+        self.children
+            .iter()
+            .filter_map(|(name, child)| {
+                if child.perm.denied() {
+                    return Some((name.clone(), AccessPaths::new(AccessPerm::Deny)));
+                }
+                let children = child.denied_children();
+                (!children.is_empty()).then(|| {
+                    let child = AccessPaths {
+                        perm: AccessPerm::IndexOnly,
+                        children,
+                    };
+                    (name.clone(), child)
+                })
+            })
+            .collect()
     }
 
     pub fn child_paths(&self, base: &Path) -> Vec<PathBuf> {
@@ -240,6 +685,9 @@ impl AccessPaths {
     fn child_paths_impl(&self, output: &mut Vec<PathBuf>, base: &Path) {
         for (name, child) in self.children.iter() {
             let base = base.join(name);
+            if child.perm().denied() {
+                continue;
+            }
             if child.perm().indexonly() {
                 child.child_paths_impl(output, &base);
             } else {
@@ -249,15 +697,40 @@ impl AccessPaths {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum AccessPerm {
     #[default]
     IndexOnly,
     ReadOnly,
+    Upload,
     ReadWrite,
+    Custom(AccessCaps),
+    Deny,
 }
 
 impl AccessPerm {
+    /// The role matching `caps`, or a custom perm if there is none.
+    pub fn from_caps(caps: AccessCaps) -> Self {
+        [
+            AccessPerm::ReadOnly,
+            AccessPerm::Upload,
+            AccessPerm::ReadWrite,
+        ]
+        .into_iter()
+        .find(|v| v.caps() == caps)
+        .unwrap_or(AccessPerm::Custom(caps))
+    }
+
+    pub fn caps(&self) -> AccessCaps {
+        match self {
+            AccessPerm::IndexOnly | AccessPerm::Deny => AccessCaps::NONE,
+            AccessPerm::ReadOnly => AccessCaps::VIEWER,
+            AccessPerm::Upload => AccessCaps::UPLOADER,
+            AccessPerm::ReadWrite => AccessCaps::ALL,
+            AccessPerm::Custom(caps) => *caps,
+        }
+    }
+
     pub fn indexonly(&self) -> bool {
         self == &AccessPerm::IndexOnly
     }
@@ -265,6 +738,97 @@ impl AccessPerm {
     pub fn readwrite(&self) -> bool {
         self == &AccessPerm::ReadWrite
     }
+
+    /// Whether some of the capabilities change files.
+    pub fn writable(&self) -> bool {
+        self.caps().intersects(AccessCaps::WRITE)
+    }
+
+    pub fn denied(&self) -> bool {
+        self == &AccessPerm::Deny
+    }
+}
+
+/// The operations allowed on a path, written in rules as e.g. `read+list+upload-new`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AccessCaps(u16);
+
+impl AccessCaps {
+    pub const NONE: AccessCaps = AccessCaps(0);
+    pub const READ: AccessCaps = AccessCaps(1);
+    pub const LIST: AccessCaps = AccessCaps(1 << 1);
+    pub const UPLOAD: AccessCaps = AccessCaps(1 << 2);
+    pub const OVERWRITE: AccessCaps = AccessCaps(1 << 3);
+    pub const DELETE: AccessCaps = AccessCaps(1 << 4);
+    pub const RENAME: AccessCaps = AccessCaps(1 << 5);
+    pub const SEARCH: AccessCaps = AccessCaps(1 << 6);
+    pub const ARCHIVE: AccessCaps = AccessCaps(1 << 7);
+    pub const MEDIA: AccessCaps = AccessCaps(1 << 8);
+    pub const ALL: AccessCaps = AccessCaps((1 << 9) - 1);
+
+    pub const VIEWER: AccessCaps =
+        AccessCaps(Self::READ.0 | Self::LIST.0 | Self::SEARCH.0 | Self::ARCHIVE.0);
+    pub const UPLOADER: AccessCaps = AccessCaps(Self::VIEWER.0 | Self::UPLOAD.0);
+    pub const WRITE: AccessCaps = AccessCaps(
+        Self::UPLOAD.0 | Self::OVERWRITE.0 | Self::DELETE.0 | Self::RENAME.0 | Self::MEDIA.0,
+    );
+
+    const NAMES: [(&'static str, AccessCaps); 9] = [
+        ("read", Self::READ),
+        ("list", Self::LIST),
+        ("upload-new", Self::UPLOAD),
+        ("overwrite", Self::OVERWRITE),
+        ("delete", Self::DELETE),
+        ("rename", Self::RENAME),
+        ("search", Self::SEARCH),
+        ("archive", Self::ARCHIVE),
+        ("media-jobs", Self::MEDIA),
+    ];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut caps = AccessCaps::NONE;
+        for name in value.split('+') {
+            let (_, cap) = Self::NAMES.iter().find(|(v, _)| *v == name)?;
+            caps = caps | *cap;
+        }
+        Some(caps)
+    }
+
+    pub fn contains(&self, other: AccessCaps) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(&self, other: AccessCaps) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl std::ops::BitOr for AccessCaps {
+    type Output = AccessCaps;
+
+    fn bitor(self, rhs: AccessCaps) -> AccessCaps {
+        AccessCaps(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitAnd for AccessCaps {
+    type Output = AccessCaps;
+
+    fn bitand(self, rhs: AccessCaps) -> AccessCaps {
+        AccessCaps(self.0 & rhs.0)
+    }
+}
+
+/// Written like in rules, e.g. `read+list`, so `AccessCaps::parse` reads it back.
+impl std::fmt::Display for AccessCaps {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<&str> = Self::NAMES
+            .iter()
+            .filter(|(_, cap)| self.contains(*cap))
+            .map(|(name, _)| *name)
+            .collect();
+        write!(f, "{}", names.join("+"))
+    }
 }
 
 pub fn www_authenticate(res: &mut Response, args: &Args) -> Result<()> {
@@ -298,6 +862,29 @@ pub fn get_auth_user(authorization: &HeaderValue) -> Option<String> {
     }
 }
 
+pub fn get_bearer_token(authorization: &HeaderValue) -> Option<&str> {
+    let value = strip_prefix(authorization.as_bytes(), b"Bearer ")?;
+    std::str::from_utf8(value).ok().map(|v| v.trim())
+}
+
+pub fn get_session(authorization: &HeaderValue) -> Option<&str> {
+    let value = strip_prefix(authorization.as_bytes(), b"Session ")?;
+    std::str::from_utf8(value).ok().map(|v| v.trim())
+}
+
+/// The user of a verified client certificate, see `CertUser`.
+pub fn get_cert_user(authorization: &HeaderValue) -> Option<&str> {
+    let value = strip_prefix(authorization.as_bytes(), b"Certificate ")?;
+    std::str::from_utf8(value).ok().map(|v| v.trim())
+}
+
+pub fn get_basic_credentials(authorization: &HeaderValue) -> Option<(String, String)> {
+    let value = strip_prefix(authorization.as_bytes(), b"Basic ")?;
+    let value: Vec<u8> = general_purpose::STANDARD.decode(value).ok()?;
+    let (user, pass) = std::str::from_utf8(&value).ok()?.split_once(':')?;
+    Some((user.to_string(), pass.to_string()))
+}
+
 pub fn check_auth(
     authorization: &HeaderValue,
     method: &str,
@@ -386,7 +973,9 @@ pub fn check_auth(
                 }
             };
             if correct_response.as_bytes() == *user_response {
-                return Some(());
+                // A response is only good once, the client counts up for each request
+                let nc = digest_map.get(b"nc".as_ref())?;
+                return check_nonce_count(nonce, nc).then_some(());
             }
         }
         None
@@ -398,13 +987,16 @@ pub fn check_auth(
 /// Check if a nonce is still valid.
 /// Return an error if it was never valid
 fn validate_nonce(nonce: &[u8]) -> Result<bool> {
-    if nonce.len() != 34 {
+    if nonce.len() != 42 {
         bail!("invalid nonce");
     }
     //parse hex
     if let Ok(n) = std::str::from_utf8(nonce) {
-        //get time
-        if let Ok(secs_nonce) = u32::from_str_radix(&n[..8], 16) {
+        //get time and salt
+        if let (Ok(secs_nonce), Ok(salt)) = (
+            u32::from_str_radix(&n[..8], 16),
+            u32::from_str_radix(&n[8..16], 16),
+        ) {
             //check time
             let now = unix_now()?;
             let secs_now = now.as_secs() as u32;
@@ -413,8 +1005,9 @@ fn validate_nonce(nonce: &[u8]) -> Result<bool> {
                 //check hash
                 let mut h = NONCESTARTHASH.clone();
                 h.consume(secs_nonce.to_be_bytes());
-                let h = format!("{:x}", h.compute());
-                if h[..26] == n[8..34] {
+                h.consume(salt.to_be_bytes());
+                let h = format!("{:032x}", h.compute());
+                if h[..26] == n[16..42] {
                     return Ok(dur < DIGEST_AUTH_TIMEOUT);
                 }
             }
@@ -423,11 +1016,71 @@ fn validate_nonce(nonce: &[u8]) -> Result<bool> {
     bail!("invalid nonce");
 }
 
+/// Accept a nonce count only once. Requests sent in parallel may arrive out of order,
+/// so counts up to `NONCE_COUNT_WINDOW` below the highest one are still accepted.
+fn check_nonce_count(nonce: &[u8], nc: &[u8]) -> bool {
+    let Some(nc) = std::str::from_utf8(nc)
+        .ok()
+        .and_then(|v| u32::from_str_radix(v, 16).ok())
+    else {
+        return false;
+    };
+    let Some(issued) = std::str::from_utf8(&nonce[..8])
+        .ok()
+        .and_then(|v| u32::from_str_radix(v, 16).ok())
+    else {
+        return false;
+    };
+    let mut counts = NONCE_COUNTS.lock().unwrap();
+    match counts.get_mut(nonce) {
+        Some(count) if nc > count.highest => {
+            let shift = nc - count.highest;
+            count.seen = if shift < NONCE_COUNT_WINDOW {
+                count.seen << shift
+            } else {
+                0
+            };
+            count.seen |= 1;
+            count.highest = nc;
+        }
+        Some(count) => {
+            let bit = count.highest - nc;
+            if bit >= NONCE_COUNT_WINDOW || count.seen & (1 << bit) != 0 {
+                return false;
+            }
+            count.seen |= 1 << bit;
+        }
+        None => {
+            if counts.len() >= DIGEST_MAX_NONCES {
+                let now = unix_now().map(|v| v.as_secs() as u32).unwrap_or_default();
+                counts.retain(|_, count| now.saturating_sub(count.issued) < DIGEST_AUTH_TIMEOUT);
+                if counts.len() >= DIGEST_MAX_NONCES {
+                    let oldest = counts
+                        .iter()
+                        .min_by_key(|(_, count)| count.issued)
+                        .map(|(k, _)| k.clone());
+                    if let Some(oldest) = oldest {
+                        counts.remove(&oldest);
+                    }
+                }
+            }
+            let count = NonceCount {
+                issued,
+                highest: nc,
+                seen: 1,
+            };
+            counts.insert(nonce.to_vec(), count);
+        }
+    }
+    true
+}
+
 fn is_readonly_method(method: &Method) -> bool {
     method == Method::GET
         || method == Method::OPTIONS
         || method == Method::HEAD
         || method.as_str() == "PROPFIND"
+        || method.as_str() == "REPORT"
 }
 
 fn strip_prefix<'a>(search: &'a [u8], prefix: &[u8]) -> Option<&'a [u8]> {
@@ -483,14 +1136,27 @@ fn to_headermap(header: &[u8]) -> Result<HashMap<&[u8], &[u8]>, ()> {
     Ok(ret)
 }
 
+/// The issue time, a random salt so that every challenge gets its own nonce, and a hash.
 fn create_nonce() -> Result<String> {
     let now = unix_now()?;
     let secs = now.as_secs() as u32;
+    let salt = Uuid::new_v4().as_u128() as u32;
     let mut h = NONCESTARTHASH.clone();
     h.consume(secs.to_be_bytes());
+    h.consume(salt.to_be_bytes());
+
+    let n = format!("{:08x}{:08x}{:032x}", secs, salt, h.compute());
+    Ok(n[..42].to_string())
+}
 
-    let n = format!("{:08x}{:032x}", secs, h.compute());
-    Ok(n[..34].to_string())
+/// Split the ip list off `/dir1:rw#10.0.0.0/8`, if what follows `#` is one.
+fn split_paths_ips(paths: &str) -> (&str, Option<IpFilter>) {
+    if let Some((left, ips)) = paths.rsplit_once('#') {
+        if let Ok(ips) = IpFilter::parse(ips) {
+            return (left, Some(ips));
+        }
+    }
+    (paths, None)
 }
 
 fn split_account_paths(s: &str) -> Option<(&str, &str)> {
@@ -612,4 +1278,184 @@ mod tests {
         );
         assert_eq!(paths.find("dir2/dir21/dir211/file", true), None);
     }
+
+    #[test]
+    fn test_access_paths_merge() {
+        let mut paths = AccessPaths::default();
+        paths.merge("/dir1:rw,/dir2:deny").unwrap();
+        paths.merge("/dir1,/dir2:rw,/dir3:up").unwrap();
+        assert!(paths.find("dir1/file", true).is_some());
+        assert_eq!(paths.find("dir2/file", false), None);
+        assert_eq!(
+            paths.find("dir3/file", true).map(|v| v.perm()),
+            Some(AccessPerm::Upload)
+        );
+        assert_eq!(paths.child_names(), ["dir1", "dir3"]);
+
+        let mut paths = AccessPaths::default();
+        paths.merge("/:rw,/dir1/secret:deny").unwrap();
+        let dir1 = paths.find("dir1", false).unwrap();
+        assert!(dir1.perm().readwrite());
+        assert!(dir1.denied(Path::new("secret/file")));
+        assert!(!dir1.denied(Path::new("public")));
+        assert!(paths.merge("/dir1:admin").is_none());
+    }
+
+    #[test]
+    fn test_access_caps() {
+        let mut paths = AccessPaths::default();
+        paths.merge("/dir1:read+list+upload-new").unwrap();
+        paths.merge("/dir2:list,/dir2:search+archive+read").unwrap();
+        paths.merge("/dir3:read+list+upload-new+overwrite").unwrap();
+        paths
+            .merge("/dir3:delete+rename+media-jobs+search+archive")
+            .unwrap();
+        let dir1 = paths.find("dir1/file", true).unwrap().perm();
+        assert!(dir1.caps().contains(AccessCaps::UPLOAD));
+        assert!(!dir1.caps().contains(AccessCaps::OVERWRITE));
+        assert!(!dir1.caps().contains(AccessCaps::SEARCH));
+        assert!(paths.find("dir2/file", true).is_none());
+        assert_eq!(
+            paths.find("dir2", false).unwrap().perm(),
+            AccessPerm::ReadOnly
+        );
+        assert_eq!(
+            paths.find("dir3", false).unwrap().perm(),
+            AccessPerm::ReadWrite
+        );
+        assert!(paths.merge("/dir1:read+write").is_none());
+        assert!(paths.merge("/dir1:read+").is_none());
+        assert_eq!(dir1.caps().to_string(), "read+list+upload-new");
+        assert_eq!(
+            AccessCaps::parse(&dir1.caps().to_string()),
+            Some(dir1.caps())
+        );
+    }
+
+    #[test]
+    fn test_rule_ips() {
+        let control = AccessControl::new(&[
+            "admin:pass@/:rw#10.0.0.0/8,!10.0.0.5",
+            "@/dir1#!192.168.0.0/16",
+        ])
+        .unwrap();
+        let authorization = HeaderValue::from_static("Basic YWRtaW46cGFzcw==");
+        let identity = control.authenticate(&Method::GET, Some(&authorization));
+        let guard = |ip: &str| {
+            control
+                .guard("dir1", &Method::GET, &identity, ip.parse().ok())
+                .1
+                .is_some()
+        };
+        assert!(guard("10.1.2.3"));
+        assert!(!guard("10.0.0.5"));
+        assert!(!guard("192.168.1.1"));
+        let identity = control.authenticate(&Method::GET, None);
+        let guard = |ip: &str| {
+            control
+                .guard("dir1", &Method::GET, &identity, ip.parse().ok())
+                .1
+                .is_some()
+        };
+        assert!(guard("10.1.2.3"));
+        assert!(!guard("192.168.1.1"));
+        assert!(AccessControl::new(&["+devs@/:rw#10.0.0.0/8"]).is_err());
+    }
+
+    #[test]
+    fn test_cert_user() {
+        let control = AccessControl::new(&[
+            "admin:pass@/:rw",
+            "backup@/backups:rw",
+            "+devs=alice@/src:rw",
+            "@/pub",
+        ])
+        .unwrap();
+        let names = |v: &[&str]| -> Vec<String> { v.iter().map(|v| v.to_string()).collect() };
+        assert_eq!(
+            control.cert_user(&names(&["unknown", "backup"])),
+            Some("backup".into())
+        );
+        assert_eq!(control.cert_user(&names(&["bob"])), None);
+
+        let guard = |user: &str, path: &str| {
+            let value = HeaderValue::from_str(&format!("Certificate {user}")).unwrap();
+            let identity = control.authenticate(&Method::PUT, Some(&value));
+            control
+                .guard(path, &Method::PUT, &identity, None)
+                .1
+                .is_some()
+        };
+        assert!(guard("admin", "dir1/file1"));
+        assert!(guard("backup", "backups/file1"));
+        assert!(!guard("backup", "src/file1"));
+        assert!(guard("alice", "src/file1"));
+        assert!(!guard("bob", "pub/file1"));
+    }
+
+    #[test]
+    fn test_nonce_count() {
+        let nonce = create_nonce().unwrap();
+        assert!(validate_nonce(nonce.as_bytes()).unwrap());
+        assert_ne!(nonce, create_nonce().unwrap());
+        assert!(check_nonce_count(nonce.as_bytes(), b"00000001"));
+        assert!(!check_nonce_count(nonce.as_bytes(), b"00000001"));
+        assert!(check_nonce_count(nonce.as_bytes(), b"00000003"));
+        assert!(check_nonce_count(nonce.as_bytes(), b"00000002"));
+        assert!(!check_nonce_count(nonce.as_bytes(), b"00000002"));
+        assert!(check_nonce_count(nonce.as_bytes(), b"00000042"));
+        assert!(check_nonce_count(nonce.as_bytes(), b"00000004"));
+        assert!(!check_nonce_count(nonce.as_bytes(), b"00000003"));
+        assert!(!check_nonce_count(nonce.as_bytes(), b"00000002"));
+        assert!(!check_nonce_count(nonce.as_bytes(), b"xyz"));
+    }
+
+    #[test]
+    fn test_groups() {
+        let control = AccessControl::new(&[
+            "+devs=alice,bob@/projects:editor,/projects/secret:deny",
+            "+ops=alice@/logs:viewer",
+            "alice:pass@/home/alice:rw",
+            "bob:pass@/home/bob:rw",
+            "carol@/home/carol:rw",
+        ])
+        .unwrap();
+        let (_, alice) = &control.users["alice"];
+        assert!(alice.find("home/alice", true).is_some());
+        assert!(alice.find("projects/app", true).is_some());
+        assert!(alice.find("projects/secret", false).is_none());
+        assert!(alice.find("logs", false).is_some());
+        assert!(alice.find("logs", true).is_none());
+        let (_, bob) = &control.users["bob"];
+        assert!(bob.find("logs", false).is_none());
+        let carol = control
+            .member_paths("carol", "", &["ops".to_string()])
+            .unwrap();
+        assert!(carol.find("logs", false).is_some());
+        assert!(carol.find("projects", false).is_none());
+
+        // A user's own rule takes precedence over its groups for the same path
+        let control = AccessControl::new(&[
+            "+devs=alice@/dir1:rw,/dir2:viewer",
+            "+ops=alice@/dir2:uploader",
+            "alice:pass@/dir1:ro",
+            "@/dir3:rw",
+            "+readers=bob@/dir3:ro",
+            "bob:pass@/dir3/sub:rw",
+        ])
+        .unwrap();
+        let (_, alice) = &control.users["alice"];
+        assert!(alice.find("dir1", false).is_some());
+        assert!(alice.find("dir1", true).is_none());
+        assert_eq!(
+            alice.find("dir2", false).unwrap().perm(),
+            AccessPerm::Upload
+        );
+        let (_, bob) = &control.users["bob"];
+        assert!(bob.find("dir3", true).is_none());
+        assert!(bob.find("dir3/sub", true).is_some());
+
+        assert!(AccessControl::new(&["+devs@/:rw", "+devs@/dir1"]).is_err());
+        assert!(AccessControl::new(&["+@/:rw"]).is_err());
+    }
 }
@@ -0,0 +1,495 @@
+use anyhow::{anyhow, bail, Context as _, Result};
+use md5::Context;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{mpsc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+const CACHE_TTL: Duration = Duration::from_secs(60);
+const CACHE_CAPACITY: usize = 1024;
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+const APR1_ITOA64: &[u8] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// A user accepted by a provider, with the path rules it brings, e.g. `/dir1:rw,/dir2`,
+/// and the `+group` rules it is a member of.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AuthUser {
+    pub paths: Option<String>,
+    pub groups: Vec<String>,
+}
+
+/// A source of accounts checked with basic auth, besides the `user:pass@/path` rules.
+pub trait AuthProvider: Debug + Send + Sync {
+    fn verify(&self, user: &str, pass: &str) -> Option<AuthUser>;
+}
+
+/// Users of an apache htpasswd file, with bcrypt, sha-crypt or apr1 hashed passwords.
+#[derive(Debug)]
+pub struct HtpasswdProvider {
+    file: WatchedFile<HashMap<String, String>>,
+    cache: VerifyCache,
+}
+
+impl HtpasswdProvider {
+    pub fn new(path: &Path) -> Result<Self> {
+        Ok(Self {
+            file: WatchedFile::new(path, parse_htpasswd)?,
+            cache: VerifyCache::default(),
+        })
+    }
+}
+
+impl AuthProvider for HtpasswdProvider {
+    fn verify(&self, user: &str, pass: &str) -> Option<AuthUser> {
+        if self.file.reload() {
+            self.cache.clear();
+        }
+        self.cache.get_or_insert(user, pass, || {
+            let hash = self.file.read(|users| users.get(user).cloned())?;
+            verify_password(pass, &hash, false).then(AuthUser::default)
+        })
+    }
+}
+
+/// Users of a JSON or YAML file, reloaded when the file changes.
+///
+/// ```yaml
+/// users:
+///   - name: admin
+///     password: $2y$05$...
+///     paths: /:rw
+///     groups: [devs]
+/// ```
+#[derive(Debug)]
+pub struct UsersFileProvider {
+    file: WatchedFile<HashMap<String, UsersFileEntry>>,
+    cache: VerifyCache,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsersFile {
+    users: Vec<UsersFileEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct UsersFileEntry {
+    name: String,
+    password: String,
+    paths: Option<String>,
+    #[serde(default)]
+    groups: Vec<String>,
+}
+
+impl UsersFileProvider {
+    pub fn new(path: &Path) -> Result<Self> {
+        Ok(Self {
+            file: WatchedFile::new(path, parse_users_file)?,
+            cache: VerifyCache::default(),
+        })
+    }
+}
+
+impl AuthProvider for UsersFileProvider {
+    fn verify(&self, user: &str, pass: &str) -> Option<AuthUser> {
+        if self.file.reload() {
+            self.cache.clear();
+        }
+        self.cache.get_or_insert(user, pass, || {
+            let entry = self.file.read(|users| users.get(user).cloned())?;
+            verify_password(pass, &entry.password, true).then_some(AuthUser {
+                paths: entry.paths,
+                groups: entry.groups,
+            })
+        })
+    }
+}
+
+/// Delegates the check to an external program.
+///
+/// The program gets the user in `DUFS_AUTH_USER` and the password on stdin, it accepts
+/// the user by exiting with 0 and may print path rules on the first line of stdout.
+#[derive(Debug)]
+pub struct CommandProvider {
+    program: String,
+    cache: VerifyCache,
+}
+
+impl CommandProvider {
+    pub fn new(program: &str) -> Result<Self> {
+        if program.trim().is_empty() {
+            bail!("Invalid auth command");
+        }
+        Ok(Self {
+            program: program.to_string(),
+            cache: VerifyCache::default(),
+        })
+    }
+
+    fn run(&self, user: &str, pass: &str) -> Result<Option<AuthUser>> {
+        let mut child = Command::new(&self.program)
+            .env("DUFS_AUTH_USER", user)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to run auth command `{}`", self.program))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(pass.as_bytes());
+        }
+        // Drain stdout meanwhile, a program printing more than the pipe holds would
+        // never exit otherwise
+        let (tx, rx) = mpsc::channel();
+        if let Some(mut stdout) = child.stdout.take() {
+            std::thread::spawn(move || {
+                let mut output = String::new();
+                let _ = stdout.read_to_string(&mut output);
+                let _ = tx.send(output);
+            });
+        }
+        let start = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() > COMMAND_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("Auth command `{}` timed out", self.program);
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        };
+        if !status.success() {
+            return Ok(None);
+        }
+        let output = rx
+            .recv_timeout(COMMAND_TIMEOUT.saturating_sub(start.elapsed()))
+            .unwrap_or_default();
+        let paths = output
+            .lines()
+            .next()
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.to_string());
+        Ok(Some(AuthUser {
+            paths,
+            ..Default::default()
+        }))
+    }
+}
+
+impl AuthProvider for CommandProvider {
+    fn verify(&self, user: &str, pass: &str) -> Option<AuthUser> {
+        self.cache
+            .get_or_insert(user, pass, || match self.run(user, pass) {
+                Ok(v) => v,
+                Err(err) => {
+                    warn!("{err}");
+                    None
+                }
+            })
+    }
+}
+
+/// Check a password against a bcrypt, sha-crypt or apr1 hash, `allow_plain` treats
+/// anything else as a plain password.
+pub fn verify_password(pass: &str, hash: &str, allow_plain: bool) -> bool {
+    if hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$") {
+        bcrypt::verify(pass, hash).unwrap_or_default()
+    } else if hash.starts_with("$6$") {
+        sha_crypt::sha512_check(pass, hash).is_ok()
+    } else if hash.starts_with("$5$") {
+        sha_crypt::sha256_check(pass, hash).is_ok()
+    } else if let Some(value) = hash.strip_prefix("$apr1$") {
+        match value.split_once('$') {
+            Some((salt, _)) => apr1_crypt(pass, salt) == hash,
+            None => false,
+        }
+    } else {
+        allow_plain && pass == hash
+    }
+}
+
+/// The apache variant of md5-crypt.
+fn apr1_crypt(pass: &str, salt: &str) -> String {
+    const MAGIC: &str = "$apr1$";
+    let pass = pass.as_bytes();
+    let salt = &salt.as_bytes()[..salt.len().min(8)];
+
+    let mut alt = Context::new();
+    alt.consume(pass);
+    alt.consume(salt);
+    alt.consume(pass);
+    let alt = alt.compute();
+
+    let mut ctx = Context::new();
+    ctx.consume(pass);
+    ctx.consume(MAGIC);
+    ctx.consume(salt);
+    let mut len = pass.len();
+    while len > 0 {
+        ctx.consume(&alt[..len.min(16)]);
+        len = len.saturating_sub(16);
+    }
+    let mut len = pass.len();
+    while len > 0 {
+        if len & 1 == 1 {
+            ctx.consume([0u8]);
+        } else {
+            ctx.consume(&pass[..1]);
+        }
+        len >>= 1;
+    }
+    let mut digest = ctx.compute();
+
+    for i in 0..1000 {
+        let mut ctx = Context::new();
+        if i & 1 == 1 {
+            ctx.consume(pass);
+        } else {
+            ctx.consume(digest.0);
+        }
+        if i % 3 != 0 {
+            ctx.consume(salt);
+        }
+        if i % 7 != 0 {
+            ctx.consume(pass);
+        }
+        if i & 1 == 1 {
+            ctx.consume(digest.0);
+        } else {
+            ctx.consume(pass);
+        }
+        digest = ctx.compute();
+    }
+
+    let d = digest.0;
+    let mut output = format!("{MAGIC}{}$", String::from_utf8_lossy(salt));
+    for (a, b, c) in [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        let v = ((d[a] as u32) << 16) | ((d[b] as u32) << 8) | d[c] as u32;
+        apr1_to64(&mut output, v, 4);
+    }
+    apr1_to64(&mut output, d[11] as u32, 2);
+    output
+}
+
+fn apr1_to64(output: &mut String, mut v: u32, n: usize) {
+    for _ in 0..n {
+        output.push(APR1_ITOA64[(v & 0x3f) as usize] as char);
+        v >>= 6;
+    }
+}
+
+fn parse_htpasswd(contents: &str) -> Result<HashMap<String, String>> {
+    let mut users = HashMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (user, hash) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid htpasswd line `{line}`"))?;
+        users.insert(user.to_string(), hash.to_string());
+    }
+    Ok(users)
+}
+
+fn parse_users_file(contents: &str) -> Result<HashMap<String, UsersFileEntry>> {
+    let file: UsersFile = serde_yaml::from_str(contents)?;
+    let mut users = HashMap::new();
+    for entry in file.users {
+        if entry.name.is_empty() || entry.password.is_empty() {
+            bail!("Invalid user `{}`", entry.name);
+        }
+        users.insert(entry.name.clone(), entry);
+    }
+    Ok(users)
+}
+
+/// A parsed file that is read again once its mtime changes.
+struct WatchedFile<T> {
+    path: PathBuf,
+    parse: fn(&str) -> Result<T>,
+    state: RwLock<WatchedState<T>>,
+}
+
+struct WatchedState<T> {
+    value: T,
+    mtime: Option<SystemTime>,
+    checked_at: Instant,
+}
+
+impl<T> Debug for WatchedFile<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WatchedFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl<T> WatchedFile<T> {
+    fn new(path: &Path, parse: fn(&str) -> Result<T>) -> Result<Self> {
+        let (value, mtime) = Self::load(path, parse)
+            .with_context(|| format!("Failed to load `{}`", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            parse,
+            state: RwLock::new(WatchedState {
+                value,
+                mtime,
+                checked_at: Instant::now(),
+            }),
+        })
+    }
+
+    fn load(path: &Path, parse: fn(&str) -> Result<T>) -> Result<(T, Option<SystemTime>)> {
+        let mtime = std::fs::metadata(path)?.modified().ok();
+        let contents = std::fs::read_to_string(path)?;
+        Ok((parse(&contents)?, mtime))
+    }
+
+    /// Reload the file if it changed, a broken file keeps the previous contents.
+    fn reload(&self) -> bool {
+        {
+            let state = self.state.read().unwrap();
+            if state.checked_at.elapsed() < RELOAD_INTERVAL {
+                return false;
+            }
+        }
+        let mut state = self.state.write().unwrap();
+        if state.checked_at.elapsed() < RELOAD_INTERVAL {
+            return false;
+        }
+        state.checked_at = Instant::now();
+        let mtime = std::fs::metadata(&self.path)
+            .ok()
+            .and_then(|v| v.modified().ok());
+        if mtime == state.mtime {
+            return false;
+        }
+        match Self::load(&self.path, self.parse) {
+            Ok((value, mtime)) => {
+                state.value = value;
+                state.mtime = mtime;
+                true
+            }
+            Err(err) => {
+                warn!("Failed to reload `{}`, {err}", self.path.display());
+                state.mtime = mtime;
+                false
+            }
+        }
+    }
+
+    fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&self.state.read().unwrap().value)
+    }
+}
+
+/// Remembers accepted credentials for a while, as hashes and external commands are slow.
+#[derive(Debug, Default)]
+struct VerifyCache {
+    entries: Mutex<HashMap<String, (Instant, AuthUser)>>,
+}
+
+impl VerifyCache {
+    fn get_or_insert(
+        &self,
+        user: &str,
+        pass: &str,
+        verify: impl FnOnce() -> Option<AuthUser>,
+    ) -> Option<AuthUser> {
+        let mut key = Context::new();
+        key.consume(user);
+        key.consume([0u8]);
+        key.consume(pass);
+        let key = format!("{:x}", key.compute());
+        if let Some((time, auth_user)) = self.entries.lock().unwrap().get(&key) {
+            if time.elapsed() < CACHE_TTL {
+                return Some(auth_user.clone());
+            }
+        }
+        let auth_user = block_in_place(verify)?;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= CACHE_CAPACITY {
+            entries.retain(|_, (time, _)| time.elapsed() < CACHE_TTL);
+            if entries.len() >= CACHE_CAPACITY {
+                entries.clear();
+            }
+        }
+        entries.insert(key, (Instant::now(), auth_user.clone()));
+        Some(auth_user)
+    }
+
+    fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+/// Run the slow `f` on a runtime worker without holding up the other tasks on it.
+fn block_in_place<R>(f: impl FnOnce() -> R) -> R {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_password() {
+        assert!(verify_password(
+            "pass",
+            "$apr1$dD3mNRhx$iUu6KG746uBneeUx3.T1y.",
+            false
+        ));
+        assert!(!verify_password(
+            "pass1",
+            "$apr1$dD3mNRhx$iUu6KG746uBneeUx3.T1y.",
+            false
+        ));
+        assert!(verify_password(
+            "pass",
+            "$2y$05$e7hiEz88gMc1yId2xpjKneX6LrcIZYKMMyBOuv2jGiTSR4sLxNGea",
+            false
+        ));
+        assert!(verify_password("pass", "pass", true));
+        assert!(!verify_password("pass", "pass", false));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_provider_long_output() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("dufs-auth-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("auth.sh");
+        std::fs::write(&program, "#!/bin/sh\nyes /dir1 | head -n 100000\n").unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let provider = CommandProvider::new(program.to_str().unwrap()).unwrap();
+        let auth_user = provider.verify("user", "pass").unwrap();
+        assert_eq!(auth_user.paths.as_deref(), Some("/dir1"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_htpasswd() {
+        let users = parse_htpasswd("# comment\nuser1:$apr1$abc$def\n\nuser2:$2y$05$xyz\n").unwrap();
+        assert_eq!(users.len(), 2);
+        assert_eq!(users["user1"], "$apr1$abc$def");
+        assert!(parse_htpasswd("user1").is_err());
+    }
+}
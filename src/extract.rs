@@ -0,0 +1,358 @@
+use crate::archive_reader::{detect_archive, normalize_name, ArchiveKind};
+
+use anyhow::{anyhow, bail, Result};
+use async_compression::tokio::bufread::{GzipDecoder, ZstdDecoder};
+use async_zip::tokio::read::seek::ZipFileReader;
+use futures_util::TryStreamExt;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File};
+use tokio::io::{self, AsyncRead, AsyncReadExt, BufReader};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+use uuid::Uuid;
+
+const MAX_ENTRIES: usize = 100_000;
+
+/// What to do when an extracted file already exists.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Conflict {
+    Skip,
+    Overwrite,
+    Rename,
+}
+
+impl std::str::FromStr for Conflict {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "" | "skip" => Ok(Conflict::Skip),
+            "overwrite" => Ok(Conflict::Overwrite),
+            "rename" => Ok(Conflict::Rename),
+            _ => Err(anyhow!("Invalid conflict `{s}`")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractStatus {
+    Created,
+    Exists,
+    Overwritten,
+    Renamed,
+    Skipped,
+    Failed,
+}
+
+/// The outcome of one archive entry, `path` is where it was written relative to the
+/// target directory.
+#[derive(Debug, Serialize)]
+pub struct ExtractResult {
+    pub name: String,
+    pub status: ExtractStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Extracts a zip, tar, tar.gz or tar.zst archive into a directory.
+///
+/// Entry names that could escape the directory are refused, `allow` tells whether the
+/// `(name, is_dir)` entry may be written and `max_size` caps the total bytes written.
+pub struct Extractor<F> {
+    dir: PathBuf,
+    conflict: Conflict,
+    max_size: Option<u64>,
+    root: Option<PathBuf>,
+    allow: F,
+    written: u64,
+    results: Vec<ExtractResult>,
+}
+
+struct Target {
+    path: PathBuf,
+    rel_path: String,
+    status: ExtractStatus,
+}
+
+impl<F> Extractor<F>
+where
+    F: Fn(&str, bool) -> bool,
+{
+    /// With `root` set, entries must also stay inside it once symlinks are resolved.
+    pub fn new(
+        dir: &Path,
+        conflict: Conflict,
+        max_size: Option<u64>,
+        root: Option<PathBuf>,
+        allow: F,
+    ) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            conflict,
+            max_size,
+            root,
+            allow,
+            written: 0,
+            results: vec![],
+        }
+    }
+
+    pub async fn extract(mut self, archive: &Path) -> Result<Vec<ExtractResult>> {
+        let Some(kind) = detect_archive(archive).await? else {
+            bail!("Unsupported archive format, expect zip, tar, tar.gz or tar.zst");
+        };
+        let reader = BufReader::new(File::open(archive).await?);
+        match kind {
+            ArchiveKind::Zip => self.extract_zip(reader).await?,
+            ArchiveKind::Tar => self.extract_tar(reader).await?,
+            ArchiveKind::TarGz => self.extract_tar(GzipDecoder::new(reader)).await?,
+            ArchiveKind::TarZst => self.extract_tar(ZstdDecoder::new(reader)).await?,
+        }
+        Ok(self.results)
+    }
+
+    async fn extract_zip(&mut self, reader: BufReader<File>) -> Result<()> {
+        let mut zip = ZipFileReader::with_tokio(reader).await?;
+        if zip.file().entries().len() > MAX_ENTRIES {
+            bail!("Too many entries");
+        }
+        for index in 0..zip.file().entries().len() {
+            let (name, is_dir, is_other, size) = {
+                let entry = &zip.file().entries()[index];
+                let name = match entry.filename().as_str() {
+                    Ok(v) => v.to_string(),
+                    Err(_) => String::from_utf8_lossy(entry.filename().as_bytes()).to_string(),
+                };
+                let is_symlink = entry
+                    .unix_permissions()
+                    .map(|v| v & 0o170000 == 0o120000)
+                    .unwrap_or_default();
+                let is_dir = entry.dir().unwrap_or_default();
+                (name, is_dir, is_symlink, entry.uncompressed_size())
+            };
+            let Some(target) = self.prepare(&name, is_dir, is_other, size).await else {
+                continue;
+            };
+            match zip.reader_without_entry(index).await {
+                Ok(reader) => self.write(name, target, reader.compat()).await,
+                Err(err) => self.fail(name, &err.to_string()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn extract_tar<R: AsyncRead + Unpin>(&mut self, reader: R) -> Result<()> {
+        let mut archive = tokio_tar::Archive::new(reader);
+        let mut entries = archive.entries()?;
+        let mut count = 0;
+        while let Some(entry) = entries.try_next().await? {
+            count += 1;
+            if count > MAX_ENTRIES {
+                bail!("Too many entries");
+            }
+            let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+            let entry_type = entry.header().entry_type();
+            let is_dir = entry_type.is_dir();
+            let is_other = !is_dir && !entry_type.is_file() && !entry_type.is_contiguous();
+            let size = entry.header().size().unwrap_or_default();
+            let Some(target) = self.prepare(&name, is_dir, is_other, size).await else {
+                continue;
+            };
+            self.write(name, target, entry).await;
+        }
+        Ok(())
+    }
+
+    /// Check an entry and create its directories, returns where to write a file entry.
+    async fn prepare(
+        &mut self,
+        name: &str,
+        is_dir: bool,
+        is_other: bool,
+        size: u64,
+    ) -> Option<Target> {
+        let Some(rel_path) = normalize_name(name) else {
+            self.fail(name.to_string(), "Invalid path");
+            return None;
+        };
+        if is_other {
+            self.push(
+                name.to_string(),
+                ExtractStatus::Skipped,
+                None,
+                "Unsupported entry type",
+            );
+            return None;
+        }
+        if !(self.allow)(&rel_path, is_dir) {
+            self.fail(name.to_string(), "Forbidden");
+            return None;
+        }
+        let path = self.dir.join(&rel_path);
+        if is_dir {
+            if !self.is_contained(&path).await {
+                self.fail(name.to_string(), "Forbidden");
+                return None;
+            }
+            let exists = fs::symlink_metadata(&path).await.is_ok();
+            match fs::create_dir_all(&path).await {
+                Ok(_) => {
+                    let status = match exists {
+                        true => ExtractStatus::Exists,
+                        false => ExtractStatus::Created,
+                    };
+                    self.push(name.to_string(), status, Some(rel_path), "");
+                }
+                Err(err) => self.fail(name.to_string(), &err.to_string()),
+            }
+            return None;
+        }
+        if self
+            .max_size
+            .map(|v| self.written.saturating_add(size) > v)
+            .unwrap_or_default()
+        {
+            self.fail(name.to_string(), "Upload quota exceeded");
+            return None;
+        }
+        let parent = path.parent().unwrap_or(&self.dir).to_path_buf();
+        if !self.is_contained(&parent).await {
+            self.fail(name.to_string(), "Forbidden");
+            return None;
+        }
+        if let Err(err) = fs::create_dir_all(&parent).await {
+            self.fail(name.to_string(), &err.to_string());
+            return None;
+        }
+        let Ok(meta) = fs::symlink_metadata(&path).await else {
+            return Some(Target {
+                path,
+                rel_path,
+                status: ExtractStatus::Created,
+            });
+        };
+        match self.conflict {
+            Conflict::Skip => {
+                self.push(
+                    name.to_string(),
+                    ExtractStatus::Skipped,
+                    Some(rel_path),
+                    "Already exists",
+                );
+                None
+            }
+            Conflict::Overwrite if meta.is_dir() => {
+                self.fail(name.to_string(), "Is a directory");
+                None
+            }
+            Conflict::Overwrite => Some(Target {
+                path,
+                rel_path,
+                status: ExtractStatus::Overwritten,
+            }),
+            Conflict::Rename => {
+                let (path, rel_path) = rename_free(&path, &rel_path).await;
+                Some(Target {
+                    path,
+                    rel_path,
+                    status: ExtractStatus::Renamed,
+                })
+            }
+        }
+    }
+
+    /// Write a file entry through a temporary file, so a failed entry leaves nothing behind.
+    async fn write<R: AsyncRead + Unpin>(&mut self, name: String, target: Target, reader: R) {
+        let limit = self.max_size.map(|v| v - self.written).unwrap_or(u64::MAX);
+        let tmp_path = target.path.with_file_name(format!(
+            ".{}.{}.part",
+            target
+                .path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy(),
+            Uuid::new_v4().simple()
+        ));
+        let ret = async {
+            let mut file = File::create(&tmp_path).await?;
+            let size = io::copy(&mut reader.take(limit.saturating_add(1)), &mut file).await?;
+            if size > limit {
+                bail!("Upload quota exceeded");
+            }
+            fs::rename(&tmp_path, &target.path).await?;
+            Ok(size)
+        }
+        .await;
+        match ret {
+            Ok(size) => {
+                self.written += size;
+                self.push(name, target.status, Some(target.rel_path), "");
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&tmp_path).await;
+                self.fail(name, &err.to_string());
+            }
+        }
+    }
+
+    /// Check the nearest existing ancestor of `path`, before any directory is created
+    /// through a symlink.
+    async fn is_contained(&self, path: &Path) -> bool {
+        let Some(root) = &self.root else {
+            return true;
+        };
+        let mut path = path;
+        loop {
+            match fs::canonicalize(path).await {
+                Ok(v) => return v.starts_with(root),
+                Err(_) => match path.parent() {
+                    Some(parent) => path = parent,
+                    None => return false,
+                },
+            }
+        }
+    }
+
+    fn fail(&mut self, name: String, error: &str) {
+        self.push(name, ExtractStatus::Failed, None, error);
+    }
+
+    fn push(&mut self, name: String, status: ExtractStatus, path: Option<String>, error: &str) {
+        self.results.push(ExtractResult {
+            name,
+            status,
+            path,
+            error: (!error.is_empty()).then(|| error.to_string()),
+        });
+    }
+}
+
+/// Find a free `name (n).ext` next to `path`.
+async fn rename_free(path: &Path, rel_path: &str) -> (PathBuf, String) {
+    let stem = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let ext = path
+        .extension()
+        .map(|v| format!(".{}", v.to_string_lossy()))
+        .unwrap_or_default();
+    let parent = rel_path.rsplit_once('/').map(|(v, _)| v);
+    let mut n = 1;
+    loop {
+        let file_name = format!("{stem} ({n}){ext}");
+        let new_path = path.with_file_name(&file_name);
+        if fs::symlink_metadata(&new_path).await.is_err() {
+            let new_rel_path = match parent {
+                Some(parent) => format!("{parent}/{file_name}"),
+                None => file_name,
+            };
+            return (new_path, new_rel_path);
+        }
+        n += 1;
+    }
+}
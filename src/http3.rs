@@ -0,0 +1,203 @@
+use crate::server::{ConnInfo, Server};
+use crate::utils::{get_cert_names, shutting_down};
+
+use anyhow::Result;
+use bytes::{Buf, Bytes};
+use h3::server::RequestResolver;
+use http_body_util::BodyExt;
+use hyper::body::{Body, Frame};
+use hyper::header::{HeaderName, CONNECTION, TRANSFER_ENCODING, UPGRADE};
+use quinn::crypto::rustls::QuicServerConfig;
+use rustls_pki_types::CertificateDer;
+use std::net::{SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, watch};
+use tokio_rustls::rustls::ServerConfig;
+
+const BODY_CHANNEL_SIZE: usize = 8;
+
+/// A QUIC endpoint on the UDP side of a TLS listener, with the same certificates.
+pub fn create_h3_endpoint(addr: SocketAddr, tls_config: &ServerConfig) -> Result<quinn::Endpoint> {
+    use socket2::{Domain, Protocol, Socket, Type};
+    let mut tls_config = tls_config.clone();
+    tls_config.alpn_protocols = vec![b"h3".to_vec()];
+    let crypto = QuicServerConfig::try_from(tls_config)?;
+    let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.set_nonblocking(true)?;
+    let endpoint = quinn::Endpoint::new(
+        quinn::EndpointConfig::default(),
+        Some(server_config),
+        UdpSocket::from(socket),
+        Arc::new(quinn::TokioRuntime),
+    )?;
+    Ok(endpoint)
+}
+
+/// Accept QUIC connections and pass their requests to `Server::call`, like the TCP
+/// listeners do.
+pub async fn serve_h3(
+    endpoint: quinn::Endpoint,
+    server: Arc<Server>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        let incoming = tokio::select! {
+            ret = endpoint.accept() => match ret {
+                Some(v) => v,
+                None => break,
+            },
+            _ = shutting_down(&mut shutdown) => break,
+        };
+        tokio::spawn(handle_connection(
+            server.clone(),
+            incoming,
+            shutdown.clone(),
+        ));
+    }
+}
+
+async fn handle_connection(
+    server: Arc<Server>,
+    incoming: quinn::Incoming,
+    shutdown: watch::Receiver<bool>,
+) {
+    let mut shutdown = Some(shutdown);
+    let addr = incoming.remote_address();
+    let conn = match incoming.await {
+        Ok(v) => v,
+        Err(err) => {
+            warn!("During QUIC handshake connection from {addr}: {err}");
+            return;
+        }
+    };
+    let cert_names = conn
+        .peer_identity()
+        .and_then(|v| v.downcast::<Vec<CertificateDer<'static>>>().ok())
+        .and_then(|v| v.first().map(|v| Arc::new(get_cert_names(v))));
+    let info = ConnInfo {
+        addr: Some(addr),
+        cert_names,
+        tls: true,
+        http3: true,
+        ..Default::default()
+    };
+    let mut h3_conn =
+        match h3::server::Connection::<_, Bytes>::new(h3_quinn::Connection::new(conn)).await {
+            Ok(v) => v,
+            Err(err) => {
+                warn!("Serving HTTP/3 connection from {addr}: {err}");
+                return;
+            }
+        };
+    loop {
+        let ret = match shutdown.as_mut() {
+            Some(rx) => tokio::select! {
+                ret = h3_conn.accept() => ret,
+                _ = shutting_down(rx) => {
+                    // The client only closes after the GOAWAY, so stop holding up the
+                    // shutdown here and leave that to the requests still in flight
+                    shutdown = None;
+                    let _ = h3_conn.shutdown(0).await;
+                    continue;
+                }
+            },
+            None => h3_conn.accept().await,
+        };
+        match ret {
+            Ok(Some(resolver)) => {
+                let server = server.clone();
+                let info = info.clone();
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_request(server, resolver, info).await {
+                        warn!("Serving HTTP/3 request from {addr}: {err}");
+                    }
+                    drop(shutdown);
+                });
+            }
+            Ok(None) => break,
+            Err(err) => {
+                if !err.is_h3_no_error() {
+                    warn!("Serving HTTP/3 connection from {addr}: {err}");
+                }
+                break;
+            }
+        }
+    }
+}
+
+async fn handle_request(
+    server: Arc<Server>,
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    info: ConnInfo,
+) -> Result<()> {
+    let (req, stream) = resolver.resolve_request().await?;
+    let (mut send, mut recv) = stream.split();
+
+    // The body is read on its own task, so the request stays `Sync` like hyper's
+    let (tx, rx) = mpsc::channel(BODY_CHANNEL_SIZE);
+    tokio::spawn(async move {
+        loop {
+            let data = match recv.recv_data().await {
+                Ok(Some(mut buf)) => Ok(buf.copy_to_bytes(buf.remaining())),
+                Ok(None) => break,
+                Err(err) => Err(anyhow::Error::from(err)),
+            };
+            let failed = data.is_err();
+            if tx.send(data).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+    let req = req.map(|_| H3Body { rx });
+
+    let res = server.call(req, info).await?;
+    let (mut parts, mut body) = res.into_parts();
+    // Connection-specific headers are malformed in HTTP/3
+    for name in [
+        CONNECTION,
+        TRANSFER_ENCODING,
+        UPGRADE,
+        HeaderName::from_static("keep-alive"),
+    ] {
+        parts.headers.remove(name);
+    }
+    send.send_response(hyper::Response::from_parts(parts, ()))
+        .await?;
+    while let Some(frame) = body.frame().await {
+        match frame?.into_data() {
+            Ok(data) => send.send_data(data).await?,
+            Err(frame) => {
+                if let Ok(trailers) = frame.into_trailers() {
+                    send.send_trailers(trailers).await?;
+                }
+            }
+        }
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+/// A request body fed by the task reading the QUIC stream.
+struct H3Body {
+    rx: mpsc::Receiver<Result<Bytes>>,
+}
+
+impl Body for H3Body {
+    type Data = Bytes;
+    type Error = anyhow::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.rx.poll_recv(cx).map(|v| v.map(|v| v.map(Frame::data)))
+    }
+}
@@ -4,6 +4,9 @@ use crate::{auth::get_auth_user, server::Request};
 
 pub const DEFAULT_LOG_FORMAT: &str = r#"$remote_addr "$request" $status"#;
 
+/// Query parameters carrying secrets, which are left out of the log.
+const SECRET_PARAMS: [&str; 2] = ["token", "share"];
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct HttpLogger {
     elements: Vec<LogElement>,
@@ -29,7 +32,8 @@ impl HttpLogger {
             match element {
                 LogElement::Variable(name) => match name.as_str() {
                     "request" => {
-                        data.insert(name.to_string(), format!("{} {}", req.method(), req.uri()));
+                        let uri = redact_uri(&req.uri().to_string());
+                        data.insert(name.to_string(), format!("{} {uri}", req.method()));
                     }
                     "remote_user" => {
                         if let Some(user) =
@@ -70,6 +74,21 @@ impl HttpLogger {
     }
 }
 
+/// Hide the values of the `SECRET_PARAMS` in the query of `uri`.
+fn redact_uri(uri: &str) -> String {
+    let Some((path, query)) = uri.split_once('?') else {
+        return uri.to_string();
+    };
+    let query: Vec<String> = query
+        .split('&')
+        .map(|v| match v.split_once('=') {
+            Some((key, _)) if SECRET_PARAMS.contains(&key) => format!("{key}=***"),
+            _ => v.to_string(),
+        })
+        .collect();
+    format!("{path}?{}", query.join("&"))
+}
+
 impl FromStr for HttpLogger {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -101,3 +120,19 @@ impl FromStr for HttpLogger {
         Ok(Self { elements })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_uri() {
+        assert_eq!(redact_uri("/dir1/file"), "/dir1/file");
+        assert_eq!(
+            redact_uri("/file?token=dufs_abc&inline"),
+            "/file?token=***&inline"
+        );
+        assert_eq!(redact_uri("/dir1/?share=abc.def"), "/dir1/?share=***");
+        assert_eq!(redact_uri("/?q=token=abc"), "/?q=token=abc");
+    }
+}
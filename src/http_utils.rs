@@ -1,7 +1,7 @@
 use bytes::{Bytes, BytesMut};
 use futures_util::Stream;
 use http_body_util::{combinators::BoxBody, BodyExt, Full};
-use hyper::body::{Body, Incoming};
+use hyper::body::Body;
 use std::{
     pin::Pin,
     task::{Context, Poll},
@@ -9,13 +9,16 @@ use std::{
 use tokio::io::AsyncRead;
 use tokio_util::io::poll_read_buf;
 
+/// The request body of every protocol, hyper's own on HTTP/1.1 and HTTP/2.
+pub type IncomingBody = BoxBody<Bytes, anyhow::Error>;
+
 #[derive(Debug)]
 pub struct IncomingStream {
-    inner: Incoming,
+    inner: IncomingBody,
 }
 
 impl IncomingStream {
-    pub fn new(inner: Incoming) -> Self {
+    pub fn new(inner: IncomingBody) -> Self {
         Self { inner }
     }
 }
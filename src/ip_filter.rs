@@ -0,0 +1,201 @@
+use anyhow::{anyhow, Result};
+use hyper::header::HeaderMap;
+use ipnet::IpNet;
+use std::net::{IpAddr, SocketAddr};
+
+/// Parse networks like `10.0.0.0/8`, a bare ip is a network of its own.
+pub fn parse_cidrs(values: &[String]) -> Result<Vec<IpNet>> {
+    values
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| parse_cidr(v).ok_or_else(|| anyhow!("Invalid ip or cidr `{v}`")))
+        .collect()
+}
+
+fn parse_cidr(value: &str) -> Option<IpNet> {
+    value
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| value.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// The resolved client ip, kept in the request extensions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub IpAddr);
+
+/// Networks a client may or may not come from.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    pub fn new(allow: &[String], deny: &[String]) -> Result<Self> {
+        Ok(Self {
+            allow: parse_cidrs(allow)?,
+            deny: parse_cidrs(deny)?,
+        })
+    }
+
+    /// Parse a list like `10.0.0.0/8,!10.0.0.5`, where `!` marks the denied networks.
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut allow = vec![];
+        let mut deny = vec![];
+        for item in value.split(',').map(|v| v.trim()) {
+            match item.strip_prefix('!') {
+                Some(v) => deny.push(v.to_string()),
+                None => allow.push(item.to_string()),
+            }
+        }
+        let filter = Self::new(&allow, &deny)?;
+        if filter.is_empty() {
+            return Err(anyhow!("Invalid ip list `{value}`"));
+        }
+        Ok(filter)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// A denied network always wins. With allowed networks, the ip has to be in one of them.
+    /// Requests without an ip, on unix sockets, are local and always pass.
+    pub fn allows(&self, ip: Option<IpAddr>) -> bool {
+        let Some(ip) = ip else {
+            return true;
+        };
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|v| v.contains(&ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|v| v.contains(&ip))
+    }
+}
+
+/// The ip of the client. Behind trusted proxies, it's the last address of the
+/// `Forwarded`/`X-Forwarded-For` chain that was not added by one of them.
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|v| v.contains(ip));
+    let mut ip = peer.to_canonical();
+    if !is_trusted(&ip) {
+        return ip;
+    }
+    for hop in forwarded_chain(headers).into_iter().rev() {
+        // An obfuscated or unknown hop ends what can be trusted
+        let Some(hop) = hop else {
+            break;
+        };
+        ip = hop.to_canonical();
+        if !is_trusted(&ip) {
+            break;
+        }
+    }
+    ip
+}
+
+/// The client and proxy addresses, the client first. `Forwarded` is preferred over
+/// `X-Forwarded-For` when a request has both.
+fn forwarded_chain(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| -> Vec<String> {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect()
+    };
+    let forwarded = values("forwarded");
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|v| v.trim().split_once('='))
+                    .find(|(k, _)| k.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, v)| parse_node(v.trim_matches('"')))
+            })
+            .collect();
+    }
+    values("x-forwarded-for")
+        .iter()
+        .map(|v| parse_node(v))
+        .collect()
+}
+
+/// Parse `1.2.3.4`, `1.2.3.4:80`, `2001:db8::1` or `[2001:db8::1]:80`.
+fn parse_node(value: &str) -> Option<IpAddr> {
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|v| v.ip()))
+        .or_else(|| {
+            let value = value.strip_prefix('[')?.strip_suffix(']')?;
+            value.parse::<IpAddr>().ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_ip_filter() {
+        let filter = IpFilter::parse("10.0.0.0/8, 2001:db8::/32,!10.0.0.5").unwrap();
+        assert!(filter.allows(Some(ip("10.1.2.3"))));
+        assert!(filter.allows(Some(ip("::ffff:10.1.2.3"))));
+        assert!(filter.allows(Some(ip("2001:db8::1"))));
+        assert!(!filter.allows(Some(ip("10.0.0.5"))));
+        assert!(!filter.allows(Some(ip("192.168.1.1"))));
+        assert!(filter.allows(None));
+
+        let filter = IpFilter::parse("!192.168.1.0/24").unwrap();
+        assert!(filter.allows(Some(ip("10.1.2.3"))));
+        assert!(!filter.allows(Some(ip("192.168.1.1"))));
+
+        assert!(IpFilter::default().allows(Some(ip("10.1.2.3"))));
+        assert!(IpFilter::parse("10.0.0.0/33").is_err());
+        assert!(IpFilter::parse("").is_err());
+    }
+
+    #[test]
+    fn test_resolve_client_ip() {
+        let trusted = parse_cidrs(&["127.0.0.1".into(), "10.0.0.0/8".into()]).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.9, 198.51.100.7, 10.0.0.2"),
+        );
+        assert_eq!(
+            resolve_client_ip(ip("127.0.0.1"), &headers, &trusted),
+            ip("198.51.100.7")
+        );
+        assert_eq!(
+            resolve_client_ip(ip("192.0.2.1"), &headers, &trusted),
+            ip("192.0.2.1")
+        );
+        headers.insert(
+            "forwarded",
+            HeaderValue::from_static(r#"for=203.0.113.9;proto=https, for="[2001:db8::1]:4711""#),
+        );
+        assert_eq!(
+            resolve_client_ip(ip("::ffff:127.0.0.1"), &headers, &trusted),
+            ip("2001:db8::1")
+        );
+        headers.insert("forwarded", HeaderValue::from_static("for=_hidden"));
+        assert_eq!(
+            resolve_client_ip(ip("127.0.0.1"), &headers, &trusted),
+            ip("127.0.0.1")
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+const SYNC_TOKEN_PREFIX: &str = "urn:dufs:sync:";
+const JOURNAL_CAPACITY: usize = 65536;
+const BROADCAST_CAPACITY: usize = 1024;
+
+/// In-memory log of filesystem changes, used to answer WebDAV
+/// `sync-collection` reports (RFC 6578) and to feed live event subscribers.
+///
+/// Sync tokens embed a per-process epoch, so tokens issued before a restart or
+/// older than the retained window are rejected and clients fall back to a full sync.
+#[derive(Debug)]
+pub struct ChangeJournal {
+    epoch: String,
+    capacity: usize,
+    inner: Mutex<JournalInner>,
+    sender: broadcast::Sender<Change>,
+}
+
+#[derive(Debug, Default)]
+struct JournalInner {
+    seq: u64,
+    entries: VecDeque<Change>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub seq: u64,
+    pub kind: ChangeKind,
+    pub path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Create,
+    Modify,
+    Delete,
+    Move(String),
+}
+
+impl Default for ChangeJournal {
+    fn default() -> Self {
+        Self::new(JOURNAL_CAPACITY)
+    }
+}
+
+impl ChangeJournal {
+    pub fn new(capacity: usize) -> Self {
+        let mut epoch = Uuid::new_v4().simple().to_string();
+        epoch.truncate(12);
+        let (sender, _) = broadcast::channel(BROADCAST_CAPACITY);
+        Self {
+            epoch,
+            capacity,
+            inner: Mutex::new(JournalInner::default()),
+            sender,
+        }
+    }
+
+    /// Record a change of `path`, which is relative to the serve path and uses `/` as separator.
+    pub fn record(&self, kind: ChangeKind, path: &str) -> Change {
+        let mut inner = self.inner.lock().unwrap();
+        inner.seq += 1;
+        let change = Change {
+            seq: inner.seq,
+            kind,
+            path: path.trim_matches('/').to_string(),
+        };
+        if inner.entries.len() >= self.capacity {
+            inner.entries.pop_front();
+        }
+        inner.entries.push_back(change.clone());
+        let _ = self.sender.send(change.clone());
+        change
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Change> {
+        self.sender.subscribe()
+    }
+
+    pub fn token(&self) -> String {
+        let seq = self.inner.lock().unwrap().seq;
+        self.to_token(seq)
+    }
+
+    pub fn to_token(&self, seq: u64) -> String {
+        format!("{SYNC_TOKEN_PREFIX}{}:{seq}", self.epoch)
+    }
+
+    /// Return changes recorded after `token`, or `None` if the token is unknown or expired.
+    pub fn since(&self, token: &str) -> Option<Vec<Change>> {
+        let (epoch, seq) = token.strip_prefix(SYNC_TOKEN_PREFIX)?.split_once(':')?;
+        if epoch != self.epoch {
+            return None;
+        }
+        let seq: u64 = seq.parse().ok()?;
+        let inner = self.inner.lock().unwrap();
+        if seq > inner.seq {
+            return None;
+        }
+        if let Some(first) = inner.entries.front() {
+            if seq + 1 < first.seq {
+                return None;
+            }
+        }
+        Some(
+            inner
+                .entries
+                .iter()
+                .filter(|v| v.seq > seq)
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_since() {
+        let journal = ChangeJournal::new(3);
+        let token = journal.token();
+        assert_eq!(journal.since(&token), Some(vec![]));
+        journal.record(ChangeKind::Create, "/dir1/file1");
+        journal.record(ChangeKind::Move("dir1/file1".into()), "dir1/file2");
+        let changes = journal.since(&token).unwrap();
+        assert_eq!(
+            changes.iter().map(|v| v.path.as_str()).collect::<Vec<_>>(),
+            ["dir1/file1", "dir1/file2"]
+        );
+        let token2 = journal.token();
+        journal.record(ChangeKind::Delete, "dir1/file2");
+        assert_eq!(journal.since(&token2).unwrap().len(), 1);
+        journal.record(ChangeKind::Modify, "dir1/file3");
+        assert_eq!(journal.since(&token), None);
+        assert_eq!(journal.since("urn:dufs:sync:000000000000:0"), None);
+        assert_eq!(journal.since("invalid"), None);
+    }
+}
@@ -1,18 +1,50 @@
+#[cfg(feature = "tls")]
+mod acme;
+mod archive_reader;
 mod args;
 mod auth;
+mod auth_provider;
+mod extract;
+#[cfg(feature = "http3")]
+mod http3;
 mod http_logger;
 mod http_utils;
+mod ip_filter;
+mod journal;
 mod logger;
+mod oidc;
+mod search_index;
+mod search_query;
 mod server;
+mod share;
+mod signer;
+mod stored_gzip;
+mod stored_zip;
+mod systemd;
+mod throttle;
+#[cfg(feature = "tls")]
+mod tls;
+mod token;
 mod utils;
+mod watcher;
 
 #[macro_use]
 extern crate log;
 
+#[cfg(feature = "tls")]
+use crate::acme::{Acme, ACME_TLS_ALPN_NAME};
+#[cfg(feature = "tls")]
+use crate::args::AcmeChallenge;
 use crate::args::{build_cli, print_completions, Args};
-use crate::server::Server;
+#[cfg(feature = "http3")]
+use crate::http3::{create_h3_endpoint, serve_h3};
+use crate::server::{ConnInfo, Server};
+use crate::systemd::ListenFds;
+#[cfg(feature = "tls")]
+use crate::tls::create_tls_config;
 #[cfg(feature = "tls")]
-use crate::utils::{load_certs, load_private_key};
+use crate::utils::get_cert_names;
+use crate::utils::shutting_down;
 
 use anyhow::{anyhow, Context, Result};
 use args::BindAddr;
@@ -29,9 +61,10 @@ use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use tokio::{net::TcpListener, task::JoinHandle};
+use std::time::Duration;
+use tokio::{net::TcpListener, sync::watch, task::JoinHandle};
 #[cfg(feature = "tls")]
-use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+use tokio_rustls::TlsAcceptor;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -44,12 +77,18 @@ async fn main() -> Result<()> {
         return Ok(());
     }
     let mut args = Args::parse(matches)?;
+    let mut listen_fds = systemd::listen_fds()?;
+    listen_fds.override_addrs(&mut args)?;
     let (new_addrs, print_addrs) = check_addrs(&args)?;
     args.addrs = new_addrs;
     let running = Arc::new(AtomicBool::new(true));
+    let (shutdown_tx, _) = watch::channel(false);
+    let shutdown_timeout = Duration::from_secs(args.shutdown_timeout);
     let listening = print_listening(&args, &print_addrs)?;
-    let handles = serve(args, running.clone())?;
+    let handles = serve(args, running.clone(), &shutdown_tx, &mut listen_fds)?;
     println!("{listening}");
+    systemd::notify_ready(listen_fds);
+    systemd::spawn_watchdog();
 
     tokio::select! {
         ret = join_all(handles) => {
@@ -61,87 +100,194 @@ async fn main() -> Result<()> {
             Ok(())
         },
         _ = shutdown_signal() => {
+            systemd::notify_stopping();
+            // Stops the listeners, the requests in flight keep running until they are done
+            shutdown_tx.send_replace(true);
+            info!("Shutting down, waiting for in-flight requests");
+            // Every connection holds a receiver, so the channel closes once they are all done
+            tokio::select! {
+                _ = shutdown_tx.closed() => {},
+                _ = tokio::time::sleep(shutdown_timeout) => {
+                    warn!("Shutdown timed out, dropping remaining connections");
+                },
+                _ = shutdown_signal() => {
+                    warn!("Forced shutdown, dropping remaining connections");
+                },
+            }
+            // Stops what is left on the blocking pool, the runtime waits for it on exit
             running.store(false, Ordering::SeqCst);
             Ok(())
         },
     }
 }
 
-fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
+fn serve(
+    args: Args,
+    running: Arc<AtomicBool>,
+    shutdown_tx: &watch::Sender<bool>,
+    listen_fds: &mut ListenFds,
+) -> Result<Vec<JoinHandle<()>>> {
+    let shutdown = shutdown_tx.subscribe();
     let addrs = args.addrs.clone();
     let port = args.port;
-    let tls_config = (args.tls_cert.clone(), args.tls_key.clone());
-    let server_handle = Arc::new(Server::init(args, running)?);
+    #[cfg(feature = "tls")]
+    let acme = Acme::new(&args)?.map(Arc::new);
+    #[cfg(feature = "tls")]
+    let tls_config = match args.tls() {
+        true => Some(create_tls_config(&args, acme.clone())?),
+        false => None,
+    };
+    #[cfg(feature = "tls")]
+    let tls_accepter = tls_config.clone().map(|v| TlsAcceptor::from(Arc::new(v)));
+    #[cfg(feature = "http3")]
+    let http3 = args.http3;
+    #[cfg(feature = "tls")]
+    let acme_http_port = acme
+        .as_ref()
+        .filter(|v| v.challenge() == AcmeChallenge::Http01)
+        .map(|_| args.acme_http_port);
+    let server = Server::init(args, running.clone(), shutdown_tx.clone())?;
+    #[cfg(feature = "tls")]
+    let server = server.with_acme(acme.clone());
+    let server_handle = Arc::new(server);
     let mut handles = vec![];
     for bind_addr in addrs.iter() {
         let server_handle = server_handle.clone();
+        let mut shutdown = shutdown.clone();
         match bind_addr {
             BindAddr::Address(ip) => {
-                let listener = create_listener(SocketAddr::new(*ip, port))
+                let listener = create_listener(SocketAddr::new(*ip, port), listen_fds)
                     .with_context(|| format!("Failed to bind `{ip}:{port}`"))?;
 
-                match &tls_config {
-                    #[cfg(feature = "tls")]
-                    (Some(cert_file), Some(key_file)) => {
-                        let certs = load_certs(cert_file)?;
-                        let key = load_private_key(key_file)?;
-                        let mut config = ServerConfig::builder()
-                            .with_no_client_auth()
-                            .with_single_cert(certs, key)?;
-                        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
-                        let config = Arc::new(config);
-                        let tls_accepter = TlsAcceptor::from(config);
+                #[cfg(feature = "tls")]
+                if let Some(http_port) = acme_http_port {
+                    let listener = create_listener(SocketAddr::new(*ip, http_port), listen_fds)
+                        .with_context(|| format!("Failed to bind `{ip}:{http_port}`"))?;
+                    let server_handle = server_handle.clone();
+                    let mut shutdown = shutdown.clone();
+                    let handle = tokio::spawn(async move {
+                        loop {
+                            let (cnx, addr) = tokio::select! {
+                                ret = listener.accept() => ret.unwrap(),
+                                _ = shutting_down(&mut shutdown) => break,
+                            };
+                            let stream = TokioIo::new(cnx);
+                            let conn = ConnInfo {
+                                addr: Some(addr),
+                                acme_http: true,
+                                ..Default::default()
+                            };
+                            let shutdown = shutdown.clone();
+                            tokio::spawn(handle_stream(
+                                server_handle.clone(),
+                                stream,
+                                conn,
+                                shutdown,
+                            ));
+                        }
+                    });
+                    handles.push(handle);
+                }
 
-                        let handle = tokio::spawn(async move {
-                            loop {
-                                let (cnx, addr) = listener.accept().await.unwrap();
-                                let Ok(stream) = tls_accepter.accept(cnx).await else {
-                                    warn!("During cls handshake connection from {}", addr);
-                                    continue;
-                                };
-                                let stream = TokioIo::new(stream);
-                                tokio::spawn(handle_stream(
-                                    server_handle.clone(),
-                                    stream,
-                                    Some(addr),
-                                ));
-                            }
-                        });
+                #[cfg(feature = "http3")]
+                if let Some(tls_config) = tls_config.as_ref().filter(|_| http3) {
+                    let endpoint = create_h3_endpoint(SocketAddr::new(*ip, port), tls_config)
+                        .with_context(|| format!("Failed to bind `{ip}:{port}` for HTTP/3"))?;
+                    handles.push(tokio::spawn(serve_h3(
+                        endpoint,
+                        server_handle.clone(),
+                        shutdown.clone(),
+                    )));
+                }
 
-                        handles.push(handle);
-                    }
-                    (None, None) => {
-                        let handle = tokio::spawn(async move {
-                            loop {
-                                let (cnx, addr) = listener.accept().await.unwrap();
-                                let stream = TokioIo::new(cnx);
-                                tokio::spawn(handle_stream(
-                                    server_handle.clone(),
-                                    stream,
-                                    Some(addr),
-                                ));
+                #[cfg(feature = "tls")]
+                if let Some(tls_accepter) = tls_accepter.clone() {
+                    let handle = tokio::spawn(async move {
+                        loop {
+                            let (cnx, addr) = tokio::select! {
+                                ret = listener.accept() => ret.unwrap(),
+                                _ = shutting_down(&mut shutdown) => break,
+                            };
+                            let Ok(stream) = tls_accepter.accept(cnx).await else {
+                                warn!("During cls handshake connection from {}", addr);
+                                continue;
+                            };
+                            let session = stream.get_ref().1;
+                            // The CA only validates the challenge certificate
+                            if session.alpn_protocol() == Some(ACME_TLS_ALPN_NAME) {
+                                continue;
                             }
-                        });
-                        handles.push(handle);
-                    }
-                    _ => {
-                        unreachable!()
+                            let cert_names = session
+                                .peer_certificates()
+                                .and_then(|v| v.first())
+                                .map(|v| Arc::new(get_cert_names(v)));
+                            let stream = TokioIo::new(stream);
+                            let conn = ConnInfo {
+                                addr: Some(addr),
+                                cert_names,
+                                tls: true,
+                                ..Default::default()
+                            };
+                            let shutdown = shutdown.clone();
+                            tokio::spawn(handle_stream(
+                                server_handle.clone(),
+                                stream,
+                                conn,
+                                shutdown,
+                            ));
+                        }
+                    });
+
+                    handles.push(handle);
+                    continue;
+                }
+
+                let handle = tokio::spawn(async move {
+                    loop {
+                        let (cnx, addr) = tokio::select! {
+                            ret = listener.accept() => ret.unwrap(),
+                            _ = shutting_down(&mut shutdown) => break,
+                        };
+                        let stream = TokioIo::new(cnx);
+                        let conn = ConnInfo {
+                            addr: Some(addr),
+                            ..Default::default()
+                        };
+                        let shutdown = shutdown.clone();
+                        tokio::spawn(handle_stream(server_handle.clone(), stream, conn, shutdown));
                     }
-                };
+                });
+                handles.push(handle);
             }
             BindAddr::Path(path) => {
-                if path.exists() {
-                    std::fs::remove_file(path)?;
-                }
                 #[cfg(unix)]
                 {
-                    let listener = tokio::net::UnixListener::bind(path)
-                        .with_context(|| format!("Failed to bind `{}`", path.display()))?;
+                    let listener = match listen_fds.take_unix(path) {
+                        Some(v) => v,
+                        None => {
+                            if path.exists() {
+                                std::fs::remove_file(path)?;
+                            }
+                            std::os::unix::net::UnixListener::bind(path)
+                                .with_context(|| format!("Failed to bind `{}`", path.display()))?
+                        }
+                    };
+                    listener.set_nonblocking(true)?;
+                    let listener = tokio::net::UnixListener::from_std(listener)?;
+                    listen_fds.push_listener(&listener);
                     let handle = tokio::spawn(async move {
                         loop {
-                            let (cnx, _) = listener.accept().await.unwrap();
+                            let (cnx, _) = tokio::select! {
+                                ret = listener.accept() => ret.unwrap(),
+                                _ = shutting_down(&mut shutdown) => break,
+                            };
                             let stream = TokioIo::new(cnx);
-                            tokio::spawn(handle_stream(server_handle.clone(), stream, None));
+                            tokio::spawn(handle_stream(
+                                server_handle.clone(),
+                                stream,
+                                ConnInfo::default(),
+                                shutdown.clone(),
+                            ));
                         }
                     });
 
@@ -150,19 +296,36 @@ fn serve(args: Args, running: Arc<AtomicBool>) -> Result<Vec<JoinHandle<()>>> {
             }
         }
     }
+    #[cfg(feature = "tls")]
+    if let Some(acme) = acme {
+        acme.spawn(running);
+    }
     Ok(handles)
 }
 
-async fn handle_stream<T>(handle: Arc<Server>, stream: TokioIo<T>, addr: Option<SocketAddr>)
-where
+async fn handle_stream<T>(
+    handle: Arc<Server>,
+    stream: TokioIo<T>,
+    conn: ConnInfo,
+    mut shutdown: watch::Receiver<bool>,
+) where
     T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
 {
+    let addr = conn.addr;
     let hyper_service =
-        service_fn(move |request: Request<Incoming>| handle.clone().call(request, addr));
+        service_fn(move |request: Request<Incoming>| handle.clone().call(request, conn.clone()));
 
-    let ret = Builder::new(TokioExecutor::new())
-        .serve_connection_with_upgrades(stream, hyper_service)
-        .await;
+    let builder = Builder::new(TokioExecutor::new());
+    let conn = builder.serve_connection_with_upgrades(stream, hyper_service);
+    tokio::pin!(conn);
+    // Idle connections close right away, busy ones once their in-flight requests are done
+    let ret = tokio::select! {
+        ret = conn.as_mut() => ret,
+        _ = shutting_down(&mut shutdown) => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
 
     if let Err(err) = ret {
         let scope = match addr {
@@ -176,18 +339,24 @@ where
     }
 }
 
-fn create_listener(addr: SocketAddr) -> Result<TcpListener> {
+fn create_listener(addr: SocketAddr, listen_fds: &mut ListenFds) -> Result<TcpListener> {
     use socket2::{Domain, Protocol, Socket, Type};
-    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
-    if addr.is_ipv6() {
-        socket.set_only_v6(true)?;
-    }
-    socket.set_reuse_address(true)?;
-    socket.bind(&addr.into())?;
-    socket.listen(1024 /* Default backlog */)?;
-    let std_listener = StdTcpListener::from(socket);
+    let std_listener = match listen_fds.take_tcp(addr) {
+        Some(v) => v,
+        None => {
+            let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+            if addr.is_ipv6() {
+                socket.set_only_v6(true)?;
+            }
+            socket.set_reuse_address(true)?;
+            socket.bind(&addr.into())?;
+            socket.listen(1024 /* Default backlog */)?;
+            StdTcpListener::from(socket)
+        }
+    };
     std_listener.set_nonblocking(true)?;
     let listener = TcpListener::from_std(std_listener)?;
+    listen_fds.push_listener(&listener);
     Ok(listener)
 }
 
@@ -255,11 +424,7 @@ fn print_listening(args: &Args, print_addrs: &[BindAddr]) -> Result<String> {
                     IpAddr::V4(_) => format!("{}:{}", addr, args.port),
                     IpAddr::V6(_) => format!("[{}]:{}", addr, args.port),
                 };
-                let protocol = if args.tls_cert.is_some() {
-                    "https"
-                } else {
-                    "http"
-                };
+                let protocol = if args.tls() { "https" } else { "http" };
                 format!("{}://{}{}", protocol, addr, args.uri_prefix)
             }
             BindAddr::Path(path) => path.display().to_string(),
@@ -281,7 +446,22 @@ fn print_listening(args: &Args, print_addrs: &[BindAddr]) -> Result<String> {
 }
 
 async fn shutdown_signal() {
-    tokio::signal::ctrl_c()
-        .await
-        .expect("Failed to install CTRL+C signal handler")
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install CTRL+C signal handler")
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM signal handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
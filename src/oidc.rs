@@ -0,0 +1,356 @@
+use crate::auth::AccessPaths;
+use crate::signer::Signer;
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use url::Url;
+use uuid::Uuid;
+
+pub const SESSION_COOKIE: &str = "dufs_session";
+pub const LOGIN_COOKIE: &str = "dufs_oidc";
+pub const SESSION_TTL: i64 = 43200; // 12 hours
+pub const LOGIN_TTL: i64 = 600;
+
+const SCOPES: &str = "openid email profile";
+const USER_CLAIMS: [&str; 3] = ["email", "preferred_username", "sub"];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A signed in user and the path rules its claims granted, kept in the session cookie.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(rename = "u")]
+    pub user: String,
+    #[serde(rename = "p")]
+    pub paths: String,
+    #[serde(rename = "e")]
+    pub expires: i64,
+}
+
+/// Signs and checks the session cookies.
+#[derive(Debug)]
+pub struct Sessions {
+    signer: Signer,
+}
+
+impl Sessions {
+    /// Without a secret, a random one is used and users have to log in again on restart.
+    pub fn new(secret: Option<&str>) -> Self {
+        Self {
+            signer: Signer::new(secret),
+        }
+    }
+
+    /// Returns the cookie value of a new session.
+    pub fn create(&self, user: &str, paths: &str) -> Result<String> {
+        self.signer.sign(&Session {
+            user: user.to_string(),
+            paths: paths.to_string(),
+            expires: Utc::now().timestamp() + SESSION_TTL,
+        })
+    }
+
+    pub fn verify(&self, value: &str) -> Option<Session> {
+        let session: Session = self.signer.verify(value)?;
+        if session.expires <= Utc::now().timestamp() {
+            return None;
+        }
+        Some(session)
+    }
+}
+
+/// Grants `paths` to the users whose `claim` is or contains `value`, or to every user.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OidcRule {
+    claim: Option<(String, String)>,
+    paths: String,
+}
+
+impl OidcRule {
+    /// Parse `<claim>=<value>@<paths>`, or `*@<paths>` for every user.
+    pub fn parse(rule: &str) -> Result<Self> {
+        let err = || anyhow!("Invalid oidc rule `{rule}`");
+        let (matcher, paths) = rule.split_once("@/").ok_or_else(err)?;
+        let paths = format!("/{paths}");
+        AccessPaths::default().merge(&paths).ok_or_else(err)?;
+        let claim = if matcher == "*" {
+            None
+        } else {
+            let (claim, value) = matcher.split_once('=').ok_or_else(err)?;
+            if claim.is_empty() || value.is_empty() {
+                return Err(err());
+            }
+            Some((claim.to_string(), value.to_string()))
+        };
+        Ok(Self { claim, paths })
+    }
+
+    fn matches(&self, claims: &Value) -> bool {
+        let Some((claim, value)) = &self.claim else {
+            return true;
+        };
+        let matches = |v: &Value| match v {
+            Value::String(v) => v == value,
+            Value::Bool(_) | Value::Number(_) => &v.to_string() == value,
+            _ => false,
+        };
+        match claims.get(claim) {
+            Some(Value::Array(values)) => values.iter().any(matches),
+            Some(v) => matches(v),
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    end_session_endpoint: Option<String>,
+}
+
+/// What the login cookie keeps between the redirect to the provider and the callback.
+#[derive(Debug, Serialize, Deserialize)]
+struct LoginState {
+    #[serde(rename = "s")]
+    state: String,
+    #[serde(rename = "n")]
+    nonce: String,
+    #[serde(rename = "v")]
+    verifier: String,
+    #[serde(rename = "r")]
+    next: String,
+    #[serde(rename = "x")]
+    expires: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+/// An OpenID Connect relying party using the authorization code flow with PKCE.
+#[derive(Debug)]
+pub struct Oidc {
+    issuer: String,
+    client_id: String,
+    client_secret: Option<String>,
+    rules: Vec<OidcRule>,
+    sessions: Arc<Sessions>,
+    discovery: OnceCell<Discovery>,
+    client: reqwest::Client,
+}
+
+impl Oidc {
+    pub fn new(
+        issuer: &str,
+        client_id: &str,
+        client_secret: Option<&str>,
+        rules: &[String],
+        sessions: Arc<Sessions>,
+    ) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|v| OidcRule::parse(v))
+            .collect::<Result<Vec<_>>>()?;
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            issuer: issuer.trim_end_matches('/').to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.map(|v| v.to_string()),
+            rules,
+            sessions,
+            discovery: OnceCell::new(),
+            client,
+        })
+    }
+
+    /// Returns the authorization URL to send the user to, and the login cookie value.
+    pub async fn start(&self, redirect_url: &str, next: &str) -> Result<(String, String)> {
+        let discovery = self.discovery().await?;
+        let state = random_string();
+        let nonce = random_string();
+        let verifier = format!("{}{}", random_string(), random_string());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let mut url = Url::parse(&discovery.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", redirect_url)
+            .append_pair("scope", SCOPES)
+            .append_pair("state", &state)
+            .append_pair("nonce", &nonce)
+            .append_pair("code_challenge", &challenge)
+            .append_pair("code_challenge_method", "S256");
+        let login = self.sessions.signer.sign(&LoginState {
+            state,
+            nonce,
+            verifier,
+            next: next.to_string(),
+            expires: Utc::now().timestamp() + LOGIN_TTL,
+        })?;
+        Ok((url.to_string(), login))
+    }
+
+    /// Exchange the code of the callback for the id token, returns the session cookie
+    /// value and where to go next.
+    pub async fn finish(
+        &self,
+        redirect_url: &str,
+        code: &str,
+        state: &str,
+        login: Option<&str>,
+    ) -> Result<(String, String)> {
+        let login: LoginState = login
+            .and_then(|v| self.sessions.signer.verify(v))
+            .filter(|v: &LoginState| v.state == state && v.expires > Utc::now().timestamp())
+            .ok_or_else(|| anyhow!("Invalid login state"))?;
+        let discovery = self.discovery().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_url),
+            ("client_id", &self.client_id),
+            ("code_verifier", &login.verifier),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let res = self
+            .client
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await
+            .context("Failed to request the token endpoint")?;
+        if !res.status().is_success() {
+            bail!("Token endpoint returned {}", res.status());
+        }
+        let tokens: TokenResponse = serde_json::from_slice(&res.bytes().await?)
+            .context("Invalid token endpoint response")?;
+        let claims = self.check_id_token(&tokens.id_token, &discovery.issuer, &login.nonce)?;
+        let user = USER_CLAIMS
+            .iter()
+            .find_map(|v| claims.get(v).and_then(|v| v.as_str()))
+            .filter(|v| !v.is_empty())
+            .ok_or_else(|| anyhow!("No user in the id token"))?;
+        let paths: Vec<&str> = self
+            .rules
+            .iter()
+            .filter(|v| v.matches(&claims))
+            .map(|v| v.paths.as_str())
+            .collect();
+        let session = self.sessions.create(user, &paths.join(","))?;
+        Ok((session, login.next))
+    }
+
+    /// Where to end the session at the provider, if it supports that.
+    pub async fn logout_url(&self, redirect_url: &str) -> Option<String> {
+        let discovery = self.discovery().await.ok()?;
+        let mut url = Url::parse(discovery.end_session_endpoint.as_ref()?).ok()?;
+        url.query_pairs_mut()
+            .append_pair("client_id", &self.client_id)
+            .append_pair("post_logout_redirect_uri", redirect_url);
+        Some(url.to_string())
+    }
+
+    async fn discovery(&self) -> Result<&Discovery> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer);
+                let res = self.client.get(&url).send().await?.error_for_status()?;
+                let discovery: Discovery = serde_json::from_slice(&res.bytes().await?)?;
+                anyhow::Ok(discovery)
+            })
+            .await
+            .with_context(|| format!("Failed to discover oidc issuer `{}`", self.issuer))
+    }
+
+    /// The id token comes straight from the token endpoint over the back channel, so its
+    /// claims are checked but not its signature.
+    fn check_id_token(&self, id_token: &str, issuer: &str, nonce: &str) -> Result<Value> {
+        let err = || anyhow!("Invalid id token");
+        let payload = id_token.split('.').nth(1).ok_or_else(err)?;
+        let payload = URL_SAFE_NO_PAD
+            .decode(payload.trim_end_matches('='))
+            .map_err(|_| err())?;
+        let claims: Value = serde_json::from_slice(&payload).map_err(|_| err())?;
+        if claims.get("iss").and_then(|v| v.as_str()) != Some(issuer) {
+            bail!("Invalid id token issuer");
+        }
+        let audience = match claims.get("aud") {
+            Some(Value::String(v)) => v == &self.client_id,
+            Some(Value::Array(v)) => v.iter().any(|v| v.as_str() == Some(&self.client_id)),
+            _ => false,
+        };
+        if !audience {
+            bail!("Invalid id token audience");
+        }
+        let expires = claims
+            .get("exp")
+            .and_then(|v| v.as_i64())
+            .unwrap_or_default();
+        if expires <= Utc::now().timestamp() {
+            bail!("Expired id token");
+        }
+        if claims.get("nonce").and_then(|v| v.as_str()) != Some(nonce) {
+            bail!("Invalid id token nonce");
+        }
+        Ok(claims)
+    }
+}
+
+fn random_string() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_oidc_rule() {
+        let claims = json!({
+            "email": "alice@example.com",
+            "groups": ["admins", "staff"],
+            "email_verified": true,
+        });
+        assert!(OidcRule::parse("groups=admins@/:rw")
+            .unwrap()
+            .matches(&claims));
+        assert!(OidcRule::parse("email=alice@example.com@/dir1")
+            .unwrap()
+            .matches(&claims));
+        assert!(OidcRule::parse("email_verified=true@/dir1")
+            .unwrap()
+            .matches(&claims));
+        assert!(OidcRule::parse("*@/dir1,/dir2:rw")
+            .unwrap()
+            .matches(&claims));
+        assert!(!OidcRule::parse("groups=users@/").unwrap().matches(&claims));
+        assert!(!OidcRule::parse("team=admins@/").unwrap().matches(&claims));
+        assert!(OidcRule::parse("groups@/").is_err());
+        assert!(OidcRule::parse("groups=admins").is_err());
+        assert!(OidcRule::parse("groups=admins@/:xx").is_err());
+    }
+
+    #[test]
+    fn test_sessions() {
+        let sessions = Sessions::new(Some("secret"));
+        let value = sessions.create("alice", "/dir1:rw").unwrap();
+        let session = sessions.verify(&value).unwrap();
+        assert_eq!(session.user, "alice");
+        assert_eq!(session.paths, "/dir1:rw");
+        assert!(Sessions::new(Some("secret2")).verify(&value).is_none());
+    }
+}
@@ -0,0 +1,392 @@
+use crate::journal::{Change, ChangeJournal, ChangeKind};
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast::error::RecvError;
+use walkdir::WalkDir;
+
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Index of the names, sizes, mtimes and types under the serve path, so name
+/// searches don't have to walk the whole tree.
+///
+/// The index is loaded from its file at startup, rebuilt in the background and then
+/// kept current from the change journal. It is written back to the file periodically.
+#[derive(Debug)]
+pub struct SearchIndex {
+    root: PathBuf,
+    file: PathBuf,
+    ignored: Vec<String>,
+    entries: RwLock<BTreeMap<String, IndexEntry>>,
+    ready: AtomicBool,
+    dirty: AtomicBool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    pub size: u64,
+    pub mtime: u64,
+}
+
+type IndexLine = (String, bool, bool, u64, u64);
+
+struct Scanned {
+    parents: Vec<(String, IndexEntry)>,
+    subtree: BTreeMap<String, IndexEntry>,
+}
+
+impl SearchIndex {
+    pub fn new(root: &Path, file: &Path) -> Self {
+        let file = match (file.parent(), file.file_name()) {
+            (Some(parent), Some(name)) => fs::canonicalize(parent)
+                .map(|v| v.join(name))
+                .unwrap_or_else(|_| file.to_path_buf()),
+            _ => file.to_path_buf(),
+        };
+        // Don't index the index file itself, or every save would mark it dirty again.
+        let ignored = match file.strip_prefix(root).ok().and_then(|v| v.to_str()) {
+            Some(path) => {
+                let path = path.replace('\\', "/");
+                vec![format!("{path}.tmp"), path]
+            }
+            None => vec![],
+        };
+        Self {
+            root: root.to_path_buf(),
+            file,
+            ignored,
+            entries: RwLock::new(BTreeMap::new()),
+            ready: AtomicBool::new(false),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Load the index file, rebuild the index, then apply journal changes until shutdown.
+    pub fn spawn(self: Arc<Self>, journal: &ChangeJournal, running: Arc<AtomicBool>) {
+        let mut receiver = journal.subscribe();
+        tokio::spawn(async move {
+            let index = self.clone();
+            let ret = tokio::task::spawn_blocking(move || {
+                if let Err(err) = index.load() {
+                    debug!("Skip loading search index, {err}");
+                }
+            })
+            .await;
+            if ret.is_err() {
+                return;
+            }
+            if !self.clone().rebuild(running.clone()).await {
+                return;
+            }
+            let mut interval = tokio::time::interval(SAVE_INTERVAL);
+            loop {
+                tokio::select! {
+                    ret = receiver.recv() => match ret {
+                        Ok(change) => {
+                            let index = self.clone();
+                            let _ = tokio::task::spawn_blocking(move || index.apply(&change)).await;
+                        }
+                        Err(RecvError::Lagged(_)) => {
+                            if !self.clone().rebuild(running.clone()).await {
+                                break;
+                            }
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = interval.tick() => {
+                        if !running.load(atomic::Ordering::SeqCst) {
+                            break;
+                        }
+                        self.clone().save_if_dirty().await;
+                    }
+                }
+            }
+            self.save_if_dirty().await;
+        });
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(atomic::Ordering::SeqCst)
+    }
+
+    /// Find entries under `base` accepted by `filter`, which is given the base name.
+    ///
+    /// Returns `None` if the index is not ready or does not cover `base`.
+    pub fn search<F>(&self, base: &str, filter: F) -> Option<Vec<(String, IndexEntry)>>
+    where
+        F: Fn(&str, &IndexEntry) -> bool,
+    {
+        if !self.is_ready() {
+            return None;
+        }
+        let entries = self.entries.read().unwrap();
+        let prefix = if base.is_empty() {
+            String::new()
+        } else {
+            match entries.get(base) {
+                Some(entry) if entry.is_dir && !entry.is_symlink => {}
+                _ => return None,
+            }
+            format!("{base}/")
+        };
+        let output = entries
+            .range(prefix.clone()..)
+            .take_while(|(path, _)| path.starts_with(&prefix))
+            .filter(|(path, entry)| filter(path.rsplit('/').next().unwrap_or_default(), entry))
+            .map(|(path, entry)| (path[prefix.len()..].to_string(), entry.clone()))
+            .collect();
+        Some(output)
+    }
+
+    async fn rebuild(self: Arc<Self>, running: Arc<AtomicBool>) -> bool {
+        let index = self.clone();
+        let ret = tokio::task::spawn_blocking(move || {
+            let mut entries = BTreeMap::new();
+            index.walk(&index.root, &mut entries, &running)?;
+            *index.entries.write().unwrap() = entries;
+            index.ready.store(true, atomic::Ordering::SeqCst);
+            index.save()
+        })
+        .await;
+        match ret {
+            Ok(Ok(())) => true,
+            Ok(Err(err)) => {
+                warn!("Failed to build search index, {err}");
+                self.is_ready()
+            }
+            Err(_) => false,
+        }
+    }
+
+    async fn save_if_dirty(self: Arc<Self>) {
+        if !self.dirty.swap(false, atomic::Ordering::SeqCst) {
+            return;
+        }
+        let index = self.clone();
+        if let Ok(Err(err)) = tokio::task::spawn_blocking(move || index.save()).await {
+            warn!("Failed to save search index, {err}");
+        }
+    }
+
+    fn apply(&self, change: &Change) {
+        let path = change.path.as_str();
+        let skip = path.is_empty() || self.ignored.iter().any(|v| v == path);
+        // Stat and walk before taking the lock, so searches go on during large rescans
+        let scanned = if skip { None } else { Some(self.scan(path)) };
+        let mut entries = self.entries.write().unwrap();
+        if let ChangeKind::Move(from) = &change.kind {
+            remove_subtree(&mut entries, from);
+        }
+        match scanned {
+            Some(Some(scanned)) => {
+                // Parent directories may have been created along with the path
+                for (parent, entry) in scanned.parents {
+                    if entries.contains_key(&parent) {
+                        break;
+                    }
+                    entries.insert(parent, entry);
+                }
+                remove_subtree(&mut entries, path);
+                entries.extend(scanned.subtree);
+            }
+            Some(None) => remove_subtree(&mut entries, path),
+            None => {}
+        }
+        self.dirty.store(true, atomic::Ordering::SeqCst);
+    }
+
+    /// Read `path`, its subtree if it is a directory, and its parents, nearest first.
+    /// `None` if the path is gone.
+    fn scan(&self, path: &str) -> Option<Scanned> {
+        let full_path = self.root.join(path);
+        let entry = to_entry(&full_path)?;
+        let mut parents = vec![];
+        let mut parent = path;
+        while let Some((v, _)) = parent.rsplit_once('/') {
+            parent = v;
+            if let Some(entry) = to_entry(&self.root.join(parent)) {
+                parents.push((parent.to_string(), entry));
+            }
+        }
+        let is_dir = entry.is_dir && !entry.is_symlink;
+        let mut subtree = BTreeMap::new();
+        subtree.insert(path.to_string(), entry);
+        if is_dir {
+            let running = AtomicBool::new(true);
+            let _ = self.walk(&full_path, &mut subtree, &running);
+        }
+        Some(Scanned { parents, subtree })
+    }
+
+    fn walk(
+        &self,
+        dir: &Path,
+        entries: &mut BTreeMap<String, IndexEntry>,
+        running: &AtomicBool,
+    ) -> Result<()> {
+        let mut it = WalkDir::new(dir).into_iter();
+        it.next();
+        for entry in it {
+            if !running.load(atomic::Ordering::SeqCst) {
+                anyhow::bail!("Interrupted");
+            }
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let Some(path) = self.to_relative_path(entry.path()) else {
+                continue;
+            };
+            if self.ignored.contains(&path) {
+                continue;
+            }
+            if let Some(entry) = to_entry(entry.path()) {
+                entries.insert(path, entry);
+            }
+        }
+        Ok(())
+    }
+
+    fn load(&self) -> Result<()> {
+        let file = fs::File::open(&self.file)?;
+        let mut entries = BTreeMap::new();
+        for line in BufReader::new(file).lines() {
+            let (path, is_dir, is_symlink, size, mtime): IndexLine = serde_json::from_str(&line?)?;
+            entries.insert(
+                path,
+                IndexEntry {
+                    is_dir,
+                    is_symlink,
+                    size,
+                    mtime,
+                },
+            );
+        }
+        *self.entries.write().unwrap() = entries;
+        self.ready.store(true, atomic::Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let tmp_file = PathBuf::from(format!("{}.tmp", self.file.display()));
+        let file = fs::File::create(&tmp_file)
+            .with_context(|| format!("Failed to create `{}`", tmp_file.display()))?;
+        let mut writer = BufWriter::new(file);
+        {
+            let entries = self.entries.read().unwrap();
+            for (path, entry) in entries.iter() {
+                let line: (&str, bool, bool, u64, u64) = (
+                    path,
+                    entry.is_dir,
+                    entry.is_symlink,
+                    entry.size,
+                    entry.mtime,
+                );
+                serde_json::to_writer(&mut writer, &line)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        writer.flush()?;
+        fs::rename(&tmp_file, &self.file)
+            .with_context(|| format!("Failed to write `{}`", self.file.display()))?;
+        Ok(())
+    }
+
+    fn to_relative_path(&self, path: &Path) -> Option<String> {
+        let path = path.strip_prefix(&self.root).ok()?.to_str()?;
+        if cfg!(windows) {
+            Some(path.replace('\\', "/"))
+        } else {
+            Some(path.to_string())
+        }
+    }
+}
+
+fn to_entry(path: &Path) -> Option<IndexEntry> {
+    let meta = fs::symlink_metadata(path).ok()?;
+    let is_symlink = meta.is_symlink();
+    let meta = if is_symlink {
+        fs::metadata(path).ok()?
+    } else {
+        meta
+    };
+    let mtime = meta
+        .modified()
+        .ok()?
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    Some(IndexEntry {
+        is_dir: meta.is_dir(),
+        is_symlink,
+        size: meta.len(),
+        mtime,
+    })
+}
+
+fn remove_subtree(entries: &mut BTreeMap<String, IndexEntry>, path: &str) {
+    entries.remove(path);
+    let prefix = format!("{path}/");
+    let children: Vec<String> = entries
+        .range(prefix.clone()..)
+        .take_while(|(k, _)| k.starts_with(&prefix))
+        .map(|(k, _)| k.clone())
+        .collect();
+    for child in children {
+        entries.remove(&child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_index() {
+        let root = std::env::temp_dir().join(format!("dufs-index-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("dir1/sub")).unwrap();
+        fs::write(root.join("dir1/sub/Foo.txt"), "foo").unwrap();
+        fs::write(root.join("foo.md"), "foo").unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+        let index = SearchIndex::new(&root, &root.join("index.jsonl"));
+        let foo = |name: &str, _: &IndexEntry| name.to_lowercase().contains("foo");
+        assert_eq!(index.search("", foo), None);
+
+        let mut entries = BTreeMap::new();
+        index
+            .walk(&root, &mut entries, &AtomicBool::new(true))
+            .unwrap();
+        *index.entries.write().unwrap() = entries;
+        index.ready.store(true, atomic::Ordering::SeqCst);
+        index.save().unwrap();
+        let names = |v: Option<Vec<(String, IndexEntry)>>| {
+            v.unwrap().into_iter().map(|(k, _)| k).collect::<Vec<_>>()
+        };
+        assert_eq!(names(index.search("", foo)), ["dir1/sub/Foo.txt", "foo.md"]);
+        assert_eq!(names(index.search("dir1", foo)), ["sub/Foo.txt"]);
+        assert_eq!(index.search("dir2", foo), None);
+
+        fs::rename(root.join("dir1/sub"), root.join("dir1/sub2")).unwrap();
+        index.apply(&Change {
+            seq: 1,
+            kind: ChangeKind::Move("dir1/sub".into()),
+            path: "dir1/sub2".into(),
+        });
+        assert_eq!(names(index.search("dir1", foo)), ["sub2/Foo.txt"]);
+
+        let index2 = SearchIndex::new(&root, &root.join("index.jsonl"));
+        index2.load().unwrap();
+        assert_eq!(
+            names(index2.search("", foo)),
+            ["dir1/sub/Foo.txt", "foo.md"]
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
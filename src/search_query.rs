@@ -0,0 +1,300 @@
+use crate::utils::glob;
+
+use anyhow::{anyhow, bail, Result};
+use chrono::{Local, NaiveDate, TimeZone};
+use regex::Regex;
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+const FILTER_PARAMS: [&str; 5] = ["name", "regex", "size", "mtime", "type"];
+
+/// Parsed search query, e.g. `name:*.mp4 size:>100M mtime:<7d type:file`.
+///
+/// Bare words are each matched as a case-insensitive substring of the name. Content
+/// search looks for them as one phrase, `text`. All terms must match. An `mtime`
+/// given as an age, such as `mtime:<7d`, compares how long ago the path was modified.
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    pub text: String,
+    words: Vec<String>,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug)]
+enum Filter {
+    Name(String),
+    Regex(Regex),
+    Size(Cmp, u64),
+    Age(Cmp, u64),
+    Mtime(Cmp, u64),
+    Type(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Cmp {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+}
+
+impl Cmp {
+    fn test(&self, left: u64, right: u64) -> bool {
+        match self {
+            Cmp::Lt => left < right,
+            Cmp::Le => left <= right,
+            Cmp::Gt => left > right,
+            Cmp::Ge => left >= right,
+            Cmp::Eq => left == right,
+        }
+    }
+}
+
+impl SearchQuery {
+    /// Parse the `q` query parameter, plus the equivalent `name`, `regex`, `size`,
+    /// `mtime` and `type` parameters.
+    pub fn from_params(query_params: &HashMap<String, String>) -> Result<Self> {
+        let mut query: Self = match query_params.get("q") {
+            Some(v) => v.parse()?,
+            None => Self::default(),
+        };
+        for key in FILTER_PARAMS {
+            if let Some(value) = query_params.get(key) {
+                query.filters.extend(parse_filter(key, value)?);
+            }
+        }
+        Ok(query)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.filters.is_empty()
+    }
+
+    /// Whether matching needs the size or mtime of the path.
+    pub fn need_metadata(&self) -> bool {
+        self.filters
+            .iter()
+            .any(|v| matches!(v, Filter::Size(..) | Filter::Age(..) | Filter::Mtime(..)))
+    }
+
+    /// Match a path by its base name. The text is not checked if `match_text` is false.
+    pub fn matches(
+        &self,
+        name: &str,
+        is_dir: bool,
+        size: u64,
+        mtime: u64,
+        match_text: bool,
+    ) -> bool {
+        if match_text {
+            let name = name.to_lowercase();
+            if !self.words.iter().all(|v| name.contains(v.as_str())) {
+                return false;
+            }
+        }
+        let now = to_timestamp(SystemTime::now());
+        self.filters.iter().all(|filter| match filter {
+            Filter::Name(pattern) => glob(pattern, &name.to_lowercase()),
+            Filter::Regex(re) => re.is_match(name),
+            Filter::Size(cmp, value) => !is_dir && cmp.test(size, *value),
+            Filter::Age(cmp, value) => cmp.test(now.saturating_sub(mtime), *value),
+            Filter::Mtime(cmp, value) => cmp.test(mtime, *value),
+            Filter::Type(v) => is_dir == *v,
+        })
+    }
+}
+
+impl std::str::FromStr for SearchQuery {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut words = vec![];
+        let mut filters = vec![];
+        for token in tokenize(s) {
+            let filter = match token.split_once(':') {
+                Some((key, value)) => parse_filter(key, value)?,
+                None => None,
+            };
+            match filter {
+                Some(filter) => filters.push(filter),
+                None => words.push(token.to_lowercase()),
+            }
+        }
+        Ok(Self {
+            text: words.join(" "),
+            words,
+            filters,
+        })
+    }
+}
+
+/// Parse a `key:value` term, `None` if the key is not a filter.
+fn parse_filter(key: &str, value: &str) -> Result<Option<Filter>> {
+    let filter = match key {
+        "name" => Filter::Name(value.to_lowercase()),
+        "regex" => {
+            Filter::Regex(Regex::new(value).map_err(|_| anyhow!("Invalid regex `{value}`"))?)
+        }
+        "size" => {
+            let (cmp, value) = parse_cmp(value);
+            Filter::Size(cmp, parse_size(value)?)
+        }
+        "mtime" => {
+            let (cmp, value) = parse_cmp(value);
+            match parse_duration(value) {
+                Some(age) => {
+                    Filter::Age(cmp, age.ok_or_else(|| anyhow!("Invalid mtime `{value}`"))?)
+                }
+                None => Filter::Mtime(cmp, parse_date(value)?),
+            }
+        }
+        "type" => match value {
+            "dir" | "d" => Filter::Type(true),
+            "file" | "f" => Filter::Type(false),
+            _ => bail!("Invalid type `{value}`"),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(filter))
+}
+
+fn tokenize(s: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    tokens
+}
+
+fn parse_cmp(value: &str) -> (Cmp, &str) {
+    for (prefix, cmp) in [
+        (">=", Cmp::Ge),
+        ("<=", Cmp::Le),
+        (">", Cmp::Gt),
+        ("<", Cmp::Lt),
+        ("=", Cmp::Eq),
+    ] {
+        if let Some(value) = value.strip_prefix(prefix) {
+            return (cmp, value);
+        }
+    }
+    (Cmp::Eq, value)
+}
+
+fn parse_size(value: &str) -> Result<u64> {
+    let err = || anyhow!("Invalid size `{value}`");
+    let upper = value.to_uppercase();
+    let digits = upper.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let unit = match upper[digits.len()..]
+        .trim_end_matches("IB")
+        .trim_end_matches('B')
+    {
+        "" => 1u64,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => return Err(err()),
+    };
+    let number: f64 = digits.parse().map_err(|_| err())?;
+    if number < 0.0 {
+        return Err(err());
+    }
+    Ok((number * unit as f64) as u64)
+}
+
+/// Parse an age like `30m` or `7d` into milliseconds. `None` if it isn't shaped like
+/// an age, `Some(None)` if it is too large.
+fn parse_duration(value: &str) -> Option<Option<u64>> {
+    let unit = match value.chars().last()? {
+        's' => 1000,
+        'm' => 60 * 1000,
+        'h' => 60 * 60 * 1000,
+        'd' => 24 * 60 * 60 * 1000,
+        'w' => 7 * 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    let number: u64 = value[..value.len() - 1].parse().ok()?;
+    Some(number.checked_mul(unit))
+}
+
+/// Parse a local date like `2024-01-31` into a timestamp in milliseconds.
+fn parse_date(value: &str) -> Result<u64> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| anyhow!("Invalid mtime `{value}`"))?;
+    let time = Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        .earliest()
+        .ok_or_else(|| anyhow!("Invalid mtime `{value}`"))?;
+    Ok(time.timestamp_millis().max(0) as u64)
+}
+
+fn to_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = 24 * 60 * 60 * 1000;
+
+    #[test]
+    fn test_search_query() {
+        let now = to_timestamp(SystemTime::now());
+        let query: SearchQuery = r#"name:*.MP4 size:>100M mtime:<7d type:file"#.parse().unwrap();
+        assert!(query.text.is_empty());
+        assert!(query.need_metadata());
+        assert!(query.matches("a.mp4", false, 200 << 20, now - DAY, true));
+        assert!(!query.matches("a.mp4", false, 200 << 20, now - 8 * DAY, true));
+        assert!(!query.matches("a.mp4", false, 1 << 20, now - DAY, true));
+        assert!(!query.matches("a.mkv", false, 200 << 20, now - DAY, true));
+        assert!(!query.matches("a.mp4", true, 200 << 20, now - DAY, true));
+
+        let query: SearchQuery = r#"my File regex:^\d+"#.parse().unwrap();
+        assert_eq!(query.text, "my file");
+        assert!(query.matches("2024 My File.txt", false, 0, 0, true));
+        assert!(!query.matches("My File.txt", false, 0, 0, true));
+        assert!(query.matches("2024.txt", false, 0, 0, false));
+
+        let query: SearchQuery = "foo bar".parse().unwrap();
+        assert!(query.matches("bar-2024-FOO.txt", false, 0, 0, true));
+        assert!(query.matches("foo bar.txt", false, 0, 0, true));
+        assert!(!query.matches("foo.txt", false, 0, 0, true));
+
+        let query: SearchQuery = r#"name:"a b*" mtime:>=2024-01-01 size:<=1.5k"#.parse().unwrap();
+        assert!(query.matches("a b.txt", false, 1536, now, true));
+        assert!(!query.matches("a b.txt", false, 1537, now, true));
+        assert!(!query.matches("a b.txt", false, 0, 0, true));
+
+        assert!("size:>100X".parse::<SearchQuery>().is_err());
+        assert!("type:link".parse::<SearchQuery>().is_err());
+        assert!("regex:(".parse::<SearchQuery>().is_err());
+        assert!("mtime:<99999999999999999d".parse::<SearchQuery>().is_err());
+
+        let params = HashMap::from([
+            ("q".to_string(), "foo".to_string()),
+            ("name".to_string(), r#"a" b*"#.to_string()),
+        ]);
+        let query = SearchQuery::from_params(&params).unwrap();
+        assert_eq!(query.text, "foo");
+        assert!(query.matches(r#"a" b.txt"#, false, 0, 0, false));
+        assert!(!query.matches("a b.txt", false, 0, 0, false));
+    }
+}
@@ -1,47 +1,74 @@
 #![allow(clippy::too_many_arguments)]
 
-use crate::auth::{www_authenticate, AccessPaths, AccessPerm};
-use crate::http_utils::{body_full, IncomingStream, LengthLimitedStream};
+#[cfg(feature = "tls")]
+use crate::acme::{Acme, HTTP_CHALLENGE_PREFIX};
+use crate::archive_reader::{
+    detect_archive, list_members, normalize_name, open_member, MemberData, MemberKind,
+};
+use crate::auth::{
+    get_auth_user, get_basic_credentials, get_bearer_token, get_cert_user, get_session,
+    www_authenticate, AccessCaps, AccessPaths, AccessPerm, CertUser, Identity,
+};
+use crate::extract::{Conflict, ExtractStatus, Extractor};
+use crate::http_utils::{body_full, IncomingBody, IncomingStream, LengthLimitedStream};
+use crate::ip_filter::{parse_cidrs, resolve_client_ip, ClientIp};
+use crate::journal::{ChangeJournal, ChangeKind};
+use crate::oidc::{Oidc, LOGIN_COOKIE, LOGIN_TTL, SESSION_COOKIE, SESSION_TTL};
+use crate::search_index::SearchIndex;
+use crate::search_query::SearchQuery;
+use crate::share::{Share, ShareLinks, SHARE_COOKIE};
+use crate::stored_gzip::StoredGzipEncoder;
+use crate::stored_zip::{CrcCache, StoredZip};
+use crate::throttle::{AuthFailure, LoginThrottle};
+use crate::token::TokenInfo;
 use crate::utils::{
     check_file_exist, create_html_file, decode_uri, encode_uri, gen_html_hls,
-    get_file_mtime_and_mode, get_file_name, get_path_from_url, glob, parse_range,
+    get_file_mtime_and_mode, get_file_name, get_path_from_url, glob, parse_range, shutting_down,
     try_get_file_name, download_segment, download_m3u8, write_m3u8, gen_html_no_poster
 };
-use crate::Args;
+use crate::watcher::FsWatcher;
+use crate::{args::Compress, Args};
 
 use anyhow::{anyhow, Result};
+use async_compression::tokio::write::{GzipEncoder, ZstdEncoder};
 use async_zip::{tokio::write::ZipFileWriter, Compression, ZipDateTime, ZipEntryBuilder};
+use base64::{engine::general_purpose, Engine as _};
 use bytes::Bytes;
 use chrono::{LocalResult, TimeZone, Utc};
 use futures_util::{pin_mut, TryStreamExt};
 use headers::{
     AcceptRanges, AccessControlAllowCredentials, AccessControlAllowOrigin, CacheControl,
-    ContentLength, ContentType, ETag, HeaderMap, HeaderMapExt, IfModifiedSince, IfNoneMatch,
-    IfRange, LastModified, Range,
+    ContentLength, ContentType, Cookie, ETag, HeaderMap, HeaderMapExt, IfModifiedSince,
+    IfNoneMatch, IfRange, LastModified, Range,
 };
-use http_body_util::{combinators::BoxBody, BodyExt, StreamBody};
-use hyper::body::Frame;
+use http_body_util::{combinators::BoxBody, BodyExt, Limited, StreamBody};
+use hyper::body::{Body, Frame};
 use hyper::{
-    body::Incoming,
     header::{
-        HeaderValue, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE,
-        CONTENT_TYPE, RANGE,
+        HeaderValue, ACCEPT, ALT_SVC, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_LENGTH,
+        CONTENT_RANGE, CONTENT_TYPE, HOST, LOCATION, RANGE, RETRY_AFTER, SET_COOKIE,
+        WWW_AUTHENTICATE,
     },
     Method, StatusCode, Uri,
 };
-use serde::Serialize;
+use indexmap::IndexSet;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::fs::Metadata;
-use std::io::SeekFrom;
-use std::net::SocketAddr;
+use std::io::{BufRead, SeekFrom};
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::atomic::{self, AtomicBool};
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::watch;
 use tokio::{fs, io};
 
 use tokio_util::compat::FuturesAsyncWriteCompatExt;
@@ -50,7 +77,7 @@ use uuid::Uuid;
 use walkdir::WalkDir;
 use xml::escape::escape_str_pcdata;
 
-pub type Request = hyper::Request<Incoming>;
+pub type Request = hyper::Request<IncomingBody>;
 pub type Response = hyper::Response<BoxBody<Bytes, anyhow::Error>>;
 
 const INDEX_HTML: &str = include_str!("../assets/index.html");
@@ -61,6 +88,18 @@ const INDEX_NAME: &str = "index.html";
 const BUF_SIZE: usize = 65536;
 const EDITABLE_TEXT_MAX_SIZE: u64 = 4194304; // 4M
 const RESUMABLE_UPLOAD_MIN_SIZE: u64 = 20971520; // 20M
+const REPORT_BODY_MAX_SIZE: usize = 65536;
+const ARCHIVE_BODY_MAX_SIZE: usize = 1048576; // 1M
+const TOKEN_BODY_MAX_SIZE: usize = 65536;
+const SHARE_DEFAULT_EXPIRES_IN: u64 = 86400; // 1 day
+const SHARE_MAX_EXPIRES_IN: u64 = 31536000; // 1 year
+const EVENTS_KEEPALIVE: Duration = Duration::from_secs(15);
+const CONTENT_SEARCH_MAX_SIZE: u64 = 4194304; // 4M
+const CONTENT_SEARCH_MAX_MATCHES: usize = 100;
+const CONTENT_SEARCH_MAX_LINE: usize = 256;
+const INDEX_PAGE_SIZE: usize = 1000;
+const LISTING_CACHE_CAPACITY: usize = 16;
+const LISTING_CACHE_TTL: Duration = Duration::from_secs(30);
 
 pub struct Server {
     args: Args,
@@ -68,10 +107,43 @@ pub struct Server {
     html: Cow<'static, str>,
     single_file_req_paths: Vec<String>,
     running: Arc<AtomicBool>,
+    /// Flips once the server stops accepting, event streams end then
+    shutdown: watch::Sender<bool>,
+    journal: Arc<ChangeJournal>,
+    search_index: Option<Arc<SearchIndex>>,
+    crc_cache: Arc<CrcCache>,
+    listing_cache: ListingCache,
+    shares: ShareLinks,
+    oidc: Option<Oidc>,
+    throttle: LoginThrottle,
+    trusted_proxies: Vec<IpNet>,
+    alt_svc: Option<HeaderValue>,
+    #[cfg(feature = "tls")]
+    acme: Option<Arc<Acme>>,
+    _watcher: Option<FsWatcher>,
+}
+
+/// What the listener knows about a connection.
+#[derive(Debug, Clone, Default)]
+pub struct ConnInfo {
+    pub addr: Option<SocketAddr>,
+    pub cert_names: Option<Arc<Vec<String>>>,
+    pub tls: bool,
+    pub http3: bool,
+    /// Accepted on the plain http port of ACME http-01 challenges
+    pub acme_http: bool,
 }
 
+/// Marks requests of the plain ACME http port, which only answers challenges.
+#[derive(Debug, Clone, Copy)]
+struct AcmeHttp;
+
 impl Server {
-    pub fn init(args: Args, running: Arc<AtomicBool>) -> Result<Self> {
+    pub fn init(
+        args: Args,
+        running: Arc<AtomicBool>,
+        shutdown: watch::Sender<bool>,
+    ) -> Result<Self> {
         let assets_prefix = format!("{}__dufs_v{}_", args.uri_prefix, env!("CARGO_PKG_VERSION"));
         let single_file_req_paths = if args.path_is_file {
             vec![
@@ -90,31 +162,111 @@ impl Server {
             Some(path) => Cow::Owned(std::fs::read_to_string(path.join("index.html"))?),
             None => Cow::Borrowed(INDEX_HTML),
         };
+        let journal = Arc::new(ChangeJournal::default());
+        let search_index = match &args.search_index {
+            Some(file) if !args.path_is_file => {
+                let index = Arc::new(SearchIndex::new(&args.serve_path, file));
+                index.clone().spawn(&journal, running.clone());
+                Some(index)
+            }
+            _ => None,
+        };
+        let watcher = if (args.watch || search_index.is_some()) && !args.path_is_file {
+            Some(FsWatcher::new(&args.serve_path, journal.clone())?)
+        } else {
+            None
+        };
+        let shares = ShareLinks::new(args.share_secret.as_deref());
+        let oidc = match (
+            &args.oidc_issuer,
+            &args.oidc_client_id,
+            args.auth.sessions(),
+        ) {
+            (Some(issuer), Some(client_id), Some(sessions)) => Some(Oidc::new(
+                issuer,
+                client_id,
+                args.oidc_client_secret.as_deref(),
+                &args.oidc_rules,
+                sessions.clone(),
+            )?),
+            _ => None,
+        };
+        let throttle = LoginThrottle::new(
+            args.auth_max_failures,
+            Duration::from_secs(args.auth_lockout),
+        );
+        let trusted_proxies = parse_cidrs(&args.trusted_proxies)?;
+        // Tells HTTP/1.1 and HTTP/2 clients about the QUIC listener on the same port
+        let alt_svc = match args.http3 {
+            true => Some(HeaderValue::from_str(&format!(
+                "h3=\":{}\"; ma=86400",
+                args.port
+            ))?),
+            false => None,
+        };
         Ok(Self {
             args,
             running,
+            shutdown,
             single_file_req_paths,
             assets_prefix,
             html,
+            journal,
+            search_index,
+            crc_cache: Default::default(),
+            listing_cache: Default::default(),
+            shares,
+            oidc,
+            throttle,
+            trusted_proxies,
+            alt_svc,
+            #[cfg(feature = "tls")]
+            acme: None,
+            _watcher: watcher,
         })
     }
 
-    pub async fn call(
+    #[cfg(feature = "tls")]
+    pub fn with_acme(mut self, acme: Option<Arc<Acme>>) -> Self {
+        self.acme = acme;
+        self
+    }
+
+    pub async fn call<B>(
         self: Arc<Self>,
-        req: Request,
-        addr: Option<SocketAddr>,
-    ) -> Result<Response, hyper::Error> {
+        req: hyper::Request<B>,
+        conn: ConnInfo,
+    ) -> Result<Response, hyper::Error>
+    where
+        B: Body<Data = Bytes> + Send + Sync + 'static,
+        B::Error: Into<anyhow::Error>,
+    {
+        let mut req = req.map(|v| v.map_err(Into::into).boxed());
         let uri = req.uri().clone();
         let assets_prefix = &self.assets_prefix;
         let enable_cors = self.args.enable_cors;
         let mut http_log_data = self.args.http_logger.data(&req);
-        if let Some(addr) = addr {
-            http_log_data.insert("remote_addr".to_string(), addr.ip().to_string());
+        if let Some(addr) = conn.addr {
+            let ip = resolve_client_ip(addr.ip(), req.headers(), &self.trusted_proxies);
+            http_log_data.insert("remote_addr".to_string(), ip.to_string());
+            req.extensions_mut().insert(ClientIp(ip));
+        }
+        if let Some(user) = conn.cert_names.and_then(|v| self.args.auth.cert_user(&v)) {
+            if !req.headers().contains_key(AUTHORIZATION) {
+                http_log_data.insert("remote_user".to_string(), user.clone());
+            }
+            req.extensions_mut().insert(CertUser(user));
+        }
+        if conn.acme_http {
+            req.extensions_mut().insert(AcmeHttp);
         }
 
         let mut res = match self.clone().handle(req).await {
             Ok(res) => {
                 http_log_data.insert("status".to_string(), res.status().as_u16().to_string());
+                if let Some(failure) = res.extensions().get::<AuthFailure>() {
+                    http_log_data.insert("auth_failure".to_string(), failure.as_str().to_string());
+                }
                 if !uri.path().starts_with(assets_prefix) {
                     self.args.http_logger.log(&http_log_data, None);
                 }
@@ -135,12 +287,31 @@ impl Server {
         if enable_cors {
             add_cors(&mut res);
         }
+        if let Some(alt_svc) = self.alt_svc.as_ref().filter(|_| conn.tls && !conn.http3) {
+            res.headers_mut().insert(ALT_SVC, alt_svc.clone());
+        }
         Ok(res)
     }
 
-    pub async fn handle(self: Arc<Self>, req: Request) -> Result<Response> {
+    pub async fn handle(self: Arc<Self>, mut req: Request) -> Result<Response> {
         let mut res = Response::default();
 
+        #[cfg(feature = "tls")]
+        if self.handle_acme(&req, &mut res)? {
+            return Ok(res);
+        }
+
+        let remote_ip = client_ip(&req);
+        if !self.args.auth.allows_ip(remote_ip) {
+            status_forbid(&mut res);
+            return Ok(res);
+        }
+
+        // A client certificate or a login session is checked like credentials everywhere
+        // the request is guarded
+        self.set_cert_authorization(&mut req);
+        self.set_session_authorization(&mut req);
+
         let req_path = req.uri().path();
         let headers = req.headers();
         let method = req.method().clone();
@@ -158,29 +329,129 @@ impl Server {
             }
         };
 
-        let guard = self.args.auth.guard(&relative_path, &method, authorization);
+        let query = req.uri().query().unwrap_or_default();
+        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        if self.oidc.is_some()
+            && method == Method::GET
+            && relative_path.is_empty()
+            && (query_params.contains_key("login") || query_params.contains_key("logout"))
+        {
+            self.handle_oidc(&query_params, headers, &mut res).await?;
+            return Ok(res);
+        }
+
+        // API tokens may also come as `?token=` on download links
+        let query_token = match authorization {
+            None if self.args.allow_query_token
+                && (method == Method::GET || method == Method::HEAD) =>
+            {
+                query_params
+                    .get("token")
+                    .and_then(|v| HeaderValue::from_str(&format!("Bearer {v}")).ok())
+            }
+            _ => None,
+        };
+        let authorization = authorization.or(query_token.as_ref());
+
+        // Certificates and sessions were checked already, other credentials count as login
+        // attempts
+        let login =
+            authorization.filter(|v| get_cert_user(v).is_none() && get_session(v).is_none());
+        let login_user = login.and_then(get_auth_user);
+        if login.is_some() {
+            if let Some(retry_after) = self.throttle.check(remote_ip, login_user.as_deref()) {
+                status_too_many_requests(&mut res, retry_after);
+                res.extensions_mut().insert(AuthFailure::Locked);
+                return Ok(res);
+            }
+        }
+
+        let share = self.resolve_share(&relative_path, &query_params, headers);
+        if share.is_none() && query_params.get("share").is_some_and(|v| !v.is_empty()) {
+            status_forbid(&mut res);
+            return Ok(res);
+        }
+
+        // The credentials are checked here only, other paths of the request are guarded
+        // with the identity
+        let identity = self.args.auth.authenticate(
+            &method,
+            authorization.filter(|_| !matches!(share, Some((_, true)))),
+        );
+
+        // Archiving selected paths only reads, each path is checked again later
+        let guard = if matches!(share, Some((_, true))) {
+            (None, None)
+        } else if method == Method::POST
+            && (is_archive_request(&query_params) || query_params.contains_key("share"))
+        {
+            self.args
+                .auth
+                .guard_read(&relative_path, &method, &identity, remote_ip)
+        } else {
+            self.args
+                .auth
+                .guard(&relative_path, &method, &identity, remote_ip)
+        };
+        if login.is_some() && !matches!(share, Some((_, true))) {
+            match guard {
+                (None, None) => {
+                    self.throttle.fail(remote_ip, login_user.as_deref());
+                    res.extensions_mut().insert(AuthFailure::Invalid);
+                }
+                (Some(_), _) => self.throttle.succeed(remote_ip, login_user.as_deref()),
+                _ => {}
+            }
+        }
 
-        let (user, access_paths) = match guard {
-            (None, None) => {
-                self.auth_reject(&mut res)?;
+        // A share link replaces the account check, a share cookie only when the account
+        // has no access
+        let (user, access_paths, shared) = match (share, guard) {
+            (Some((share, explicit)), guard) if explicit || guard.1.is_none() => {
+                if !self.check_share(&share, &method, &query_params, authorization, &mut res)? {
+                    return Ok(res);
+                }
+                let Some(access_paths) = share.access_paths(&relative_path) else {
+                    status_forbid(&mut res);
+                    return Ok(res);
+                };
+                (None, access_paths, Some((share, explicit)))
+            }
+            (_, (None, None)) => {
+                if self.oidc.is_some() && method == Method::GET && accepts_html(headers) {
+                    let next = req.uri().path_and_query().map(|v| v.as_str());
+                    self.redirect_login(next.unwrap_or(req_path), &mut res)?;
+                } else {
+                    self.auth_reject(&mut res)?;
+                }
                 return Ok(res);
             }
-            (Some(_), None) => {
+            (_, (Some(_), None)) => {
                 status_forbid(&mut res);
                 return Ok(res);
             }
-            (x, Some(y)) => (x, y),
+            (_, (x, Some(y))) => (x, y, None),
         };
 
-        let query = req.uri().query().unwrap_or_default();
-        let query_params: HashMap<String, String> = form_urlencoded::parse(query.as_bytes())
-            .map(|(k, v)| (k.to_string(), v.to_string()))
-            .collect();
-
         if method.as_str() == "WRITEABLE" {
             return Ok(res);
         }
 
+        if query_params.contains_key("tokens") && relative_path.is_empty() {
+            let is_admin = user.is_some()
+                && authorization.and_then(get_bearer_token).is_none()
+                && access_paths.perm().readwrite();
+            if !is_admin {
+                status_forbid(&mut res);
+            } else {
+                self.handle_tokens(&query_params, req, &mut res).await?;
+            }
+            return Ok(res);
+        }
+
         let head_only = method == Method::HEAD;
 
         if self.args.path_is_file {
@@ -211,10 +482,20 @@ impl Server {
             None => (true, false, false, 0),
         };
 
-        let allow_upload = self.args.allow_upload;
-        let allow_delete = self.args.allow_delete;
-        let allow_search = self.args.allow_search;
-        let allow_archive = self.args.allow_archive;
+        // The global flags turn operations off, the rules of the path grant them
+        let caps = access_paths.perm().caps();
+        let allow_read = caps.contains(AccessCaps::READ);
+        let allow_list = caps.contains(AccessCaps::LIST) || access_paths.perm().indexonly();
+        let allow_upload = self.args.allow_upload && caps.contains(AccessCaps::UPLOAD);
+        let allow_overwrite = self.args.allow_upload
+            && self.args.allow_delete
+            && caps.contains(AccessCaps::OVERWRITE);
+        let allow_delete = self.args.allow_delete && caps.contains(AccessCaps::DELETE);
+        let allow_rename =
+            self.args.allow_upload && self.args.allow_delete && caps.contains(AccessCaps::RENAME);
+        let allow_search = self.args.allow_search && caps.contains(AccessCaps::SEARCH);
+        let allow_archive = self.args.allow_archive && caps.contains(AccessCaps::ARCHIVE);
+        let allow_media = caps.contains(AccessCaps::MEDIA);
         let render_index = self.args.render_index;
         let render_spa = self.args.render_spa;
         let render_try_index = self.args.render_try_index;
@@ -224,17 +505,41 @@ impl Server {
             return Ok(res);
         }
 
+        if let Some((share, explicit)) = &shared {
+            if share.upload_only && method == Method::PUT && !is_miss {
+                status_forbid(&mut res);
+                return Ok(res);
+            }
+            if method == Method::GET
+                && (is_file || is_archive_request(&query_params))
+                && !is_resumed(headers)
+                && !self.shares.take_download(share)
+            {
+                *res.status_mut() = StatusCode::GONE;
+                *res.body_mut() = body_full("Download limit reached");
+                return Ok(res);
+            }
+            if *explicit && is_dir {
+                self.set_share_cookie(share, &query_params, &mut res)?;
+            }
+        }
+
         match method {
             Method::GET | Method::HEAD => {
-                if is_dir {
+                if (is_dir && !allow_list) || (is_file && !allow_read) {
+                    status_forbid(&mut res);
+                } else if is_dir {
                     if render_try_index {
-                        if allow_archive && query_params.contains_key("zip") {
-                            if !allow_archive {
-                                status_not_found(&mut res);
-                                return Ok(res);
-                            }
-                            self.handle_zip_dir(path, head_only, access_paths, &mut res)
-                                .await?;
+                        if allow_archive && is_archive_request(&query_params) {
+                            self.handle_archive_dir(
+                                path,
+                                &query_params,
+                                headers,
+                                head_only,
+                                access_paths,
+                                &mut res,
+                            )
+                            .await?;
                         } else if allow_search && query_params.contains_key("q") {
                             self.handle_search_dir(
                                 path,
@@ -245,6 +550,14 @@ impl Server {
                                 &mut res,
                             )
                             .await?;
+                        } else if query_params.contains_key("events") {
+                            self.clone()
+                                .handle_events(path, head_only, access_paths, &mut res)
+                                .await?;
+                        } else if query_params.contains_key("ndjson") {
+                            self.clone()
+                                .handle_ndjson_dir(path, head_only, access_paths, &mut res)
+                                .await?;
                         } else {
                             self.handle_render_index(
                                 path,
@@ -268,13 +581,20 @@ impl Server {
                             &mut res,
                         )
                         .await?;
-                    } else if query_params.contains_key("zip") {
+                    } else if is_archive_request(&query_params) {
                         if !allow_archive {
                             status_not_found(&mut res);
                             return Ok(res);
                         }
-                        self.handle_zip_dir(path, head_only, access_paths, &mut res)
-                            .await?;
+                        self.handle_archive_dir(
+                            path,
+                            &query_params,
+                            headers,
+                            head_only,
+                            access_paths,
+                            &mut res,
+                        )
+                        .await?;
                     } else if allow_search && query_params.contains_key("q") {
                         self.handle_search_dir(
                             path,
@@ -285,6 +605,14 @@ impl Server {
                             &mut res,
                         )
                         .await?;
+                    } else if query_params.contains_key("events") {
+                        self.clone()
+                            .handle_events(path, head_only, access_paths, &mut res)
+                            .await?;
+                    } else if query_params.contains_key("ndjson") {
+                        self.clone()
+                            .handle_ndjson_dir(path, head_only, access_paths, &mut res)
+                            .await?;
                     } else {
                         self.handle_ls_dir(
                             path,
@@ -299,10 +627,29 @@ impl Server {
                     }
                 } else if is_file {
                     if query_params.contains_key("edit") {
-                        self.handle_deal_file(path, DataKind::Edit, head_only, user, &mut res)
-                            .await?;
+                        self.handle_deal_file(
+                            path,
+                            DataKind::Edit,
+                            head_only,
+                            user,
+                            caps,
+                            &mut res,
+                        )
+                        .await?;
                     } else if query_params.contains_key("view") {
-                        self.handle_deal_file(path, DataKind::View, head_only, user, &mut res)
+                        self.handle_deal_file(
+                            path,
+                            DataKind::View,
+                            head_only,
+                            user,
+                            caps,
+                            &mut res,
+                        )
+                        .await?;
+                    } else if query_params.contains_key("list") {
+                        self.handle_list_archive(path, head_only, &mut res).await?;
+                    } else if let Some(name) = query_params.get("member") {
+                        self.handle_send_archive_member(path, name, headers, head_only, &mut res)
                             .await?;
                     } else {
                         self.handle_send_file(path, headers, head_only, &mut res)
@@ -330,7 +677,26 @@ impl Server {
                 set_webdav_headers(&mut res);
             }
             Method::PUT => {
-                if is_dir || !allow_upload || (!allow_delete && size > 0) {
+                if query_params.contains_key("extract") {
+                    if !allow_upload {
+                        status_forbid(&mut res);
+                    } else {
+                        // Extract into the requested directory, or next to the requested file
+                        let dir = match is_dir || req_path.ends_with('/') {
+                            true => path,
+                            false => path.parent().unwrap_or(path),
+                        };
+                        self.handle_extract(
+                            dir,
+                            allow_overwrite,
+                            &query_params,
+                            &identity,
+                            req,
+                            &mut res,
+                        )
+                        .await?;
+                    }
+                } else if is_dir || !allow_upload || (!allow_overwrite && size > 0) {
                     status_forbid(&mut res);
                 } else {
                     self.handle_upload(path, None, size, req, &mut res).await?;
@@ -339,7 +705,8 @@ impl Server {
             Method::PATCH => {
                 if is_miss {
                     status_not_found(&mut res);
-                } else if !allow_upload {
+                } else if !allow_upload || !caps.contains(AccessCaps::OVERWRITE) {
+                    // Appending changes an existing file too
                     status_forbid(&mut res);
                 } else {
                     let offset = match parse_upload_offset(headers, size) {
@@ -351,11 +718,12 @@ impl Server {
                     };
                     match offset {
                         Some(offset) => {
-                            if offset < size && !allow_delete {
+                            if offset < size && !allow_overwrite {
                                 status_forbid(&mut res);
+                            } else {
+                                self.handle_upload(path, Some(offset), size, req, &mut res)
+                                    .await?;
                             }
-                            self.handle_upload(path, Some(offset), size, req, &mut res)
-                                .await?;
                         }
                         None => {
                             *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
@@ -364,7 +732,8 @@ impl Server {
                 }
             }
             Method::DELETE => {
-                if !allow_delete {
+                // Denied paths inside a directory would go along with it
+                if !allow_delete || !access_paths.denied_paths().is_empty() {
                     status_forbid(&mut res);
                 } else if !is_miss {
                     self.handle_delete(path, is_dir, &mut res).await?
@@ -372,7 +741,58 @@ impl Server {
                     status_not_found(&mut res);
                 }
             }
+            Method::POST => {
+                if query_params.get("share").is_some_and(|v| v.is_empty()) {
+                    if is_miss {
+                        status_not_found(&mut res);
+                    } else if self.args.auth.exist() && user.is_none() {
+                        status_forbid(&mut res);
+                    } else {
+                        let writable = is_dir && allow_upload;
+                        self.handle_share(
+                            &relative_path,
+                            is_dir,
+                            writable,
+                            &access_paths,
+                            req,
+                            &mut res,
+                        )
+                        .await?;
+                    }
+                } else if !is_dir {
+                    status_not_found(&mut res);
+                } else if query_params.contains_key("extract") {
+                    if !allow_upload {
+                        status_forbid(&mut res);
+                    } else {
+                        self.handle_extract(
+                            path,
+                            allow_overwrite,
+                            &query_params,
+                            &identity,
+                            req,
+                            &mut res,
+                        )
+                        .await?;
+                    }
+                } else if !allow_archive || !is_archive_request(&query_params) {
+                    *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                } else {
+                    self.handle_archive_paths(
+                        path,
+                        &relative_path,
+                        &query_params,
+                        &identity,
+                        req,
+                        &mut res,
+                    )
+                    .await?;
+                }
+            }
             method => match method.as_str() {
+                "GETVIDEO" | "CUTVIDEO" if !allow_media => {
+                    status_forbid(&mut res);
+                }
                 "GETVIDEO" => {
                     let url = headers.get("video_url").unwrap().to_str().unwrap();
                     self.handle_get_video(url).await?;
@@ -402,7 +822,9 @@ impl Server {
                     }
                 }
                 "PROPFIND" => {
-                    if is_dir {
+                    if (is_dir && !allow_list) || (is_file && !allow_read) {
+                        status_forbid(&mut res);
+                    } else if is_dir {
                         let access_paths =
                             if access_paths.perm().indexonly() && authorization.is_none() {
                                 // see https://github.com/sigoden/dufs/issues/229
@@ -418,6 +840,16 @@ impl Server {
                         status_not_found(&mut res);
                     }
                 }
+                "REPORT" => {
+                    if is_dir && !allow_list {
+                        status_forbid(&mut res);
+                    } else if is_dir {
+                        self.handle_report(path, req, access_paths, &mut res)
+                            .await?;
+                    } else {
+                        status_not_found(&mut res);
+                    }
+                }
                 "PROPPATCH" => {
                     if is_file {
                         self.handle_proppatch(req_path, &mut res).await?;
@@ -436,21 +868,21 @@ impl Server {
                     }
                 }
                 "COPY" => {
-                    if !allow_upload {
+                    if !allow_read || !self.args.allow_upload {
                         status_forbid(&mut res);
                     } else if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_copy(path, &req, &mut res).await?
+                        self.handle_copy(path, &identity, &req, &mut res).await?
                     }
                 }
                 "MOVE" => {
-                    if !allow_upload || !allow_delete {
+                    if !allow_rename || !access_paths.denied_paths().is_empty() {
                         status_forbid(&mut res);
                     } else if is_miss {
                         status_not_found(&mut res);
                     } else {
-                        self.handle_move(path, &req, &mut res).await?
+                        self.handle_move(path, &identity, &req, &mut res).await?
                     }
                 }
                 "LOCK" => {
@@ -694,6 +1126,7 @@ impl Server {
         // let new_dir = self.create_dir().await?;
         let relative_path = self.get_path_file_type(path).await?;
         let path = relative_path.as_path();
+        let is_new = fs::symlink_metadata(path).await.is_err();
         ensure_path_parent(path).await?;
         let (mut file, status) = match upload_offset {
             None => (fs::File::create(path).await?, StatusCode::CREATED),
@@ -726,6 +1159,13 @@ impl Server {
             ret?;
         }
 
+        let kind = if is_new {
+            ChangeKind::Create
+        } else {
+            ChangeKind::Modify
+        };
+        self.record_change(kind, path);
+
         if path.extension().unwrap_or_default() == "mp4" {
             let parent = path.parent().unwrap_or(Path::new(""));
             self.generate_file(path, parent);
@@ -739,75 +1179,427 @@ impl Server {
         Ok(())
     }
 
-    async fn handle_delete(&self, path: &Path, is_dir: bool, res: &mut Response) -> Result<()> {
-        match is_dir {
-            true => fs::remove_dir_all(path).await?,
-            false => fs::remove_file(path).await?,
-        }
-
-        status_no_content(res);
-        Ok(())
-    }
-
-    async fn handle_ls_dir(
+    /// Extract the archive in the request body into `dir`, and report each entry as JSON.
+    ///
+    /// Every entry is checked for write permission, as if it had been uploaded directly.
+    async fn handle_extract(
         &self,
-        path: &Path,
-        exist: bool,
+        dir: &Path,
+        allow_overwrite: bool,
         query_params: &HashMap<String, String>,
-        head_only: bool,
-        user: Option<String>,
-        access_paths: AccessPaths,
+        identity: &Identity,
+        req: Request,
         res: &mut Response,
     ) -> Result<()> {
-        let mut paths = vec![];
-        if exist {
-            paths = match self.list_dir(path, path, access_paths.clone()).await {
-                Ok(paths) => paths,
-                Err(_) => {
-                    status_forbid(res);
+        let conflict = match query_params.get("conflict") {
+            Some(v) => match v.parse::<Conflict>() {
+                Ok(v) => v,
+                Err(err) => {
+                    status_bad_request(res, &err.to_string());
                     return Ok(());
                 }
+            },
+            None => Conflict::Skip,
+        };
+        if conflict == Conflict::Overwrite && !allow_overwrite {
+            status_forbid(res);
+            return Ok(());
+        }
+        let Some(base) = self.relative_path(dir) else {
+            status_forbid(res);
+            return Ok(());
+        };
+        if !self.args.allow_symlink
+            && fs::metadata(dir).await.is_ok()
+            && !self.is_root_contained(dir).await
+        {
+            status_not_found(res);
+            return Ok(());
+        }
+        let method = req.method().clone();
+        let remote_ip = client_ip(&req);
+
+        fs::create_dir_all(dir).await?;
+        // Buffered outside the served tree, so it is never listed, indexed or journaled
+        let archive_path =
+            std::env::temp_dir().join(format!("dufs-extract-{}", Uuid::new_v4().simple()));
+        let mut file = fs::File::create(&archive_path).await?;
+        let max_upload = self.args.extract_max_upload.unwrap_or(u64::MAX);
+        let stream = IncomingStream::new(req.into_body());
+        let body_with_io_error = stream.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
+        let body_reader = StreamReader::new(body_with_io_error).take(max_upload.saturating_add(1));
+        pin_mut!(body_reader);
+        let ret = io::copy(&mut body_reader, &mut file).await;
+        drop(file);
+        match ret {
+            Ok(size) if size > max_upload => {
+                let _ = fs::remove_file(&archive_path).await;
+                *res.status_mut() = StatusCode::PAYLOAD_TOO_LARGE;
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(err) => {
+                let _ = fs::remove_file(&archive_path).await;
+                return Err(err.into());
+            }
+        }
+
+        let allow = |name: &str, _is_dir: bool| {
+            let full_relative_path = match base.is_empty() {
+                true => name.to_string(),
+                false => format!("{base}/{name}"),
+            };
+            let guard = self
+                .args
+                .auth
+                .guard(&full_relative_path, &method, identity, remote_ip);
+            match guard {
+                (_, Some(paths)) => {
+                    let caps = paths.perm().caps();
+                    caps.contains(AccessCaps::UPLOAD)
+                        && (conflict != Conflict::Overwrite || caps.contains(AccessCaps::OVERWRITE))
+                }
+                _ => false,
             }
         };
-        self.send_index(
-            path,
-            paths,
-            exist,
-            query_params,
-            head_only,
-            user,
-            access_paths,
-            res,
-        )
+        let root = (!self.args.allow_symlink).then(|| self.args.serve_path.clone());
+        let ret = Extractor::new(dir, conflict, self.args.extract_max_size, root, allow)
+            .extract(&archive_path)
+            .await;
+        let _ = fs::remove_file(&archive_path).await;
+        let results = match ret {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+
+        for result in results.iter() {
+            let kind = match result.status {
+                ExtractStatus::Created | ExtractStatus::Renamed => ChangeKind::Create,
+                ExtractStatus::Overwritten => ChangeKind::Modify,
+                _ => continue,
+            };
+            if let Some(path) = &result.path {
+                self.record_change(kind, &dir.join(path));
+            }
+        }
+
+        let output = serde_json::to_string_pretty(&results)?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.as_bytes().len() as u64));
+        *res.body_mut() = body_full(output);
+        Ok(())
     }
 
-    async fn handle_search_dir(
+    async fn handle_tokens(
         &self,
-        path: &Path,
         query_params: &HashMap<String, String>,
-        head_only: bool,
-        user: Option<String>,
-        access_paths: AccessPaths,
+        req: Request,
         res: &mut Response,
     ) -> Result<()> {
-        let mut paths: Vec<PathItem> = vec![];
-        let search = query_params
-            .get("q")
-            .ok_or_else(|| anyhow!("invalid q"))?
-            .to_lowercase();
-        if search.is_empty() {
-            return self
-                .handle_ls_dir(path, true, query_params, head_only, user, access_paths, res)
-                .await;
-        } else {
-            let path_buf = path.to_path_buf();
-            let hidden = Arc::new(self.args.hidden.to_vec());
-            let hidden = hidden.clone();
-            let running = self.running.clone();
-            let access_paths = access_paths.clone();
-            let search_paths = tokio::task::spawn_blocking(move || {
-                let mut paths: Vec<PathBuf> = vec![];
-                for dir in access_paths.child_paths(&path_buf) {
+        let Some(tokens) = self.args.auth.tokens().cloned() else {
+            status_not_found(res);
+            return Ok(());
+        };
+        match req.method().clone() {
+            Method::GET => {
+                let output = serde_json::to_string_pretty(&tokens.list())?;
+                res.headers_mut()
+                    .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+                res.headers_mut()
+                    .typed_insert(ContentLength(output.as_bytes().len() as u64));
+                *res.body_mut() = body_full(output);
+            }
+            Method::POST => {
+                let body = match Limited::new(req.into_body(), TOKEN_BODY_MAX_SIZE)
+                    .collect()
+                    .await
+                {
+                    Ok(v) => v.to_bytes(),
+                    Err(_) => {
+                        status_bad_request(res, "Request body too large");
+                        return Ok(());
+                    }
+                };
+                let data: NewToken = match serde_json::from_slice(&body) {
+                    Ok(v) => v,
+                    Err(err) => {
+                        status_bad_request(res, &format!("Invalid token, {err}"));
+                        return Ok(());
+                    }
+                };
+                let ret = tokio::task::spawn_blocking(move || {
+                    tokens.create(&data.label, &data.paths, data.expires)
+                })
+                .await?;
+                let (token, info) = match ret {
+                    Ok(v) => v,
+                    Err(err) => {
+                        status_bad_request(res, &err.to_string());
+                        return Ok(());
+                    }
+                };
+                let output = serde_json::to_string_pretty(&CreatedToken { token, info })?;
+                *res.status_mut() = StatusCode::CREATED;
+                res.headers_mut()
+                    .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+                res.headers_mut()
+                    .typed_insert(ContentLength(output.as_bytes().len() as u64));
+                *res.body_mut() = body_full(output);
+            }
+            Method::DELETE => {
+                let id = query_params.get("id").cloned().unwrap_or_default();
+                match tokio::task::spawn_blocking(move || tokens.remove(&id)).await? {
+                    Ok(true) => status_no_content(res),
+                    Ok(false) => status_not_found(res),
+                    Err(err) => status_bad_request(res, &err.to_string()),
+                }
+            }
+            _ => {
+                *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_share(
+        &self,
+        relative_path: &str,
+        is_dir: bool,
+        writable: bool,
+        access_paths: &AccessPaths,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let body = match Limited::new(req.into_body(), TOKEN_BODY_MAX_SIZE)
+            .collect()
+            .await
+        {
+            Ok(v) => v.to_bytes(),
+            Err(_) => {
+                status_bad_request(res, "Request body too large");
+                return Ok(());
+            }
+        };
+        let data: NewShare = match body.is_empty() {
+            true => Default::default(),
+            false => match serde_json::from_slice(&body) {
+                Ok(v) => v,
+                Err(err) => {
+                    status_bad_request(res, &format!("Invalid share, {err}"));
+                    return Ok(());
+                }
+            },
+        };
+        // Index-only paths have no capabilities, so they can't be shared either
+        let allowed = match data.upload_only {
+            true => writable,
+            false => access_paths.perm().caps().contains(AccessCaps::READ),
+        };
+        if !allowed {
+            status_forbid(res);
+            return Ok(());
+        }
+        let expires_in = data.expires_in.unwrap_or(SHARE_DEFAULT_EXPIRES_IN);
+        if expires_in == 0 || expires_in > SHARE_MAX_EXPIRES_IN {
+            status_bad_request(res, "Invalid expires_in");
+            return Ok(());
+        }
+        let expires = Utc::now().timestamp() + expires_in as i64;
+        let token = self.shares.sign(
+            relative_path,
+            access_paths,
+            expires,
+            data.max_downloads,
+            data.password.as_deref().filter(|v| !v.is_empty()),
+            data.upload_only,
+        )?;
+        let slash = if is_dir && !relative_path.is_empty() {
+            "/"
+        } else {
+            ""
+        };
+        let output = serde_json::to_string_pretty(&CreatedShare {
+            url: format!(
+                "{}{}{slash}?share={token}",
+                self.args.uri_prefix,
+                encode_uri(relative_path)
+            ),
+            expires: Utc.timestamp_opt(expires, 0).single(),
+        })?;
+        *res.status_mut() = StatusCode::CREATED;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.as_bytes().len() as u64));
+        *res.body_mut() = body_full(output);
+        Ok(())
+    }
+
+    async fn handle_delete(&self, path: &Path, is_dir: bool, res: &mut Response) -> Result<()> {
+        match is_dir {
+            true => fs::remove_dir_all(path).await?,
+            false => fs::remove_file(path).await?,
+        }
+
+        self.record_change(ChangeKind::Delete, path);
+        status_no_content(res);
+        Ok(())
+    }
+
+    async fn handle_ls_dir(
+        &self,
+        path: &Path,
+        exist: bool,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        user: Option<String>,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let mut paths = vec![];
+        if exist {
+            let ret = match self.list_dir_page(path, query_params, &access_paths).await {
+                Some(ret) => ret,
+                None => self.list_dir(path, path, access_paths.clone()).await,
+            };
+            paths = match ret {
+                Ok(paths) => paths,
+                Err(_) => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            }
+        };
+        self.send_index(
+            path,
+            paths,
+            exist,
+            query_params,
+            head_only,
+            user,
+            access_paths,
+            res,
+        )
+    }
+
+    /// Stream the listing as newline-delimited JSON, unsorted and without buffering
+    /// the whole directory.
+    async fn handle_ndjson_dir(
+        self: Arc<Self>,
+        path: &Path,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let path = path.to_path_buf();
+        let (names, rd) = if access_paths.perm().indexonly() {
+            let names: Vec<String> = access_paths.child_names().into_iter().cloned().collect();
+            (names, None)
+        } else {
+            match fs::read_dir(&path).await {
+                Ok(rd) => (vec![], Some(rd)),
+                Err(_) => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            }
+        };
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/x-ndjson"),
+        );
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
+        if head_only {
+            return Ok(());
+        }
+        let stream = async_stream::stream! {
+            let mut entry_paths = names.iter().map(|v| path.join(v)).collect::<Vec<_>>().into_iter();
+            let mut rd = rd;
+            loop {
+                if !self.running.load(atomic::Ordering::SeqCst) {
+                    break;
+                }
+                let entry_path = match entry_paths.next() {
+                    Some(v) => v,
+                    None => match rd.as_mut() {
+                        Some(rd) => match rd.next_entry().await {
+                            Ok(Some(entry)) => entry.path(),
+                            _ => break,
+                        },
+                        None => break,
+                    },
+                };
+                if access_paths.denied(entry_path.strip_prefix(&path).unwrap_or(&entry_path)) {
+                    continue;
+                }
+                if let Some(item) = self.to_visible_pathitem(&path, &entry_path).await {
+                    match serde_json::to_string(&item) {
+                        Ok(line) => yield Ok(Bytes::from(format!("{line}\n"))),
+                        Err(err) => yield Err(anyhow!("{err}")),
+                    }
+                }
+            }
+        };
+        let stream_body = StreamBody::new(stream.map_ok(Frame::data));
+        *res.body_mut() = stream_body.boxed();
+        Ok(())
+    }
+
+    async fn handle_search_dir(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        head_only: bool,
+        user: Option<String>,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let mut paths: Vec<PathItem> = vec![];
+        let query = match SearchQuery::from_params(query_params) {
+            Ok(v) => Arc::new(v),
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+        if query.is_empty() {
+            return self
+                .handle_ls_dir(path, true, query_params, head_only, user, access_paths, res)
+                .await;
+        } else {
+            let content = !query.text.is_empty()
+                && query_params
+                    .get("content")
+                    .map(|v| v != "0" && v != "false")
+                    .unwrap_or_default();
+            if !content {
+                if let Some(items) = self.search_by_index(path, &query, &access_paths).await? {
+                    return self.send_index(
+                        path,
+                        items,
+                        true,
+                        query_params,
+                        head_only,
+                        user,
+                        access_paths,
+                        res,
+                    );
+                }
+            }
+            let path_buf = path.to_path_buf();
+            let hidden = Arc::new(self.args.hidden.to_vec());
+            let hidden = hidden.clone();
+            let running = self.running.clone();
+            let access_paths = access_paths.clone();
+            let search_paths = tokio::task::spawn_blocking(move || {
+                let mut paths: Vec<(PathBuf, Option<Vec<ContentMatch>>)> = vec![];
+                for dir in access_paths.child_paths(&path_buf) {
                     let mut it = WalkDir::new(&dir).into_iter();
                     it.next();
                     while let Some(Ok(entry)) = it.next() {
@@ -828,23 +1620,49 @@ impl Server {
                                 }
                             }
                         }
-                        if is_hidden(&hidden, base_name, is_dir_type) {
+                        if is_hidden(&hidden, base_name, is_dir_type)
+                            || access_paths
+                                .denied(entry_path.strip_prefix(&path_buf).unwrap_or(entry_path))
+                        {
                             if file_type.is_dir() {
                                 it.skip_current_dir();
                             }
                             continue;
                         }
-                        if !base_name.to_lowercase().contains(&search) {
+                        let (size, mtime) = match query.need_metadata() {
+                            true => match std::fs::metadata(entry_path) {
+                                Ok(meta) => (
+                                    meta.len(),
+                                    meta.modified()
+                                        .map(|v| to_timestamp(&v))
+                                        .unwrap_or_default(),
+                                ),
+                                Err(_) => continue,
+                            },
+                            false => (0, 0),
+                        };
+                        if !query.matches(base_name, is_dir_type, size, mtime, !content) {
+                            continue;
+                        }
+                        if content {
+                            if is_dir_type {
+                                continue;
+                            }
+                            if let Some(matches) = search_file_content(entry_path, &query.text) {
+                                paths.push((entry_path.to_path_buf(), Some(matches)));
+                            }
                             continue;
                         }
-                        paths.push(entry_path.to_path_buf());
+                        paths.push((entry_path.to_path_buf(), None));
                     }
                 }
                 paths
             })
             .await?;
-            for search_path in search_paths.into_iter() {
-                if let Ok(Some(item)) = self.to_pathitem(search_path, path.to_path_buf()).await {
+            for (search_path, matches) in search_paths.into_iter() {
+                if let Ok(Some(mut item)) = self.to_pathitem(search_path, path.to_path_buf()).await
+                {
+                    item.matches = matches;
                     paths.push(item);
                 }
             }
@@ -861,48 +1679,411 @@ impl Server {
         )
     }
 
-    async fn handle_zip_dir(
+    /// Search names with the search index, or return `None` if it can't serve the request.
+    async fn search_by_index(
+        &self,
+        path: &Path,
+        query: &Arc<SearchQuery>,
+        access_paths: &AccessPaths,
+    ) -> Result<Option<Vec<PathItem>>> {
+        let (Some(index), Some(base)) = (self.search_index.clone(), self.relative_path(path))
+        else {
+            return Ok(None);
+        };
+        let query = query.clone();
+        let hidden = self.args.hidden.clone();
+        let access_paths = access_paths.clone();
+        let entries = tokio::task::spawn_blocking(move || {
+            let entries = index.search(&base, |name, entry| {
+                query.matches(name, entry.is_dir, entry.size, entry.mtime, true)
+            })?;
+            let entries: Vec<_> = entries
+                .into_iter()
+                .filter(|(name, entry)| {
+                    access_paths
+                        .find(name, false)
+                        .map(|v| !v.perm().indexonly())
+                        .unwrap_or_default()
+                        && !is_hidden_path(&hidden, name, entry.is_dir)
+                })
+                .collect();
+            Some(entries)
+        })
+        .await?;
+        let Some(entries) = entries else {
+            return Ok(None);
+        };
+        let mut paths = vec![];
+        for (name, entry) in entries {
+            if entry.is_symlink {
+                // Leave the symlink policy to `to_pathitem`
+                if let Ok(Some(item)) = self.to_pathitem(path.join(&name), path.to_path_buf()).await
+                {
+                    paths.push(item);
+                }
+                continue;
+            }
+            let (path_type, size) = if entry.is_dir {
+                (PathType::Dir, None)
+            } else {
+                (PathType::File, Some(entry.size))
+            };
+            paths.push(PathItem {
+                path_type,
+                name,
+                mtime: entry.mtime,
+                size,
+                matches: None,
+            });
+        }
+        Ok(Some(paths))
+    }
+
+    async fn handle_archive_dir(
         &self,
         path: &Path,
+        query_params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
         head_only: bool,
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        let (mut writer, reader) = tokio::io::duplex(BUF_SIZE);
+        let Some(format) = parse_archive_format(query_params, res) else {
+            return Ok(());
+        };
         let filename = try_get_file_name(path)?;
-        set_content_disposition(res, false, &format!("{}.zip", filename))?;
+        let sources = vec![ArchiveSource {
+            name: String::new(),
+            path: path.to_path_buf(),
+            access_paths,
+        }];
+        if format == ArchiveFormat::Zip
+            && (self.args.compress == Compress::None || query_params.contains_key("stored"))
+        {
+            return self
+                .send_stored_zip(filename, sources, headers, head_only, res)
+                .await;
+        }
+        self.send_archive(filename, format, sources, head_only, res)
+    }
+
+    /// Archive the paths listed in the JSON array of the request body, relative to `path`.
+    ///
+    /// Every path is checked on its own, as if it had been requested directly.
+    async fn handle_archive_paths(
+        &self,
+        path: &Path,
+        relative_path: &str,
+        query_params: &HashMap<String, String>,
+        identity: &Identity,
+        req: Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(format) = parse_archive_format(query_params, res) else {
+            return Ok(());
+        };
+        let method = req.method().clone();
+        let remote_ip = client_ip(&req);
+        let body = match Limited::new(req.into_body(), ARCHIVE_BODY_MAX_SIZE)
+            .collect()
+            .await
+        {
+            Ok(v) => v.to_bytes(),
+            Err(_) => {
+                status_bad_request(res, "Request body too large");
+                return Ok(());
+            }
+        };
+        let names: Vec<String> = match serde_json::from_slice(&body) {
+            Ok(v) => v,
+            Err(_) => {
+                status_bad_request(res, "Invalid paths, expect a JSON array of strings");
+                return Ok(());
+            }
+        };
+        if names.is_empty() {
+            status_bad_request(res, "No paths");
+            return Ok(());
+        }
+        let mut sources = vec![];
+        for name in names {
+            let name = name.trim_matches('/');
+            if name.is_empty()
+                || name
+                    .split('/')
+                    .any(|v| v.is_empty() || v == "." || v == "..")
+            {
+                status_bad_request(res, &format!("Invalid path `{name}`"));
+                return Ok(());
+            }
+            let full_relative_path = match relative_path.is_empty() {
+                true => name.to_string(),
+                false => format!("{relative_path}/{name}"),
+            };
+            let guard =
+                self.args
+                    .auth
+                    .guard_read(&full_relative_path, &method, identity, remote_ip);
+            let access_paths = match guard {
+                (_, Some(v)) => v,
+                _ => {
+                    status_forbid(res);
+                    return Ok(());
+                }
+            };
+            let Some(entry_path) = self.join_path(&full_relative_path) else {
+                status_forbid(res);
+                return Ok(());
+            };
+            let Ok(meta) = fs::metadata(&entry_path).await else {
+                status_not_found(res);
+                return Ok(());
+            };
+            if is_hidden_path(&self.args.hidden, name, meta.is_dir())
+                || (!self.args.allow_symlink && !self.is_root_contained(&entry_path).await)
+            {
+                status_not_found(res);
+                return Ok(());
+            }
+            sources.push(ArchiveSource {
+                name: name.to_string(),
+                path: entry_path,
+                access_paths,
+            });
+        }
+        let filename = try_get_file_name(path)?;
+        self.send_archive(filename, format, sources, false, res)
+    }
+
+    /// Send a zip without compression, with its size, an etag and range support.
+    async fn send_stored_zip(
+        &self,
+        filename: &str,
+        sources: Vec<ArchiveSource>,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let entries =
+            archive_entries(sources, &self.args.hidden, self.running.clone(), false).await?;
+        let zip = Arc::new(StoredZip::new(entries).await?);
+        let size = zip.size();
+        let etag = zip.etag().parse::<ETag>()?;
+        if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            if !if_none_match.precondition_passes(&etag) {
+                *res.status_mut() = StatusCode::NOT_MODIFIED;
+                return Ok(());
+            }
+        }
+        set_content_disposition(res, false, &format!("{filename}.zip"))?;
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static(ArchiveFormat::Zip.content_type()),
+        );
+        res.headers_mut().typed_insert(etag.clone());
+        res.headers_mut().typed_insert(AcceptRanges::bytes());
+
+        let use_range = headers
+            .typed_get::<IfRange>()
+            .map(|if_range| !if_range.is_modified(Some(&etag), None))
+            .unwrap_or(true);
+        let range = match headers.get(RANGE) {
+            Some(range) if use_range => {
+                Some(range.to_str().ok().and_then(|v| parse_range(v, size)))
+            }
+            _ => None,
+        };
+        let (start, end) = match range {
+            Some(Some((start, end))) => {
+                *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                res.headers_mut().insert(
+                    CONTENT_RANGE,
+                    format!("bytes {start}-{end}/{size}").parse()?,
+                );
+                (start, end)
+            }
+            Some(None) => {
+                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                res.headers_mut()
+                    .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                return Ok(());
+            }
+            None => (0, size - 1),
+        };
+        res.headers_mut()
+            .insert(CONTENT_LENGTH, format!("{}", end - start + 1).parse()?);
+        if head_only {
+            return Ok(());
+        }
+        let stream = zip.stream(start, end, self.crc_cache.clone());
+        *res.body_mut() = StreamBody::new(stream.map_ok(Frame::data)).boxed();
+        Ok(())
+    }
+
+    fn send_archive(
+        &self,
+        filename: &str,
+        format: ArchiveFormat,
+        sources: Vec<ArchiveSource>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let (writer, reader) = tokio::io::duplex(BUF_SIZE);
+        set_content_disposition(res, false, &format!("{}.{}", filename, format.extension()))?;
+        res.headers_mut().insert(
+            "content-type",
+            HeaderValue::from_static(format.content_type()),
+        );
+        if head_only {
+            return Ok(());
+        }
+        let filename = filename.to_string();
+        let hidden = self.args.hidden.clone();
+        let running = self.running.clone();
+        let compress = self.args.compress;
+        let follow_symlinks = self.args.allow_symlink;
+        tokio::spawn(async move {
+            let all = format != ArchiveFormat::Zip;
+            let ret = match archive_entries(sources, &hidden, running.clone(), all).await {
+                Ok(entries) => match format {
+                    ArchiveFormat::Zip => {
+                        zip_entries(writer, entries, compress.to_compression()).await
+                    }
+                    ArchiveFormat::Tar => {
+                        tar_entries(writer, entries, follow_symlinks, running).await
+                    }
+                    ArchiveFormat::TarGz => match compress.to_gzip_level() {
+                        Some(level) => {
+                            let writer = GzipEncoder::with_quality(writer, level);
+                            tar_entries(writer, entries, follow_symlinks, running).await
+                        }
+                        None => {
+                            let writer = StoredGzipEncoder::new(writer);
+                            tar_entries(writer, entries, follow_symlinks, running).await
+                        }
+                    },
+                    ArchiveFormat::TarZst => {
+                        let writer = ZstdEncoder::with_quality(writer, compress.to_zstd_level());
+                        tar_entries(writer, entries, follow_symlinks, running).await
+                    }
+                },
+                Err(err) => Err(err),
+            };
+            if let Err(e) = ret {
+                error!("Failed to archive {}, {}", filename, e);
+            }
+        });
+        let reader_stream = ReaderStream::new(reader);
+        let stream_body = StreamBody::new(
+            reader_stream
+                .map_ok(Frame::data)
+                .map_err(|err| anyhow!("{err}")),
+        );
+        let boxed_body = stream_body.boxed();
+        *res.body_mut() = boxed_body;
+        Ok(())
+    }
+
+    async fn handle_events(
+        self: Arc<Self>,
+        path: &Path,
+        head_only: bool,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
         res.headers_mut()
-            .insert("content-type", HeaderValue::from_static("application/zip"));
+            .insert(CONTENT_TYPE, HeaderValue::from_static("text/event-stream"));
+        res.headers_mut()
+            .typed_insert(CacheControl::new().with_no_cache());
         if head_only {
             return Ok(());
         }
-        let path = path.to_owned();
-        let hidden = self.args.hidden.clone();
-        let running = self.running.clone();
-        let compression = self.args.compress.to_compression();
-        tokio::spawn(async move {
-            if let Err(e) = zip_dir(
-                &mut writer,
-                &path,
-                access_paths,
-                &hidden,
-                compression,
-                running,
-            )
+        let path = path.to_path_buf();
+        let base = self.relative_path(&path).unwrap_or_default();
+        let mut receiver = self.journal.subscribe();
+        // Unlike downloads, the stream never finishes on its own, so it doesn't wait
+        // for the drain
+        let mut shutdown = self.shutdown.subscribe();
+        let stream = async_stream::stream! {
+            let mut keepalive = tokio::time::interval(EVENTS_KEEPALIVE);
+            loop {
+                let change = tokio::select! {
+                    ret = receiver.recv() => match ret {
+                        Ok(change) => change,
+                        Err(RecvError::Lagged(_)) => {
+                            yield Ok(Bytes::from_static(b"event: reset\ndata: {}\n\n"));
+                            continue;
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = keepalive.tick() => {
+                        yield Ok(Bytes::from_static(b": ping\n\n"));
+                        continue;
+                    }
+                    _ = shutting_down(&mut shutdown) => break,
+                };
+                let mut events = vec![];
+                if let ChangeKind::Move(from) = &change.kind {
+                    events.push(("delete", from.as_str()));
+                    events.push(("create", change.path.as_str()));
+                } else {
+                    let kind = match change.kind {
+                        ChangeKind::Create => "create",
+                        ChangeKind::Modify => "modify",
+                        _ => "delete",
+                    };
+                    events.push((kind, change.path.as_str()));
+                }
+                for (kind, name) in events {
+                    if let Some(event) = self.to_change_event(&path, &base, &access_paths, kind, name).await {
+                        match serde_json::to_string(&event) {
+                            Ok(data) => yield Ok(Bytes::from(format!("event: change\ndata: {data}\n\n"))),
+                            Err(err) => yield Err(anyhow!("{err}")),
+                        }
+                    }
+                }
+            }
+        };
+        let stream_body = StreamBody::new(stream.map_ok(Frame::data));
+        *res.body_mut() = stream_body.boxed();
+        Ok(())
+    }
+
+    async fn to_change_event(
+        &self,
+        path: &Path,
+        base: &str,
+        access_paths: &AccessPaths,
+        kind: &'static str,
+        name: &str,
+    ) -> Option<ChangeEvent> {
+        if !is_sync_member(base, name, true) {
+            return None;
+        }
+        let subpath = name.strip_prefix(base)?.trim_start_matches('/');
+        access_paths.find(subpath, false)?;
+        if kind == "delete" {
+            if is_hidden_path(&self.args.hidden, subpath, false) {
+                return None;
+            }
+            return Some(ChangeEvent {
+                kind,
+                name: subpath.to_string(),
+                item: None,
+            });
+        }
+        let item = self
+            .to_pathitem(self.join_path(name)?.as_path(), path)
             .await
-            {
-                error!("Failed to zip {}, {}", path.display(), e);
-            }
-        });
-        let reader_stream = ReaderStream::new(reader);
-        let stream_body = StreamBody::new(
-            reader_stream
-                .map_ok(Frame::data)
-                .map_err(|err| anyhow!("{err}")),
-        );
-        let boxed_body = stream_body.boxed();
-        *res.body_mut() = boxed_body;
-        Ok(())
+            .ok()??;
+        if is_hidden_path(&self.args.hidden, subpath, item.is_dir()) {
+            return None;
+        }
+        Some(ChangeEvent {
+            kind,
+            name: subpath.to_string(),
+            item: Some(item),
+        })
     }
 
     async fn handle_render_index(
@@ -1107,12 +2288,189 @@ impl Server {
         Ok(())
     }
 
+    /// List the members of an archive file as JSON, names are full paths in the archive.
+    async fn handle_list_archive(
+        &self,
+        path: &Path,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(kind) = detect_archive(path).await? else {
+            status_bad_request(res, "Not an archive");
+            return Ok(());
+        };
+        let members = match list_members(path, kind).await {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+        let mut paths: Vec<PathItem> = members
+            .into_iter()
+            .map(|member| {
+                let (path_type, size) = match member.kind {
+                    MemberKind::Dir => (PathType::Dir, None),
+                    MemberKind::File => (PathType::File, Some(member.size)),
+                    MemberKind::Other => (PathType::SymlinkFile, Some(member.size)),
+                };
+                PathItem {
+                    path_type,
+                    name: member.name,
+                    mtime: member.mtime,
+                    size,
+                    matches: None,
+                }
+            })
+            .collect();
+        paths.sort_by(|v1, v2| v1.sort_by_name(v2));
+        let output = serde_json::to_string_pretty(&paths)?;
+        res.headers_mut()
+            .typed_insert(ContentType::from(mime_guess::mime::APPLICATION_JSON));
+        res.headers_mut()
+            .typed_insert(ContentLength(output.as_bytes().len() as u64));
+        if head_only {
+            return Ok(());
+        }
+        *res.body_mut() = body_full(output);
+        Ok(())
+    }
+
+    /// Send a member of an archive file, with range support if it's stored uncompressed.
+    async fn handle_send_archive_member(
+        &self,
+        path: &Path,
+        name: &str,
+        headers: &HeaderMap<HeaderValue>,
+        head_only: bool,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(kind) = detect_archive(path).await? else {
+            status_bad_request(res, "Not an archive");
+            return Ok(());
+        };
+        let Some(name) = normalize_name(name) else {
+            status_not_found(res);
+            return Ok(());
+        };
+        let member = match open_member(path, kind, &name).await {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                status_not_found(res);
+                return Ok(());
+            }
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+        let (reader, size, raw_offset): (Pin<Box<dyn AsyncRead + Send>>, u64, Option<u64>) =
+            match member {
+                MemberData::Raw { offset, size } => {
+                    let mut file = fs::File::open(path).await?;
+                    file.seek(SeekFrom::Start(offset)).await?;
+                    (Box::pin(file.take(size)), size, Some(offset))
+                }
+                MemberData::Stream { reader, size } => (reader, size, None),
+            };
+
+        // The member changes only with the archive
+        let mtime = fs::metadata(path).await?.modified()?;
+        let digest = md5::compute(format!("{}-{}-{name}", to_timestamp(&mtime), size));
+        let etag = format!(r#""{digest:x}""#).parse::<ETag>()?;
+        let last_modified = LastModified::from(mtime);
+        let cached = if let Some(if_none_match) = headers.typed_get::<IfNoneMatch>() {
+            !if_none_match.precondition_passes(&etag)
+        } else if let Some(if_modified_since) = headers.typed_get::<IfModifiedSince>() {
+            !if_modified_since.is_modified(last_modified.into())
+        } else {
+            false
+        };
+        if cached {
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(());
+        }
+        res.headers_mut().typed_insert(last_modified);
+        res.headers_mut().typed_insert(etag.clone());
+
+        let mut reader = reader;
+        let mut buffer: Vec<u8> = vec![];
+        (&mut reader).take(1024).read_to_end(&mut buffer).await?;
+        let member_name = Path::new(&name);
+        res.headers_mut().insert(
+            CONTENT_TYPE,
+            HeaderValue::from_str(&detect_content_type(member_name, &buffer))?,
+        );
+        set_content_disposition(res, true, get_file_name(member_name))?;
+
+        let range = match raw_offset {
+            Some(_) => {
+                res.headers_mut().typed_insert(AcceptRanges::bytes());
+                let use_range = headers
+                    .typed_get::<IfRange>()
+                    .map(|if_range| !if_range.is_modified(Some(&etag), Some(&last_modified)))
+                    .unwrap_or(true);
+                match headers.get(RANGE) {
+                    Some(range) if use_range => {
+                        Some(range.to_str().ok().and_then(|v| parse_range(v, size)))
+                    }
+                    _ => None,
+                }
+            }
+            None => None,
+        };
+        let (start, length) = match range {
+            Some(Some((start, end))) => {
+                *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                res.headers_mut().insert(
+                    CONTENT_RANGE,
+                    format!("bytes {start}-{end}/{size}").parse()?,
+                );
+                (start, end - start + 1)
+            }
+            Some(None) => {
+                *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+                res.headers_mut()
+                    .insert(CONTENT_RANGE, format!("bytes */{size}").parse()?);
+                return Ok(());
+            }
+            None => (0, size),
+        };
+        res.headers_mut()
+            .insert(CONTENT_LENGTH, format!("{length}").parse()?);
+        if head_only {
+            return Ok(());
+        }
+        let reader: Pin<Box<dyn AsyncRead + Send>> = match raw_offset {
+            Some(offset) if start > 0 => {
+                let mut file = fs::File::open(path).await?;
+                file.seek(SeekFrom::Start(offset + start)).await?;
+                Box::pin(file)
+            }
+            _ => Box::pin(std::io::Cursor::new(buffer).chain(reader)),
+        };
+        let (mut writer, duplex_reader) = tokio::io::duplex(BUF_SIZE);
+        tokio::spawn(async move {
+            if let Err(e) = io::copy(&mut reader.take(length), &mut writer).await {
+                error!("Failed to send member {}, {}", name, e);
+            }
+        });
+        let stream_body = StreamBody::new(
+            ReaderStream::new(duplex_reader)
+                .map_ok(Frame::data)
+                .map_err(|err| anyhow!("{err}")),
+        );
+        *res.body_mut() = stream_body.boxed();
+        Ok(())
+    }
+
     async fn handle_deal_file(
         &self,
         path: &Path,
         kind: DataKind,
         head_only: bool,
         user: Option<String>,
+        caps: AccessCaps,
         res: &mut Response,
     ) -> Result<()> {
         let (file, meta) = tokio::join!(fs::File::open(path), fs::metadata(path),);
@@ -1129,9 +2487,13 @@ impl Server {
             href,
             kind,
             uri_prefix: self.args.uri_prefix.clone(),
-            allow_upload: self.args.allow_upload,
-            allow_delete: self.args.allow_delete,
+            // Saving an edit overwrites the file
+            allow_upload: self.args.allow_upload
+                && self.args.allow_delete
+                && caps.contains(AccessCaps::OVERWRITE),
+            allow_delete: self.args.allow_delete && caps.contains(AccessCaps::DELETE),
             auth: self.args.auth.exist(),
+            oidc: self.oidc.is_some(),
             domain: self.args.domain.clone(),
             user,
             editable,
@@ -1206,6 +2568,7 @@ impl Server {
 
     async fn handle_mkcol(&self, path: &Path, res: &mut Response) -> Result<()> {
         fs::create_dir_all(path).await?;
+        self.record_change(ChangeKind::Create, path);
         *res.status_mut() = StatusCode::CREATED;
         Ok(())
     }
@@ -1232,8 +2595,14 @@ impl Server {
         Ok(folder_path_clone)
     }
 
-    async fn handle_copy(&self, path: &Path, req: &Request, res: &mut Response) -> Result<()> {
-        let dest = match self.extract_dest(req, res) {
+    async fn handle_copy(
+        &self,
+        path: &Path,
+        identity: &Identity,
+        req: &Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let (dest, dest_caps) = match self.extract_dest(identity, req, res) {
             Some(dest) => dest,
             None => {
                 return Ok(());
@@ -1246,26 +2615,51 @@ impl Server {
             return Ok(());
         }
 
-        ensure_path_parent(&dest).await?;
+        let kind = if fs::symlink_metadata(&dest).await.is_ok() {
+            ChangeKind::Modify
+        } else {
+            ChangeKind::Create
+        };
+        if kind == ChangeKind::Modify && !dest_caps.contains(AccessCaps::OVERWRITE) {
+            status_forbid(res);
+            return Ok(());
+        }
 
+        ensure_path_parent(&dest).await?;
         fs::copy(path, &dest).await?;
+        self.record_change(kind, &dest);
 
         status_no_content(res);
         Ok(())
     }
 
-    async fn handle_move(&self, path: &Path, req: &Request, res: &mut Response) -> Result<()> {
-        let dest = match self.extract_dest(req, res) {
+    async fn handle_move(
+        &self,
+        path: &Path,
+        identity: &Identity,
+        req: &Request,
+        res: &mut Response,
+    ) -> Result<()> {
+        let (dest, dest_caps) = match self.extract_dest(identity, req, res) {
             Some(dest) => dest,
             None => {
                 return Ok(());
             }
         };
 
+        if fs::symlink_metadata(&dest).await.is_ok() && !dest_caps.contains(AccessCaps::OVERWRITE) {
+            status_forbid(res);
+            return Ok(());
+        }
+
         ensure_path_parent(&dest).await?;
 
         fs::rename(path, &dest).await?;
 
+        if let Some(from) = self.relative_path(path) {
+            self.record_change(ChangeKind::Move(from), &dest);
+        }
+
         status_no_content(res);
         Ok(())
     }
@@ -1309,6 +2703,158 @@ impl Server {
         Ok(())
     }
 
+    async fn handle_report(
+        &self,
+        path: &Path,
+        req: Request,
+        access_paths: AccessPaths,
+        res: &mut Response,
+    ) -> Result<()> {
+        let body = match Limited::new(req.into_body(), REPORT_BODY_MAX_SIZE)
+            .collect()
+            .await
+        {
+            Ok(v) => v.to_bytes(),
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+        let report = match parse_sync_collection(&body) {
+            Ok(Some(v)) => v,
+            Ok(None) => {
+                res_dav_error(res, StatusCode::FORBIDDEN, "supported-report");
+                return Ok(());
+            }
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
+            }
+        };
+        let base = self.relative_path(path).unwrap_or_default();
+        let mut output = String::new();
+        let token = match report.sync_token.as_deref() {
+            None => {
+                let token = self.journal.token();
+                let paths = if report.infinite {
+                    self.walk_dir(path, access_paths).await?
+                } else {
+                    match self
+                        .list_dir(path, &self.args.serve_path, access_paths)
+                        .await
+                    {
+                        Ok(v) => v,
+                        Err(_) => {
+                            status_forbid(res);
+                            return Ok(());
+                        }
+                    }
+                };
+                if report.limit.map(|v| paths.len() > v).unwrap_or_default() {
+                    res_dav_error(
+                        res,
+                        StatusCode::INSUFFICIENT_STORAGE,
+                        "number-of-matches-within-limits",
+                    );
+                    return Ok(());
+                }
+                for item in paths.iter() {
+                    output.push_str(&item.to_dav_xml(self.args.uri_prefix.as_str()));
+                }
+                token
+            }
+            Some(token) => {
+                let changes = match self.journal.since(token) {
+                    Some(v) => v,
+                    None => {
+                        res_dav_error(res, StatusCode::FORBIDDEN, "valid-sync-token");
+                        return Ok(());
+                    }
+                };
+                let mut token = token.to_string();
+                let mut names: IndexSet<String> = IndexSet::new();
+                let mut truncated = false;
+                for change in changes.iter() {
+                    let mut change_names = vec![change.path.as_str()];
+                    if let ChangeKind::Move(from) = &change.kind {
+                        change_names.insert(0, from.as_str());
+                    }
+                    let change_names: Vec<&str> = change_names
+                        .into_iter()
+                        .filter(|v| is_sync_member(&base, v, report.infinite))
+                        .collect();
+                    let added = change_names.iter().filter(|v| !names.contains(**v)).count();
+                    if report
+                        .limit
+                        .map(|v| names.len() + added > v)
+                        .unwrap_or_default()
+                    {
+                        truncated = true;
+                        break;
+                    }
+                    for name in change_names {
+                        names.shift_remove(name);
+                        names.insert(name.to_string());
+                    }
+                    token = self.journal.to_token(change.seq);
+                }
+                for name in names.iter() {
+                    let subpath = name
+                        .strip_prefix(&base)
+                        .unwrap_or(name)
+                        .trim_start_matches('/');
+                    if access_paths.find(subpath, false).is_none() {
+                        continue;
+                    }
+                    let member_path = match self.join_path(name) {
+                        Some(v) => v,
+                        None => continue,
+                    };
+                    match self
+                        .to_pathitem(member_path.as_path(), &self.args.serve_path)
+                        .await
+                    {
+                        Ok(Some(item)) => {
+                            if is_hidden_path(&self.args.hidden, subpath, item.is_dir()) {
+                                continue;
+                            }
+                            output.push_str(&item.to_dav_xml(self.args.uri_prefix.as_str()));
+                        }
+                        Ok(None) => {}
+                        Err(_) => {
+                            if is_hidden_path(&self.args.hidden, subpath, false) {
+                                continue;
+                            }
+                            let href = encode_uri(&format!("{}{}", self.args.uri_prefix, name));
+                            output.push_str(&format!(
+                                r#"<D:response>
+<D:href>{href}</D:href>
+<D:status>HTTP/1.1 404 Not Found</D:status>
+</D:response>"#
+                            ));
+                        }
+                    }
+                }
+                if truncated {
+                    let mut href = encode_uri(&format!("{}{}", self.args.uri_prefix, base));
+                    if !href.ends_with('/') {
+                        href.push('/');
+                    }
+                    output.push_str(&format!(
+                        r#"<D:response>
+<D:href>{href}</D:href>
+<D:status>HTTP/1.1 507 Insufficient Storage</D:status>
+</D:response>"#
+                    ));
+                }
+                token
+            }
+        };
+        output.push_str(&format!("<D:sync-token>{token}</D:sync-token>"));
+        res_multistatus(res, &output);
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn send_index(
         &self,
@@ -1321,23 +2867,18 @@ impl Server {
         access_paths: AccessPaths,
         res: &mut Response,
     ) -> Result<()> {
-        if let Some(sort) = query_params.get("sort") {
-            if sort == "name" {
-                paths.sort_by(|v1, v2| v1.sort_by_name(v2))
-            } else if sort == "mtime" {
-                paths.sort_by(|v1, v2| v1.sort_by_mtime(v2))
-            } else if sort == "size" {
-                paths.sort_by(|v1, v2| v1.sort_by_size(v2))
-            }
-            if query_params
-                .get("order")
-                .map(|v| v == "desc")
-                .unwrap_or_default()
-            {
-                paths.reverse()
+        let compare = list_order(query_params);
+        paths.sort_by(&compare);
+        let next_cursor = match paginate(&mut paths, query_params, &compare) {
+            Ok(v) => v,
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                return Ok(());
             }
-        } else {
-            paths.sort_by(|v1, v2| v1.sort_by_name(v2))
+        };
+        if let Some(cursor) = &next_cursor {
+            res.headers_mut()
+                .insert("x-next-cursor", HeaderValue::from_str(cursor)?);
         }
         if query_params.contains_key("simple") {
             let output = paths
@@ -1365,19 +2906,21 @@ impl Server {
             "/{}",
             normalize_path(path.strip_prefix(&self.args.serve_path)?)
         );
-        let readwrite = access_paths.perm().readwrite();
+        let caps = access_paths.perm().caps();
         let data = IndexData {
             kind: DataKind::Index,
             href,
             uri_prefix: self.args.uri_prefix.clone(),
-            allow_upload: self.args.allow_upload && readwrite,
-            allow_delete: self.args.allow_delete && readwrite,
-            allow_search: self.args.allow_search,
-            allow_archive: self.args.allow_archive,
+            allow_upload: self.args.allow_upload && caps.contains(AccessCaps::UPLOAD),
+            allow_delete: self.args.allow_delete && caps.contains(AccessCaps::DELETE),
+            allow_search: self.args.allow_search && caps.contains(AccessCaps::SEARCH),
+            allow_archive: self.args.allow_archive && caps.contains(AccessCaps::ARCHIVE),
             dir_exists: exist,
             auth: self.args.auth.exist(),
+            oidc: self.oidc.is_some(),
             user,
             paths,
+            next_cursor,
             domain: self.args.domain.clone(),
         };
         let output = if query_params.contains_key("json") {
@@ -1402,14 +2945,267 @@ impl Server {
         if head_only {
             return Ok(());
         }
-        *res.body_mut() = body_full(output);
-        Ok(())
+        *res.body_mut() = body_full(output);
+        Ok(())
+    }
+
+    /// Find the share link of the request, from `?share=` or else from the share cookie.
+    fn resolve_share(
+        &self,
+        relative_path: &str,
+        query_params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
+    ) -> Option<(Share, bool)> {
+        let (token, explicit) = match query_params.get("share").filter(|v| !v.is_empty()) {
+            Some(v) => (v.to_string(), true),
+            None => (
+                headers
+                    .typed_get::<Cookie>()?
+                    .get(SHARE_COOKIE)?
+                    .to_string(),
+                false,
+            ),
+        };
+        let share = self.shares.verify(&token)?;
+        share.contains(relative_path).then_some((share, explicit))
+    }
+
+    /// Check the method and the password of a share link request.
+    fn check_share(
+        &self,
+        share: &Share,
+        method: &Method,
+        query_params: &HashMap<String, String>,
+        authorization: Option<&HeaderValue>,
+        res: &mut Response,
+    ) -> Result<bool> {
+        let allowed = if share.upload_only {
+            (method == Method::PUT && !query_params.contains_key("extract"))
+                || method == Method::OPTIONS
+        } else {
+            method == Method::GET
+                || method == Method::HEAD
+                || method == Method::OPTIONS
+                || method.as_str() == "PROPFIND"
+        };
+        if !allowed {
+            status_forbid(res);
+            return Ok(false);
+        }
+        if share.has_password() {
+            let password = authorization
+                .and_then(get_basic_credentials)
+                .map(|(_, pass)| pass)
+                .unwrap_or_default();
+            if !self.shares.check_password(share, &password) {
+                let basic = HeaderValue::from_static("Basic realm=\"DUFS share\"");
+                res.headers_mut().insert(WWW_AUTHENTICATE, basic);
+                *res.status_mut() = StatusCode::UNAUTHORIZED;
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Keep a directory share for the pages and files inside it.
+    fn set_share_cookie(
+        &self,
+        share: &Share,
+        query_params: &HashMap<String, String>,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(token) = query_params.get("share") else {
+            return Ok(());
+        };
+        let max_age = share.expires - Utc::now().timestamp();
+        let path = format!("{}{}", self.args.uri_prefix, encode_uri(&share.path));
+        self.set_cookie(SHARE_COOKIE, token, &path, max_age, res)
+    }
+
+    fn set_cookie(
+        &self,
+        name: &str,
+        value: &str,
+        path: &str,
+        max_age: i64,
+        res: &mut Response,
+    ) -> Result<()> {
+        let secure = if self.args.tls() { "; Secure" } else { "" };
+        let cookie = format!(
+            "{name}={value}; Path={path}; Max-Age={max_age}; HttpOnly; SameSite=Lax{secure}"
+        );
+        res.headers_mut()
+            .append(SET_COOKIE, HeaderValue::from_str(&cookie)?);
+        Ok(())
+    }
+
+    /// Answer ACME http-01 challenges, and send everything else on the plain ACME port
+    /// over to https.
+    #[cfg(feature = "tls")]
+    fn handle_acme(&self, req: &Request, res: &mut Response) -> Result<bool> {
+        let Some(acme) = &self.acme else {
+            return Ok(false);
+        };
+        let req_path = req.uri().path();
+        if let Some(token) = req_path.strip_prefix(HTTP_CHALLENGE_PREFIX) {
+            match acme.http_challenge(token) {
+                Some(key_authorization) => {
+                    res.headers_mut()
+                        .typed_insert(ContentType::from(mime_guess::mime::TEXT_PLAIN));
+                    *res.body_mut() = body_full(key_authorization);
+                }
+                None => status_not_found(res),
+            }
+            return Ok(true);
+        }
+        if req.extensions().get::<AcmeHttp>().is_none() {
+            return Ok(false);
+        }
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<hyper::http::uri::Authority>().ok());
+        let Some(host) = host else {
+            status_not_found(res);
+            return Ok(true);
+        };
+        let port = match self.args.port {
+            443 => String::new(),
+            port => format!(":{port}"),
+        };
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map(|v| v.as_str())
+            .unwrap_or("/");
+        let location = format!("https://{}{port}{path_and_query}", host.host());
+        status_redirect(res, &location)?;
+        Ok(true)
+    }
+
+    /// Only the certificate the client was verified with logs in as `Certificate`.
+    fn set_cert_authorization(&self, req: &mut Request) {
+        if req
+            .headers()
+            .get(AUTHORIZATION)
+            .is_some_and(|v| get_cert_user(v).is_some())
+        {
+            req.headers_mut().remove(AUTHORIZATION);
+        }
+        if req.headers().contains_key(AUTHORIZATION) {
+            return;
+        }
+        let value = req
+            .extensions()
+            .get::<CertUser>()
+            .and_then(|v| HeaderValue::from_str(&format!("Certificate {}", v.0)).ok());
+        if let Some(value) = value {
+            req.headers_mut().insert(AUTHORIZATION, value);
+        }
+    }
+
+    /// Pass a valid session cookie on as `Authorization: Session <cookie>`, so the
+    /// per-path checks made deeper in the handlers see it too.
+    fn set_session_authorization(&self, req: &mut Request) {
+        let Some(sessions) = self.args.auth.sessions() else {
+            return;
+        };
+        if req.headers().contains_key(AUTHORIZATION) {
+            return;
+        }
+        let value = req
+            .headers()
+            .typed_get::<Cookie>()
+            .and_then(|v| v.get(SESSION_COOKIE).map(|v| v.to_string()))
+            .filter(|v| sessions.verify(v).is_some())
+            .and_then(|v| HeaderValue::from_str(&format!("Session {v}")).ok());
+        if let Some(value) = value {
+            req.headers_mut().insert(AUTHORIZATION, value);
+        }
+    }
+
+    /// Handle `?login`, the callback of the provider to `?login&code=`, and `?logout`.
+    async fn handle_oidc(
+        &self,
+        query_params: &HashMap<String, String>,
+        headers: &HeaderMap<HeaderValue>,
+        res: &mut Response,
+    ) -> Result<()> {
+        let Some(oidc) = &self.oidc else {
+            status_not_found(res);
+            return Ok(());
+        };
+        let uri_prefix = &self.args.uri_prefix;
+        let redirect_url = match &self.args.oidc_redirect_url {
+            Some(v) => v.clone(),
+            None => {
+                let scheme = if self.args.tls() { "https" } else { "http" };
+                let host = headers
+                    .get(HOST)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("localhost");
+                format!("{scheme}://{host}{uri_prefix}?login")
+            }
+        };
+        if query_params.contains_key("logout") {
+            self.set_cookie(SESSION_COOKIE, "", uri_prefix, 0, res)?;
+            let home = redirect_url.split('?').next().unwrap_or_default();
+            let location = match oidc.logout_url(home).await {
+                Some(v) => v,
+                None => uri_prefix.clone(),
+            };
+            return status_redirect(res, &location);
+        }
+        if let Some(error) = query_params.get("error") {
+            *res.status_mut() = StatusCode::FORBIDDEN;
+            *res.body_mut() = body_full(format!("Login failed, {error}"));
+            return Ok(());
+        }
+        if let (Some(code), Some(state)) = (query_params.get("code"), query_params.get("state")) {
+            let login = headers
+                .typed_get::<Cookie>()
+                .and_then(|v| v.get(LOGIN_COOKIE).map(|v| v.to_string()));
+            match oidc
+                .finish(&redirect_url, code, state, login.as_deref())
+                .await
+            {
+                Ok((session, next)) => {
+                    self.set_cookie(LOGIN_COOKIE, "", uri_prefix, 0, res)?;
+                    self.set_cookie(SESSION_COOKIE, &session, uri_prefix, SESSION_TTL, res)?;
+                    status_redirect(res, &next)?;
+                }
+                Err(err) => {
+                    *res.status_mut() = StatusCode::FORBIDDEN;
+                    *res.body_mut() = body_full(format!("Login failed, {err}"));
+                }
+            }
+            return Ok(());
+        }
+        // Only go back to paths of this server after the login
+        let next = query_params
+            .get("next")
+            .filter(|v| v.starts_with(uri_prefix.as_str()) && !v.starts_with("//"))
+            .filter(|v| !v.contains('\\'))
+            .unwrap_or(uri_prefix);
+        let (location, login) = oidc.start(&redirect_url, next).await?;
+        self.set_cookie(LOGIN_COOKIE, &login, uri_prefix, LOGIN_TTL, res)?;
+        status_redirect(res, &location)
+    }
+
+    fn redirect_login(&self, next: &str, res: &mut Response) -> Result<()> {
+        let next: String = form_urlencoded::byte_serialize(next.as_bytes()).collect();
+        let location = format!("{}?login&next={next}", self.args.uri_prefix);
+        status_redirect(res, &location)
     }
 
     fn auth_reject(&self, res: &mut Response) -> Result<()> {
         set_webdav_headers(res);
 
-        www_authenticate(res, &self.args)?;
+        // Without passwords to ask for, users log in through the login page
+        if self.oidc.is_none() || self.args.auth.has_passwords() {
+            www_authenticate(res, &self.args)?;
+        }
         *res.status_mut() = StatusCode::UNAUTHORIZED;
         Ok(())
     }
@@ -1422,7 +3218,13 @@ impl Server {
             .unwrap_or_default()
     }
 
-    fn extract_dest(&self, req: &Request, res: &mut Response) -> Option<PathBuf> {
+    /// The destination of a COPY or MOVE, with the capabilities on it.
+    fn extract_dest(
+        &self,
+        identity: &Identity,
+        req: &Request,
+        res: &mut Response,
+    ) -> Option<(PathBuf, AccessCaps)> {
         let headers = req.headers();
         let dest_path = match self.extract_destination_header(headers) {
             Some(dest) => dest,
@@ -1440,14 +3242,20 @@ impl Server {
             }
         };
 
-        let authorization = headers.get(AUTHORIZATION);
+        let remote_ip = client_ip(req);
         let guard = self
             .args
             .auth
-            .guard(&relative_path, req.method(), authorization);
+            .guard(&relative_path, req.method(), identity, remote_ip);
 
-        match guard {
-            (_, Some(_)) => {}
+        // Nothing may be moved or copied into denied paths
+        let caps = match guard {
+            (_, Some(paths))
+                if paths.perm().caps().contains(AccessCaps::UPLOAD)
+                    && paths.denied_paths().is_empty() =>
+            {
+                paths.perm().caps()
+            }
             _ => {
                 status_forbid(res);
                 return None;
@@ -1462,7 +3270,7 @@ impl Server {
             }
         };
 
-        Some(dest)
+        Some((dest, caps))
     }
 
     fn extract_destination_header(&self, headers: &HeaderMap<HeaderValue>) -> Option<String> {
@@ -1482,6 +3290,18 @@ impl Server {
             .map(|v| v.trim_matches('/').to_string())
     }
 
+    fn relative_path(&self, path: &Path) -> Option<String> {
+        path.strip_prefix(&self.args.serve_path)
+            .ok()
+            .map(normalize_path)
+    }
+
+    fn record_change(&self, kind: ChangeKind, path: &Path) {
+        if let Some(name) = self.relative_path(path) {
+            self.journal.record(kind, &name);
+        }
+    }
+
     fn join_path(&self, path: &str) -> Option<PathBuf> {
         if path.is_empty() {
             return Some(self.args.serve_path.clone());
@@ -1509,6 +3329,9 @@ impl Server {
         } else {
             let mut rd = fs::read_dir(entry_path).await?;
             while let Ok(Some(entry)) = rd.next_entry().await {
+                if access_paths.denied(Path::new(&entry.file_name())) {
+                    continue;
+                }
                 let entry_path = entry.path();
                 self.add_pathitem(&mut paths, base_path, &entry_path).await;
             }
@@ -1516,16 +3339,116 @@ impl Server {
         Ok(paths)
     }
 
-    async fn add_pathitem(&self, paths: &mut Vec<PathItem>, base_path: &Path, entry_path: &Path) {
-        let base_name = get_file_name(entry_path);
-        if let Ok(Some(item)) = self.to_pathitem(entry_path, base_path).await {
-            if is_hidden(&self.args.hidden, base_name, item.is_dir()) {
-                return;
+    /// List one page of a directory from its sorted listing in the listing cache, plus
+    /// one path more, so `send_index` still finds the next cursor. `None` if the request
+    /// is not paged or the directory is index-only.
+    async fn list_dir_page(
+        &self,
+        path: &Path,
+        query_params: &HashMap<String, String>,
+        access_paths: &AccessPaths,
+    ) -> Option<Result<Vec<PathItem>>> {
+        if access_paths.perm().indexonly() {
+            return None;
+        }
+        let limit = page_limit(query_params).ok()??;
+        let cursor = decode_cursor(query_params).ok()?;
+        let compare = list_order(query_params);
+        let mtime = match fs::metadata(path).await.and_then(|v| v.modified()) {
+            Ok(v) => v,
+            Err(err) => return Some(Err(err.into())),
+        };
+        let key = (path.to_path_buf(), list_order_key(query_params));
+        let paths = match self.listing_cache.get(&key, mtime) {
+            Some(paths) => paths,
+            None => {
+                // Cached for every user, so deny rules are applied per page below
+                let all_paths = AccessPaths::new(access_paths.perm());
+                let mut paths = match self.list_dir(path, path, all_paths).await {
+                    Ok(v) => v,
+                    Err(err) => return Some(Err(err)),
+                };
+                paths.sort_by(&compare);
+                let paths = Arc::new(paths);
+                if paths.len() > limit {
+                    self.listing_cache.insert(key, mtime, paths.clone());
+                }
+                paths
+            }
+        };
+        let start = match &cursor {
+            Some(cursor) => paths.partition_point(|v| compare(v, cursor) != Ordering::Greater),
+            None => 0,
+        };
+        let page = paths[start..]
+            .iter()
+            .filter(|v| !access_paths.denied(Path::new(&v.name)))
+            .take(limit + 1)
+            .cloned()
+            .collect();
+        Some(Ok(page))
+    }
+
+    async fn walk_dir(&self, path: &Path, access_paths: AccessPaths) -> Result<Vec<PathItem>> {
+        let path_buf = path.to_path_buf();
+        let hidden = self.args.hidden.clone();
+        let running = self.running.clone();
+        let walk_paths = tokio::task::spawn_blocking(move || {
+            let mut paths: Vec<PathBuf> = vec![];
+            for dir in access_paths.child_paths(&path_buf) {
+                let mut it = WalkDir::new(&dir).into_iter();
+                if dir == path_buf {
+                    it.next();
+                }
+                while let Some(Ok(entry)) = it.next() {
+                    if !running.load(atomic::Ordering::SeqCst) {
+                        break;
+                    }
+                    let entry_path = entry.path();
+                    let base_name = get_file_name(entry_path);
+                    let file_type = entry.file_type();
+                    if is_hidden(&hidden, base_name, file_type.is_dir())
+                        || access_paths
+                            .denied(entry_path.strip_prefix(&path_buf).unwrap_or(entry_path))
+                    {
+                        if file_type.is_dir() {
+                            it.skip_current_dir();
+                        }
+                        continue;
+                    }
+                    paths.push(entry_path.to_path_buf());
+                }
+            }
+            paths
+        })
+        .await?;
+        let mut paths = vec![];
+        for walk_path in walk_paths.into_iter() {
+            if let Ok(Some(item)) = self
+                .to_pathitem(walk_path.as_path(), &self.args.serve_path)
+                .await
+            {
+                paths.push(item);
             }
+        }
+        Ok(paths)
+    }
+
+    async fn add_pathitem(&self, paths: &mut Vec<PathItem>, base_path: &Path, entry_path: &Path) {
+        if let Some(item) = self.to_visible_pathitem(base_path, entry_path).await {
             paths.push(item);
         }
     }
 
+    async fn to_visible_pathitem(&self, base_path: &Path, entry_path: &Path) -> Option<PathItem> {
+        let base_name = get_file_name(entry_path);
+        let item = self.to_pathitem(entry_path, base_path).await.ok()??;
+        if is_hidden(&self.args.hidden, base_name, item.is_dir()) {
+            return None;
+        }
+        Some(item)
+    }
+
     async fn to_pathitem<P: AsRef<Path>>(&self, path: P, base_path: P) -> Result<Option<PathItem>> {
         let path = path.as_ref();
         let (meta, meta2) = tokio::join!(fs::metadata(&path), fs::symlink_metadata(&path));
@@ -1553,6 +3476,7 @@ impl Server {
             name,
             mtime,
             size,
+            matches: None,
         }))
     }
 }
@@ -1575,11 +3499,21 @@ struct IndexData {
     allow_archive: bool,
     dir_exists: bool,
     auth: bool,
+    oidc: bool,
     user: Option<String>,
     paths: Vec<PathItem>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
     domain: String,
 }
 
+#[derive(Debug, Serialize)]
+struct ChangeEvent {
+    kind: &'static str,
+    name: String,
+    item: Option<PathItem>,
+}
+
 #[derive(Debug, Serialize)]
 struct EditData {
     href: String,
@@ -1588,17 +3522,60 @@ struct EditData {
     allow_upload: bool,
     allow_delete: bool,
     auth: bool,
+    oidc: bool,
     user: Option<String>,
     editable: bool,
     domain: String,
 }
 
-#[derive(Debug, Serialize, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Debug, Default, Deserialize)]
+struct NewShare {
+    expires_in: Option<u64>,
+    max_downloads: Option<u64>,
+    password: Option<String>,
+    #[serde(default)]
+    upload_only: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedShare {
+    url: String,
+    expires: Option<chrono::DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NewToken {
+    label: String,
+    #[serde(default = "default_token_paths")]
+    paths: String,
+    expires: Option<chrono::DateTime<Utc>>,
+}
+
+fn default_token_paths() -> String {
+    "/".to_string()
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedToken {
+    token: String,
+    #[serde(flatten)]
+    info: TokenInfo,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
 struct PathItem {
     path_type: PathType,
     name: String,
     mtime: u64,
     size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    matches: Option<Vec<ContentMatch>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd)]
+struct ContentMatch {
+    line: usize,
+    text: String,
 }
 
 impl PathItem {
@@ -1688,7 +3665,7 @@ impl PathItem {
     }
 }
 
-#[derive(Debug, Serialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
 enum PathType {
     Dir,
     SymlinkDir,
@@ -1771,19 +3748,115 @@ fn res_multistatus(res: &mut Response, content: &str) {
     ));
 }
 
-async fn zip_dir<W: AsyncWrite + Unpin>(
-    writer: &mut W,
+async fn zip_entries<W: AsyncWrite + Unpin>(
+    writer: W,
+    entries: Vec<(String, PathBuf)>,
+    compression: Compression,
+) -> Result<()> {
+    let mut writer = ZipFileWriter::with_tokio(writer);
+    for (filename, zip_path) in entries.into_iter() {
+        let (datetime, mode) = get_file_mtime_and_mode(&zip_path).await?;
+        let builder = ZipEntryBuilder::new(filename.into(), compression)
+            .unix_permissions(mode)
+            .last_modification_date(ZipDateTime::from_chrono(&datetime));
+        let mut file = File::open(&zip_path).await?;
+        let mut file_writer = writer.write_entry_stream(builder).await?.compat_write();
+        io::copy(&mut file, &mut file_writer).await?;
+        file_writer.into_inner().close().await?;
+    }
+    writer.close().await?;
+    Ok(())
+}
+
+/// Write a tar archive. Symlinks are stored as links unless `follow_symlinks` is on.
+async fn tar_entries<W: AsyncWrite + Unpin + Send + 'static>(
+    writer: W,
+    entries: Vec<(String, PathBuf)>,
+    follow_symlinks: bool,
+    running: Arc<AtomicBool>,
+) -> Result<()> {
+    let mut builder = tokio_tar::Builder::new(writer);
+    builder.follow_symlinks(follow_symlinks);
+    for (name, tar_path) in entries.into_iter() {
+        if !running.load(atomic::Ordering::SeqCst) {
+            break;
+        }
+        if follow_symlinks && fs::metadata(&tar_path).await.is_err() {
+            warn!("Skipped broken symlink {}", tar_path.display());
+            continue;
+        }
+        builder.append_path_with_name(&tar_path, name).await?;
+    }
+    let mut writer = builder.into_inner().await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+/// A path to put into an archive, with its name inside the archive and its access paths.
+struct ArchiveSource {
+    name: String,
+    path: PathBuf,
+    access_paths: AccessPaths,
+}
+
+/// Expand archive sources into `(name, path)` entries, walking the directories.
+///
+/// Only regular files are collected unless `all` is set, which adds directories and symlinks.
+async fn archive_entries(
+    sources: Vec<ArchiveSource>,
+    hidden: &[String],
+    running: Arc<AtomicBool>,
+    all: bool,
+) -> Result<Vec<(String, PathBuf)>> {
+    let mut entries = vec![];
+    for source in sources {
+        let meta = fs::symlink_metadata(&source.path).await?;
+        if !meta.is_dir() {
+            if meta.is_file() || all {
+                entries.push((source.name, source.path));
+            }
+            continue;
+        }
+        if all && !source.name.is_empty() {
+            entries.push((source.name.clone(), source.path.clone()));
+        }
+        let paths = archive_paths(
+            &source.path,
+            source.access_paths,
+            hidden,
+            running.clone(),
+            all,
+        )
+        .await?;
+        for path in paths {
+            let Ok(rel_path) = path.strip_prefix(&source.path) else {
+                continue;
+            };
+            let rel_path = normalize_path(rel_path);
+            let name = match source.name.is_empty() {
+                true => rel_path,
+                false => format!("{}/{rel_path}", source.name),
+            };
+            entries.push((name, path));
+        }
+    }
+    Ok(entries)
+}
+
+/// Collect the paths to archive under `dir`, honoring `access_paths` and `hidden`.
+///
+/// Only regular files are collected unless `all` is set, which adds directories and symlinks.
+async fn archive_paths(
     dir: &Path,
     access_paths: AccessPaths,
     hidden: &[String],
-    compression: Compression,
     running: Arc<AtomicBool>,
-) -> Result<()> {
-    let mut writer = ZipFileWriter::with_tokio(writer);
+    all: bool,
+) -> Result<Vec<PathBuf>> {
     let hidden = Arc::new(hidden.to_vec());
     let hidden = hidden.clone();
     let dir_clone = dir.to_path_buf();
-    let zip_paths = tokio::task::spawn_blocking(move || {
+    let archive_paths = tokio::task::spawn_blocking(move || {
         let mut paths: Vec<PathBuf> = vec![];
         for dir in access_paths.child_paths(&dir_clone) {
             let mut it = WalkDir::new(&dir).into_iter();
@@ -1806,7 +3879,10 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
                         }
                     }
                 }
-                if is_hidden(&hidden, base_name, is_dir_type) {
+                if is_hidden(&hidden, base_name, is_dir_type)
+                    || access_paths
+                        .denied(entry_path.strip_prefix(&dir_clone).unwrap_or(entry_path))
+                {
                     if file_type.is_dir() {
                         it.skip_current_dir();
                     }
@@ -1815,7 +3891,7 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
                 if entry.path().symlink_metadata().is_err() {
                     continue;
                 }
-                if !file_type.is_file() {
+                if !file_type.is_file() && !all {
                     continue;
                 }
                 paths.push(entry_path.to_path_buf());
@@ -1824,22 +3900,72 @@ async fn zip_dir<W: AsyncWrite + Unpin>(
         paths
     })
     .await?;
-    for zip_path in zip_paths.into_iter() {
-        let filename = match zip_path.strip_prefix(dir).ok().and_then(|v| v.to_str()) {
-            Some(v) => v,
-            None => continue,
-        };
-        let (datetime, mode) = get_file_mtime_and_mode(&zip_path).await?;
-        let builder = ZipEntryBuilder::new(filename.into(), compression)
-            .unix_permissions(mode)
-            .last_modification_date(ZipDateTime::from_chrono(&datetime));
-        let mut file = File::open(&zip_path).await?;
-        let mut file_writer = writer.write_entry_stream(builder).await?.compat_write();
-        io::copy(&mut file, &mut file_writer).await?;
-        file_writer.into_inner().close().await?;
+    Ok(archive_paths)
+}
+
+fn is_archive_request(query_params: &HashMap<String, String>) -> bool {
+    query_params.contains_key("zip") || query_params.contains_key("archive")
+}
+
+fn parse_archive_format(
+    query_params: &HashMap<String, String>,
+    res: &mut Response,
+) -> Option<ArchiveFormat> {
+    match query_params
+        .get("archive")
+        .filter(|_| !query_params.contains_key("zip"))
+    {
+        Some(v) => match v.parse::<ArchiveFormat>() {
+            Ok(v) => Some(v),
+            Err(err) => {
+                status_bad_request(res, &err.to_string());
+                None
+            }
+        },
+        None => Some(ArchiveFormat::Zip),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+impl ArchiveFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarZst => "tar.zst",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::TarGz => "application/gzip",
+            ArchiveFormat::TarZst => "application/zstd",
+        }
+    }
+}
+
+impl std::str::FromStr for ArchiveFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "" | "zip" => Ok(ArchiveFormat::Zip),
+            "tar" => Ok(ArchiveFormat::Tar),
+            "tar.gz" | "tgz" => Ok(ArchiveFormat::TarGz),
+            "tar.zst" | "tzst" => Ok(ArchiveFormat::TarZst),
+            _ => Err(anyhow!("Invalid archive format `{s}`")),
+        }
     }
-    writer.close().await?;
-    Ok(())
 }
 
 fn extract_cache_headers(meta: &Metadata) -> Option<(ETag, LastModified)> {
@@ -1851,6 +3977,24 @@ fn extract_cache_headers(meta: &Metadata) -> Option<(ETag, LastModified)> {
     Some((etag, last_modified))
 }
 
+/// The client ip resolved in `call`, none on unix sockets.
+/// Whether a `Range` request resumes a download rather than starting one, so it is not
+/// counted against the downloads of a share.
+fn is_resumed(headers: &HeaderMap<HeaderValue>) -> bool {
+    headers
+        .get(RANGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("bytes="))
+        .filter(|v| !v.contains(','))
+        .and_then(|v| v.split_once('-'))
+        .and_then(|(start, _)| start.trim().parse::<u64>().ok())
+        .is_some_and(|v| v > 0)
+}
+
+fn client_ip(req: &Request) -> Option<IpAddr> {
+    req.extensions().get::<ClientIp>().map(|v| v.0)
+}
+
 fn status_forbid(res: &mut Response) {
     *res.status_mut() = StatusCode::FORBIDDEN;
     *res.body_mut() = body_full("Forbidden");
@@ -1861,6 +4005,27 @@ fn status_not_found(res: &mut Response) {
     *res.body_mut() = body_full("Not Found");
 }
 
+fn status_redirect(res: &mut Response, location: &str) -> Result<()> {
+    *res.status_mut() = StatusCode::FOUND;
+    res.headers_mut()
+        .insert(LOCATION, HeaderValue::from_str(location)?);
+    Ok(())
+}
+
+fn accepts_html(headers: &HeaderMap<HeaderValue>) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("text/html"))
+}
+
+fn status_too_many_requests(res: &mut Response, retry_after: Duration) {
+    *res.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    res.headers_mut().insert(RETRY_AFTER, secs.into());
+    *res.body_mut() = body_full("Too Many Requests");
+}
+
 fn status_no_content(res: &mut Response) {
     *res.status_mut() = StatusCode::NO_CONTENT;
 }
@@ -1908,10 +4073,177 @@ fn is_hidden(hidden: &[String], file_name: &str, is_dir_type: bool) -> bool {
     })
 }
 
+/// Build the ordering of a listing from the `sort` and `order` query parameters.
+///
+/// Ties are broken by name so that the order is total and cursors are stable.
+fn list_order(query_params: &HashMap<String, String>) -> impl Fn(&PathItem, &PathItem) -> Ordering {
+    let sort = query_params.get("sort").cloned().unwrap_or_default();
+    let desc = query_params
+        .get("order")
+        .map(|v| v == "desc")
+        .unwrap_or_default();
+    move |v1: &PathItem, v2: &PathItem| {
+        let (v1, v2) = if desc { (v2, v1) } else { (v1, v2) };
+        match sort.as_str() {
+            "mtime" => v1.sort_by_mtime(v2),
+            "size" => v1.sort_by_size(v2),
+            _ => v1.sort_by_name(v2),
+        }
+        .then_with(|| v1.name.cmp(&v2.name))
+    }
+}
+
+type ListingKey = (PathBuf, String);
+
+/// Sorted listings of large directories, so paging through one doesn't read and sort
+/// the whole directory for every page. A listing is dropped once the directory's mtime
+/// changes, or after a while, since file sizes and mtimes change without it.
+#[derive(Debug, Default)]
+struct ListingCache {
+    listings: Mutex<HashMap<ListingKey, (SystemTime, Instant, Arc<Vec<PathItem>>)>>,
+}
+
+impl ListingCache {
+    fn get(&self, key: &ListingKey, mtime: SystemTime) -> Option<Arc<Vec<PathItem>>> {
+        let listings = self.listings.lock().unwrap();
+        match listings.get(key) {
+            Some((v, created, paths)) if *v == mtime && created.elapsed() < LISTING_CACHE_TTL => {
+                Some(paths.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn insert(&self, key: ListingKey, mtime: SystemTime, paths: Arc<Vec<PathItem>>) {
+        let mut listings = self.listings.lock().unwrap();
+        if listings.len() >= LISTING_CACHE_CAPACITY {
+            listings.retain(|_, (_, created, _)| created.elapsed() < LISTING_CACHE_TTL);
+        }
+        if listings.len() >= LISTING_CACHE_CAPACITY {
+            listings.clear();
+        }
+        listings.insert(key, (mtime, Instant::now(), paths));
+    }
+}
+
+/// Cache key of the order `list_order` sorts in.
+fn list_order_key(query_params: &HashMap<String, String>) -> String {
+    let sort = query_params.get("sort").cloned().unwrap_or_default();
+    let order = query_params.get("order").cloned().unwrap_or_default();
+    format!("{sort}:{order}")
+}
+
+/// Page size from the `limit` query parameter. Listings rendered as html are paged by
+/// default, `simple` and `json` ones are not.
+fn page_limit(query_params: &HashMap<String, String>) -> Result<Option<usize>> {
+    match query_params.get("limit") {
+        Some(v) => Ok(Some(
+            v.parse::<usize>()
+                .ok()
+                .filter(|v| *v > 0)
+                .ok_or_else(|| anyhow!("Invalid limit"))?,
+        )),
+        None if query_params.contains_key("simple") || query_params.contains_key("json") => {
+            Ok(None)
+        }
+        None => Ok(Some(INDEX_PAGE_SIZE)),
+    }
+}
+
+fn decode_cursor(query_params: &HashMap<String, String>) -> Result<Option<PathItem>> {
+    let Some(cursor) = query_params.get("cursor").filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    let cursor = general_purpose::URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|v| serde_json::from_slice(&v).ok())
+        .ok_or_else(|| anyhow!("Invalid cursor"))?;
+    Ok(Some(cursor))
+}
+
+/// Keep the page of sorted `paths` selected by the `cursor` and `limit` query parameters,
+/// and return the cursor of the next page if there is one.
+fn paginate<F>(
+    paths: &mut Vec<PathItem>,
+    query_params: &HashMap<String, String>,
+    compare: F,
+) -> Result<Option<String>>
+where
+    F: Fn(&PathItem, &PathItem) -> Ordering,
+{
+    if let Some(cursor) = decode_cursor(query_params)? {
+        let start = paths.partition_point(|v| compare(v, &cursor) != Ordering::Greater);
+        paths.drain(..start);
+    }
+    let limit = match page_limit(query_params)? {
+        Some(limit) if paths.len() > limit => limit,
+        _ => return Ok(None),
+    };
+    paths.truncate(limit);
+    let last = paths.last().ok_or_else(|| anyhow!("Invalid limit"))?;
+    let cursor = PathItem {
+        path_type: last.path_type,
+        name: last.name.clone(),
+        mtime: last.mtime,
+        size: last.size,
+        matches: None,
+    };
+    Ok(Some(
+        general_purpose::URL_SAFE_NO_PAD.encode(serde_json::to_vec(&cursor)?),
+    ))
+}
+
+/// Find lines of a text file containing `search` (already lowercased).
+///
+/// Returns `None` if the file is binary, larger than the size cap or has no match.
+fn search_file_content(path: &Path, search: &str) -> Option<Vec<ContentMatch>> {
+    let file = std::fs::File::open(path).ok()?;
+    if file.metadata().ok()?.len() > CONTENT_SEARCH_MAX_SIZE {
+        return None;
+    }
+    let mut reader = std::io::BufReader::new(file);
+    if !content_inspector::inspect(reader.fill_buf().ok()?).is_text() {
+        return None;
+    }
+    let mut matches = vec![];
+    let mut buf = vec![];
+    let mut line = 0;
+    while matches.len() < CONTENT_SEARCH_MAX_MATCHES {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf).ok()? == 0 {
+            break;
+        }
+        line += 1;
+        let text = String::from_utf8_lossy(&buf);
+        if text.to_lowercase().contains(search) {
+            let text = text.trim_end_matches(['\r', '\n']);
+            matches.push(ContentMatch {
+                line,
+                text: text.chars().take(CONTENT_SEARCH_MAX_LINE).collect(),
+            });
+        }
+    }
+    if matches.is_empty() {
+        None
+    } else {
+        Some(matches)
+    }
+}
+
+fn is_hidden_path(hidden: &[String], path: &str, is_dir_type: bool) -> bool {
+    let parts: Vec<&str> = path.split('/').filter(|v| !v.is_empty()).collect();
+    let len = parts.len();
+    parts
+        .iter()
+        .enumerate()
+        .any(|(i, name)| is_hidden(hidden, name, is_dir_type || i + 1 < len))
+}
+
 fn set_webdav_headers(res: &mut Response) {
     res.headers_mut().insert(
         "Allow",
-        HeaderValue::from_static("GET,HEAD,PUT,OPTIONS,DELETE,PATCH,PROPFIND,COPY,MOVE"),
+        HeaderValue::from_static("GET,HEAD,PUT,OPTIONS,DELETE,PATCH,PROPFIND,COPY,MOVE,REPORT"),
     );
     res.headers_mut().insert(
         "DAV",
@@ -1926,11 +4258,16 @@ async fn get_content_type(path: &Path) -> Result<String> {
         .take(1024)
         .read_to_end(&mut buffer)
         .await?;
+    Ok(detect_content_type(path, &buffer))
+}
+
+/// Guess the content type from the name of `path` and the first 1024 bytes of the content.
+fn detect_content_type(path: &Path, buffer: &[u8]) -> String {
     let mime = mime_guess::from_path(path).first();
-    let is_text = content_inspector::inspect(&buffer).is_text();
-    let content_type = if is_text {
+    let is_text = content_inspector::inspect(buffer).is_text();
+    if is_text {
         let mut detector = chardetng::EncodingDetector::new();
-        detector.feed(&buffer, buffer.len() < 1024);
+        detector.feed(buffer, buffer.len() < 1024);
         let (enc, confident) = detector.guess_assess(None, true);
         let charset = if confident {
             format!("; charset={}", enc.name())
@@ -1946,8 +4283,83 @@ async fn get_content_type(path: &Path) -> Result<String> {
             Some(m) => m.to_string(),
             None => "application/octet-stream".into(),
         }
+    }
+}
+
+struct SyncCollection {
+    sync_token: Option<String>,
+    infinite: bool,
+    limit: Option<usize>,
+}
+
+/// Parse a `DAV:sync-collection` report body, returns `None` for other report types.
+fn parse_sync_collection(body: &[u8]) -> Result<Option<SyncCollection>> {
+    use xml::reader::{EventReader, XmlEvent};
+    let mut report = SyncCollection {
+        sync_token: None,
+        infinite: false,
+        limit: None,
+    };
+    let mut stack: Vec<String> = vec![];
+    for event in EventReader::new(body) {
+        match event? {
+            XmlEvent::StartElement { name, .. } => {
+                if stack.is_empty()
+                    && (name.local_name != "sync-collection"
+                        || name.namespace.as_deref() != Some("DAV:"))
+                {
+                    return Ok(None);
+                }
+                stack.push(name.local_name);
+            }
+            XmlEvent::EndElement { .. } => {
+                stack.pop();
+            }
+            XmlEvent::Characters(value) => {
+                let value = value.trim();
+                match stack.last().map(|v| v.as_str()) {
+                    Some("sync-token") if !value.is_empty() => {
+                        report.sync_token = Some(value.to_string())
+                    }
+                    Some("sync-level") => report.infinite = value == "infinite",
+                    Some("nresults") => {
+                        report.limit = Some(
+                            value
+                                .parse()
+                                .map_err(|_| anyhow!("Invalid nresults `{value}`"))?,
+                        )
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Some(report))
+}
+
+fn is_sync_member(base: &str, name: &str, infinite: bool) -> bool {
+    let rest = if base.is_empty() {
+        name
+    } else {
+        match name.strip_prefix(base).and_then(|v| v.strip_prefix('/')) {
+            Some(v) => v,
+            None => return false,
+        }
     };
-    Ok(content_type)
+    !rest.is_empty() && (infinite || !rest.contains('/'))
+}
+
+fn res_dav_error(res: &mut Response, status: StatusCode, condition: &str) {
+    *res.status_mut() = status;
+    res.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("application/xml; charset=utf-8"),
+    );
+    *res.body_mut() = body_full(format!(
+        r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:error xmlns:D="DAV:"><D:{condition}/></D:error>"#
+    ));
 }
 
 fn parse_upload_offset(headers: &HeaderMap<HeaderValue>, size: u64) -> Result<Option<u64>> {
@@ -0,0 +1,206 @@
+use crate::auth::{AccessCaps, AccessPaths, AccessPerm};
+use crate::signer::{HmacSha256, Signer};
+
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::Utc;
+use hmac::Mac;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+pub const SHARE_COOKIE: &str = "dufs_share";
+
+/// What a share link grants, signed into the link itself. `path` is relative to the
+/// serve path and `expires` is a unix timestamp.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Share {
+    #[serde(rename = "i")]
+    pub id: String,
+    #[serde(rename = "p")]
+    pub path: String,
+    #[serde(rename = "e")]
+    pub expires: i64,
+    #[serde(rename = "n", default, skip_serializing_if = "Option::is_none")]
+    pub max_downloads: Option<u64>,
+    #[serde(rename = "w", default, skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
+    #[serde(rename = "u", default, skip_serializing_if = "is_false")]
+    pub upload_only: bool,
+    /// The capabilities of the minter on the path, as far as the share grants them.
+    #[serde(rename = "c")]
+    caps: String,
+    /// The paths below `path` denied to the minter.
+    #[serde(rename = "d", default, skip_serializing_if = "Vec::is_empty")]
+    denied: Vec<String>,
+}
+
+impl Share {
+    pub fn has_password(&self) -> bool {
+        self.password.is_some()
+    }
+
+    /// Whether `path` is the shared path or inside it.
+    pub fn contains(&self, path: &str) -> bool {
+        self.path.is_empty()
+            || path == self.path
+            || path
+                .strip_prefix(&self.path)
+                .map(|v| v.starts_with('/'))
+                .unwrap_or_default()
+    }
+
+    /// The access paths the share grants on `path`, which must be inside the share.
+    pub fn access_paths(&self, path: &str) -> Option<AccessPaths> {
+        let caps = AccessCaps::parse(&self.caps)?;
+        let mut access_paths = AccessPaths::new(AccessPerm::from_caps(caps));
+        for denied in self.denied.iter() {
+            access_paths.deny(denied);
+        }
+        access_paths.find(path.strip_prefix(&self.path).unwrap_or(path), false)
+    }
+}
+
+/// Mints and checks HMAC signed share links, and counts their downloads.
+#[derive(Debug)]
+pub struct ShareLinks {
+    signer: Signer,
+    downloads: Mutex<HashMap<String, (i64, u64)>>,
+}
+
+impl ShareLinks {
+    /// Without a secret, a random one is used and links stop working on restart.
+    pub fn new(secret: Option<&str>) -> Self {
+        Self {
+            signer: Signer::new(secret),
+            downloads: Default::default(),
+        }
+    }
+
+    /// Returns the link token of a new share. It grants no more than the `access_paths`
+    /// of the minter on `path`.
+    pub fn sign(
+        &self,
+        path: &str,
+        access_paths: &AccessPaths,
+        expires: i64,
+        max_downloads: Option<u64>,
+        password: Option<&str>,
+        upload_only: bool,
+    ) -> Result<String> {
+        let id = Uuid::new_v4().simple().to_string();
+        let password = password.map(|v| self.password_hash(&id, v));
+        let grant = match upload_only {
+            true => AccessCaps::UPLOADER,
+            false => AccessCaps::VIEWER,
+        };
+        let share = Share {
+            id,
+            path: path.to_string(),
+            expires,
+            max_downloads,
+            password,
+            upload_only,
+            caps: (access_paths.perm().caps() & grant).to_string(),
+            denied: access_paths.denied_paths(),
+        };
+        self.signer.sign(&share)
+    }
+
+    /// Check the signature and the expiry of a link token.
+    pub fn verify(&self, token: &str) -> Option<Share> {
+        let share: Share = self.signer.verify(token)?;
+        if share.expires <= Utc::now().timestamp() {
+            return None;
+        }
+        Some(share)
+    }
+
+    pub fn check_password(&self, share: &Share, password: &str) -> bool {
+        match &share.password {
+            Some(hash) => URL_SAFE_NO_PAD
+                .decode(hash)
+                .map(|v| {
+                    self.password_mac(&share.id, password)
+                        .verify_slice(&v)
+                        .is_ok()
+                })
+                .unwrap_or_default(),
+            None => true,
+        }
+    }
+
+    /// Count a download, returns false once the share reached its limit.
+    pub fn take_download(&self, share: &Share) -> bool {
+        let Some(max_downloads) = share.max_downloads else {
+            return true;
+        };
+        let now = Utc::now().timestamp();
+        let mut downloads = self.downloads.lock().unwrap();
+        downloads.retain(|_, (expires, _)| *expires > now);
+        let (_, count) = downloads
+            .entry(share.id.clone())
+            .or_insert((share.expires, 0));
+        if *count >= max_downloads {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+
+    fn password_hash(&self, id: &str, password: &str) -> String {
+        URL_SAFE_NO_PAD.encode(self.password_mac(id, password).finalize().into_bytes())
+    }
+
+    fn password_mac(&self, id: &str, password: &str) -> HmacSha256 {
+        let mut mac = self.signer.mac(format!("password:{id}:").as_bytes());
+        mac.update(password.as_bytes());
+        mac
+    }
+}
+
+fn is_false(v: &bool) -> bool {
+    !v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_links() {
+        let links = ShareLinks::new(Some("secret"));
+        let expires = Utc::now().timestamp() + 60;
+        let mut access_paths = AccessPaths::new(AccessPerm::ReadWrite);
+        access_paths.deny("sub/secret");
+        let token = links
+            .sign("dir1", &access_paths, expires, Some(1), Some("pass"), false)
+            .unwrap();
+        let share = links.verify(&token).unwrap();
+        assert!(share.contains("dir1"));
+        let perm = share.access_paths("dir1/sub").unwrap().perm();
+        assert_eq!(perm, AccessPerm::ReadOnly);
+        assert!(share.access_paths("dir1/sub/secret").is_none());
+        assert!(share.contains("dir1/file"));
+        assert!(!share.contains("dir10"));
+        assert!(!share.contains("dir2"));
+        assert!(links.check_password(&share, "pass"));
+        assert!(!links.check_password(&share, "pass1"));
+        assert!(links.take_download(&share));
+        assert!(!links.take_download(&share));
+
+        assert!(ShareLinks::new(Some("secret2")).verify(&token).is_none());
+        let (payload, signature) = token.split_once('.').unwrap();
+        let mut data: serde_json::Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap()).unwrap();
+        data["p"] = "".into();
+        let payload = URL_SAFE_NO_PAD.encode(data.to_string());
+        assert!(links.verify(&format!("{payload}.{signature}")).is_none());
+
+        let token = links
+            .sign("", &access_paths, expires - 120, None, None, false)
+            .unwrap();
+        assert!(links.verify(&token).is_none());
+    }
+}
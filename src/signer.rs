@@ -0,0 +1,47 @@
+use anyhow::Result;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+pub type HmacSha256 = Hmac<Sha256>;
+
+/// Signs values with HMAC-SHA256 into `<payload>.<signature>` strings safe for URLs
+/// and cookies.
+#[derive(Debug)]
+pub struct Signer {
+    secret: Vec<u8>,
+}
+
+impl Signer {
+    /// Without a secret, a random one is used and signed values stop working on restart.
+    pub fn new(secret: Option<&str>) -> Self {
+        let secret = match secret {
+            Some(v) => v.as_bytes().to_vec(),
+            None => [Uuid::new_v4().into_bytes(), Uuid::new_v4().into_bytes()].concat(),
+        };
+        Self { secret }
+    }
+
+    pub fn sign<T: Serialize>(&self, value: &T) -> Result<String> {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(value)?);
+        let signature =
+            URL_SAFE_NO_PAD.encode(self.mac(payload.as_bytes()).finalize().into_bytes());
+        Ok(format!("{payload}.{signature}"))
+    }
+
+    pub fn verify<T: DeserializeOwned>(&self, signed: &str) -> Option<T> {
+        let (payload, signature) = signed.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+        self.mac(payload.as_bytes()).verify_slice(&signature).ok()?;
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()
+    }
+
+    pub fn mac(&self, data: &[u8]) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any size");
+        mac.update(data);
+        mac
+    }
+}
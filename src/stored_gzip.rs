@@ -0,0 +1,121 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::AsyncWrite;
+
+/// Deflate stored blocks hold at most this many bytes.
+const MAX_BLOCK_SIZE: usize = 65535;
+/// Deflate, no name, no mtime, unknown OS.
+const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+
+pin_project_lite::pin_project! {
+    /// A gzip writer that doesn't compress, the data goes out in deflate stored blocks.
+    ///
+    /// Gzip encoders raise level 0 to 1, so this is what `--compress none` uses.
+    pub struct StoredGzipEncoder<W> {
+        #[pin]
+        inner: W,
+        pending: Vec<u8>,
+        written: usize,
+        crc: crc32fast::Hasher,
+        size: u32,
+        finished: bool,
+    }
+}
+
+impl<W: AsyncWrite> StoredGzipEncoder<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            pending: HEADER.to_vec(),
+            written: 0,
+            crc: crc32fast::Hasher::new(),
+            size: 0,
+            finished: false,
+        }
+    }
+
+    fn poll_pending(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut this = self.project();
+        while *this.written < this.pending.len() {
+            let n = ready!(this
+                .inner
+                .as_mut()
+                .poll_write(cx, &this.pending[*this.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            *this.written += n;
+        }
+        this.pending.clear();
+        *this.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for StoredGzipEncoder<W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.as_mut().poll_pending(cx))?;
+        let this = self.project();
+        let data = &buf[..buf.len().min(MAX_BLOCK_SIZE)];
+        if data.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let len = data.len() as u16;
+        this.pending.push(0);
+        this.pending.extend_from_slice(&len.to_le_bytes());
+        this.pending.extend_from_slice(&(!len).to_le_bytes());
+        this.pending.extend_from_slice(data);
+        this.crc.update(data);
+        *this.size = this.size.wrapping_add(data.len() as u32);
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.as_mut().poll_pending(cx))?;
+        self.project().inner.poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.finished {
+            let this = self.as_mut().project();
+            // An empty final block ends the deflate stream, the trailer follows
+            this.pending.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+            this.pending
+                .extend_from_slice(&this.crc.clone().finalize().to_le_bytes());
+            this.pending.extend_from_slice(&this.size.to_le_bytes());
+            *this.finished = true;
+        }
+        ready!(self.as_mut().poll_pending(cx))?;
+        self.project().inner.poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_compression::tokio::bufread::GzipDecoder;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_stored_gzip() {
+        let data: Vec<u8> = (0..200_000u32).map(|v| (v % 251) as u8).collect();
+        let mut encoder = StoredGzipEncoder::new(Vec::new());
+        encoder.write_all(&data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+        let output = encoder.inner;
+        // Four blocks of data, then the empty final one
+        assert_eq!(output.len(), 10 + 5 * 5 + data.len() + 8);
+
+        let mut decoded = vec![];
+        GzipDecoder::new(&output[..])
+            .read_to_end(&mut decoded)
+            .await
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+}
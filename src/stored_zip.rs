@@ -0,0 +1,525 @@
+use crate::utils::get_file_mtime_and_mode;
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use futures_util::Stream;
+use indexmap::IndexMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const BUF_SIZE: usize = 65536;
+const CRC_CACHE_CAPACITY: usize = 100_000;
+
+const LOCAL_HEADER_SIZE: u64 = 30;
+const CENTRAL_HEADER_SIZE: u64 = 46;
+const ZIP64_EXTRA_SIZE: u64 = 28;
+const LOCAL_ZIP64_EXTRA_SIZE: u64 = 20;
+const END_SIZE: u64 = 22;
+const ZIP64_END_SIZE: u64 = 56 + 20;
+const U16_MAX: u64 = u16::MAX as u64;
+const U32_MAX: u64 = u32::MAX as u64;
+
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+const VERSION_MADE_BY_UNIX: u16 = 3 << 8;
+/// The name is UTF-8.
+const FLAGS: u16 = 0x0800;
+
+/// A zip without compression whose exact size and byte layout are known from file
+/// metadata alone, so it can be sent with a Content-Length and served by ranges.
+///
+/// The crc of each entry goes in its local header, so it's computed, or taken from the
+/// cache, before the entry is sent. Zip64 records are used for entries and archives
+/// beyond 4G.
+#[derive(Debug)]
+pub struct StoredZip {
+    entries: Vec<StoredEntry>,
+    central_dir_offset: u64,
+    central_dir_size: u64,
+    size: u64,
+    etag: String,
+}
+
+#[derive(Debug)]
+struct StoredEntry {
+    name: String,
+    path: PathBuf,
+    size: u64,
+    mtime: DateTime<Utc>,
+    mode: u16,
+    offset: u64,
+}
+
+impl StoredEntry {
+    fn is_zip64(&self) -> bool {
+        self.size >= U32_MAX || self.offset >= U32_MAX
+    }
+
+    /// Whether the sizes need 8 bytes in the local header.
+    fn is_large(&self) -> bool {
+        self.size >= U32_MAX
+    }
+
+    fn header_size(&self) -> u64 {
+        let extra = if self.is_large() {
+            LOCAL_ZIP64_EXTRA_SIZE
+        } else {
+            0
+        };
+        LOCAL_HEADER_SIZE + self.name.len() as u64 + extra
+    }
+
+    fn data_offset(&self) -> u64 {
+        self.offset + self.header_size()
+    }
+
+    fn end_offset(&self) -> u64 {
+        self.data_offset() + self.size
+    }
+
+    fn central_header_size(&self) -> u64 {
+        let extra = if self.is_zip64() { ZIP64_EXTRA_SIZE } else { 0 };
+        CENTRAL_HEADER_SIZE + self.name.len() as u64 + extra
+    }
+
+    fn version(&self) -> u16 {
+        if self.is_zip64() {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        }
+    }
+
+    fn dos_datetime(&self) -> (u16, u16) {
+        let datetime = self.mtime.with_timezone(&Local);
+        if datetime.year() < 1980 {
+            return (0, (1 << 5) | 1);
+        }
+        let time = (datetime.hour() << 11) | (datetime.minute() << 5) | (datetime.second() / 2);
+        let date = ((datetime.year() as u32 - 1980).min(127) << 9)
+            | (datetime.month() << 5)
+            | datetime.day();
+        (time as u16, date as u16)
+    }
+
+    fn local_header(&self, crc: u32) -> Vec<u8> {
+        let (time, date) = self.dos_datetime();
+        let large = self.is_large();
+        let mut buf = Vec::with_capacity(self.header_size() as usize);
+        put_u32(&mut buf, 0x04034b50);
+        put_u16(&mut buf, self.version());
+        put_u16(&mut buf, FLAGS);
+        put_u16(&mut buf, 0); // stored
+        put_u16(&mut buf, time);
+        put_u16(&mut buf, date);
+        // Large sizes are in the zip64 extra
+        let (size, extra) = if large {
+            (U32_MAX as u32, LOCAL_ZIP64_EXTRA_SIZE as u16)
+        } else {
+            (self.size as u32, 0)
+        };
+        put_u32(&mut buf, crc);
+        put_u32(&mut buf, size);
+        put_u32(&mut buf, size);
+        put_u16(&mut buf, self.name.len() as u16);
+        put_u16(&mut buf, extra);
+        buf.extend_from_slice(self.name.as_bytes());
+        if large {
+            put_u16(&mut buf, 0x0001);
+            put_u16(&mut buf, LOCAL_ZIP64_EXTRA_SIZE as u16 - 4);
+            put_u64(&mut buf, self.size);
+            put_u64(&mut buf, self.size);
+        }
+        buf
+    }
+
+    fn central_header(&self, crc: u32, buf: &mut Vec<u8>) {
+        let (time, date) = self.dos_datetime();
+        let zip64 = self.is_zip64();
+        put_u32(buf, 0x02014b50);
+        put_u16(buf, VERSION_MADE_BY_UNIX | self.version());
+        put_u16(buf, self.version());
+        put_u16(buf, FLAGS);
+        put_u16(buf, 0);
+        put_u16(buf, time);
+        put_u16(buf, date);
+        put_u32(buf, crc);
+        let size = if zip64 { U32_MAX } else { self.size } as u32;
+        put_u32(buf, size);
+        put_u32(buf, size);
+        put_u16(buf, self.name.len() as u16);
+        put_u16(buf, if zip64 { ZIP64_EXTRA_SIZE as u16 } else { 0 });
+        put_u16(buf, 0); // comment
+        put_u16(buf, 0); // disk
+        put_u16(buf, 0); // internal attributes
+        put_u32(buf, (0o100000 | self.mode as u32) << 16);
+        put_u32(buf, if zip64 { U32_MAX } else { self.offset } as u32);
+        buf.extend_from_slice(self.name.as_bytes());
+        if zip64 {
+            put_u16(buf, 0x0001);
+            put_u16(buf, ZIP64_EXTRA_SIZE as u16 - 4);
+            put_u64(buf, self.size);
+            put_u64(buf, self.size);
+            put_u64(buf, self.offset);
+        }
+    }
+}
+
+impl StoredZip {
+    /// Lay out the `(name, path)` entries, which must be regular files.
+    pub async fn new(entries: Vec<(String, PathBuf)>) -> Result<Self> {
+        let mut offset = 0;
+        let mut stored_entries = Vec::with_capacity(entries.len());
+        let mut etag = md5::Context::new();
+        for (name, path) in entries {
+            if name.len() as u64 > U16_MAX {
+                bail!("Too long name `{name}`");
+            }
+            let (size, (mtime, mode)) = tokio::try_join!(
+                async { Ok(tokio::fs::metadata(&path).await?.len()) },
+                get_file_mtime_and_mode(&path)
+            )?;
+            etag.consume(format!("{name}\0{size}\0{}\0", mtime.timestamp_millis()));
+            let entry = StoredEntry {
+                name,
+                path,
+                size,
+                mtime,
+                mode,
+                offset,
+            };
+            offset = entry.end_offset();
+            stored_entries.push(entry);
+        }
+        let central_dir_offset = offset;
+        let central_dir_size: u64 = stored_entries.iter().map(|v| v.central_header_size()).sum();
+        let mut size = central_dir_offset + central_dir_size + END_SIZE;
+        if is_zip64_end(stored_entries.len(), central_dir_offset, central_dir_size) {
+            size += ZIP64_END_SIZE;
+        }
+        Ok(Self {
+            entries: stored_entries,
+            central_dir_offset,
+            central_dir_size,
+            size,
+            etag: format!(r#""{:x}-{size}""#, etag.compute()),
+        })
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn etag(&self) -> &str {
+        &self.etag
+    }
+
+    /// Stream the bytes from `start` to `end`, both inclusive.
+    pub fn stream(
+        self: Arc<Self>,
+        start: u64,
+        end: u64,
+        crc_cache: Arc<CrcCache>,
+    ) -> impl Stream<Item = Result<Bytes>> {
+        async_stream::try_stream! {
+            let end = end + 1;
+            let first = self.entries.partition_point(|v| v.end_offset() <= start);
+            for entry in self.entries.iter().skip(first) {
+                if entry.offset >= end {
+                    break;
+                }
+                let data_offset = entry.data_offset();
+                if data_offset > start {
+                    let crc = crc_cache.get_or_compute(entry).await?;
+                    if let Some(chunk) = slice(&entry.local_header(crc), entry.offset, start, end) {
+                        yield chunk;
+                    }
+                }
+                if data_offset + entry.size > start && data_offset < end {
+                    let from = start.saturating_sub(data_offset);
+                    let to = (end - data_offset).min(entry.size);
+                    let mut file = File::open(&entry.path).await?;
+                    file.seek(SeekFrom::Start(from)).await?;
+                    let mut remaining = to - from;
+                    let mut buf = vec![0; BUF_SIZE];
+                    while remaining > 0 {
+                        let len = (remaining as usize).min(BUF_SIZE);
+                        let n = file.read(&mut buf[..len]).await?;
+                        if n == 0 {
+                            Err(anyhow!("File `{}` was truncated", entry.path.display()))?;
+                        }
+                        remaining -= n as u64;
+                        yield Bytes::copy_from_slice(&buf[..n]);
+                    }
+                }
+            }
+            if end > self.central_dir_offset {
+                let mut buf = Vec::with_capacity((self.size - self.central_dir_offset) as usize);
+                for entry in self.entries.iter() {
+                    let crc = crc_cache.get_or_compute(entry).await?;
+                    entry.central_header(crc, &mut buf);
+                }
+                self.write_end(&mut buf);
+                if let Some(chunk) = slice(&buf, self.central_dir_offset, start, end) {
+                    yield chunk;
+                }
+            }
+        }
+    }
+
+    fn write_end(&self, buf: &mut Vec<u8>) {
+        let count = self.entries.len() as u64;
+        let zip64 = is_zip64_end(
+            self.entries.len(),
+            self.central_dir_offset,
+            self.central_dir_size,
+        );
+        if zip64 {
+            let zip64_end_offset = self.central_dir_offset + self.central_dir_size;
+            put_u32(buf, 0x06064b50);
+            put_u64(buf, 56 - 12);
+            put_u16(buf, VERSION_MADE_BY_UNIX | VERSION_ZIP64);
+            put_u16(buf, VERSION_ZIP64);
+            put_u32(buf, 0);
+            put_u32(buf, 0);
+            put_u64(buf, count);
+            put_u64(buf, count);
+            put_u64(buf, self.central_dir_size);
+            put_u64(buf, self.central_dir_offset);
+            // locator
+            put_u32(buf, 0x07064b50);
+            put_u32(buf, 0);
+            put_u64(buf, zip64_end_offset);
+            put_u32(buf, 1);
+        }
+        put_u32(buf, 0x06054b50);
+        put_u16(buf, 0);
+        put_u16(buf, 0);
+        let count = count.min(U16_MAX) as u16;
+        put_u16(buf, count);
+        put_u16(buf, count);
+        put_u32(buf, self.central_dir_size.min(U32_MAX) as u32);
+        put_u32(buf, self.central_dir_offset.min(U32_MAX) as u32);
+        put_u16(buf, 0);
+    }
+}
+
+/// Size, mtime and crc of a file.
+type CrcEntry = (u64, DateTime<Utc>, u32);
+
+/// Crcs of files sent in stored zips, so a resumed download doesn't have to read
+/// every file again for the headers.
+///
+/// Entries are kept from the least to the most recently used, the least recently
+/// used one makes room when the cache is full.
+#[derive(Debug)]
+pub struct CrcCache {
+    crcs: Mutex<IndexMap<PathBuf, CrcEntry>>,
+    capacity: usize,
+}
+
+impl Default for CrcCache {
+    fn default() -> Self {
+        Self::new(CRC_CACHE_CAPACITY)
+    }
+}
+
+impl CrcCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            crcs: Mutex::new(IndexMap::new()),
+            capacity,
+        }
+    }
+
+    fn get(&self, entry: &StoredEntry) -> Option<u32> {
+        let mut crcs = self.crcs.lock().unwrap();
+        let index = crcs.get_index_of(&entry.path)?;
+        let (size, mtime, crc) = crcs[index];
+        if size != entry.size || mtime != entry.mtime {
+            return None;
+        }
+        let last = crcs.len() - 1;
+        crcs.move_index(index, last);
+        Some(crc)
+    }
+
+    fn insert(&self, entry: &StoredEntry, crc: u32) {
+        let mut crcs = self.crcs.lock().unwrap();
+        crcs.shift_remove(&entry.path);
+        if crcs.len() >= self.capacity {
+            crcs.shift_remove_index(0);
+        }
+        crcs.insert(entry.path.clone(), (entry.size, entry.mtime, crc));
+    }
+
+    async fn get_or_compute(&self, entry: &StoredEntry) -> Result<u32> {
+        if let Some(crc) = self.get(entry) {
+            return Ok(crc);
+        }
+        let crc = compute_crc(&entry.path, entry.size).await?;
+        self.insert(entry, crc);
+        Ok(crc)
+    }
+}
+
+async fn compute_crc(path: &Path, size: u64) -> Result<u32> {
+    let mut file = File::open(path).await?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut remaining = size;
+    let mut buf = vec![0; BUF_SIZE];
+    while remaining > 0 {
+        let len = (remaining as usize).min(BUF_SIZE);
+        let n = file.read(&mut buf[..len]).await?;
+        if n == 0 {
+            bail!("File `{}` was truncated", path.display());
+        }
+        hasher.update(&buf[..n]);
+        remaining -= n as u64;
+    }
+    Ok(hasher.finalize())
+}
+
+fn is_zip64_end(count: usize, central_dir_offset: u64, central_dir_size: u64) -> bool {
+    count as u64 >= U16_MAX || central_dir_offset >= U32_MAX || central_dir_size >= U32_MAX
+}
+
+/// The part of `data`, placed at `offset`, that falls in `start..end`.
+fn slice(data: &[u8], offset: u64, start: u64, end: u64) -> Option<Bytes> {
+    let from = start.max(offset);
+    let to = end.min(offset + data.len() as u64);
+    if from >= to {
+        return None;
+    }
+    Some(Bytes::copy_from_slice(
+        &data[(from - offset) as usize..(to - offset) as usize],
+    ))
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    async fn collect(zip: &Arc<StoredZip>, start: u64, end: u64, cache: &Arc<CrcCache>) -> Vec<u8> {
+        let chunks: Vec<Bytes> = zip
+            .clone()
+            .stream(start, end, cache.clone())
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat()
+    }
+
+    #[tokio::test]
+    async fn test_stored_zip() {
+        let root = std::env::temp_dir().join(format!("dufs-zip-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("a.txt"), "hello world").unwrap();
+        std::fs::write(root.join("b.bin"), vec![7u8; 100_000]).unwrap();
+        let entries = vec![
+            ("a.txt".to_string(), root.join("a.txt")),
+            ("dir/b.bin".to_string(), root.join("b.bin")),
+        ];
+        let zip = Arc::new(StoredZip::new(entries).await.unwrap());
+        let cache = Arc::new(CrcCache::default());
+
+        // Ranges work without the crcs having been computed before
+        let tail = collect(&zip, 100, zip.size() - 1, &cache).await;
+        let data = collect(&zip, 0, zip.size() - 1, &cache).await;
+        assert_eq!(data.len() as u64, zip.size());
+        assert_eq!(&data[100..], &tail[..]);
+        assert_eq!(collect(&zip, 5, 5, &cache).await, &data[5..6]);
+
+        // No data descriptors, the crc and sizes are in the local header
+        assert_eq!(&data[6..8], &FLAGS.to_le_bytes());
+        assert_eq!(
+            &data[14..18],
+            &crc32fast::hash(b"hello world").to_le_bytes()
+        );
+        assert_eq!(&data[18..26], &[11, 0, 0, 0, 11, 0, 0, 0]);
+
+        let archive = async_zip::base::read::mem::ZipFileReader::new(data)
+            .await
+            .unwrap();
+        let names: Vec<String> = archive
+            .file()
+            .entries()
+            .iter()
+            .map(|v| v.filename().as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(names, ["a.txt", "dir/b.bin"]);
+        let mut content = String::new();
+        let mut reader = archive.reader_with_entry(0).await.unwrap();
+        reader.read_to_string_checked(&mut content).await.unwrap();
+        assert_eq!(content, "hello world");
+
+        let zip2 = StoredZip::new(vec![("a.txt".to_string(), root.join("a.txt"))])
+            .await
+            .unwrap();
+        assert_ne!(zip.etag(), zip2.etag());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_large_local_header() {
+        let entry = StoredEntry {
+            name: "big.bin".to_string(),
+            path: PathBuf::from("big.bin"),
+            size: 5 << 30,
+            mtime: Utc::now(),
+            mode: 0o644,
+            offset: 0,
+        };
+        let header = entry.local_header(0);
+        assert_eq!(header.len() as u64, entry.header_size());
+        assert_eq!(&header[18..26], &[0xff; 8]);
+        let extra = &header[30 + entry.name.len()..];
+        assert_eq!(&extra[..4], &[0x01, 0x00, 0x10, 0x00]);
+        assert_eq!(&extra[4..12], &(5u64 << 30).to_le_bytes());
+        assert_eq!(&extra[12..], &(5u64 << 30).to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_crc_cache_evicts_least_recently_used() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let mut entries = vec![];
+        for name in ["a", "b", "c"] {
+            let path = root.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            let (mtime, mode) = get_file_mtime_and_mode(&path).await.unwrap();
+            entries.push(StoredEntry {
+                name: name.to_string(),
+                path,
+                size: 1,
+                mtime,
+                mode,
+                offset: 0,
+            });
+        }
+        let cache = CrcCache::new(2);
+        cache.insert(&entries[0], 1);
+        cache.insert(&entries[1], 2);
+        assert_eq!(cache.get(&entries[0]), Some(1));
+        cache.insert(&entries[2], 3);
+        assert_eq!(cache.get(&entries[0]), Some(1));
+        assert_eq!(cache.get(&entries[1]), None);
+        assert_eq!(cache.get(&entries[2]), Some(3));
+    }
+}
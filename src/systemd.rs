@@ -0,0 +1,222 @@
+use crate::Args;
+
+use anyhow::Result;
+use std::net::{SocketAddr, TcpListener as StdTcpListener};
+#[cfg(unix)]
+use {
+    crate::args::BindAddr,
+    anyhow::bail,
+    sd_notify::NotifyState,
+    socket2::{Socket, Type},
+    std::os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    std::os::unix::net::UnixListener as StdUnixListener,
+    std::path::Path,
+    std::time::Duration,
+};
+
+/// Name of the listeners dufs keeps in systemd's fd store, to tell them apart from
+/// socket activated ones.
+#[cfg(unix)]
+const STORE_FDNAME: &str = "dufs-listener";
+
+/// Listening sockets passed in by systemd, either from socket activation or from the fd
+/// store of an earlier run. Outside systemd there are none and nothing is sent.
+#[derive(Default)]
+pub struct ListenFds {
+    #[cfg(unix)]
+    activated: bool,
+    #[cfg(unix)]
+    sockets: Vec<(String, Socket)>,
+    #[cfg(unix)]
+    listeners: Vec<RawFd>,
+}
+
+/// Take over the sockets in `LISTEN_FDS`.
+#[cfg(unix)]
+pub fn listen_fds() -> Result<ListenFds> {
+    let mut listen_fds = ListenFds::default();
+    for (fd, name) in sd_notify::listen_fds_with_names(true)? {
+        // SAFETY: systemd hands the fds over to this process
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        if socket.r#type()? != Type::STREAM {
+            bail!("Passed fd {fd} is not a stream socket");
+        }
+        listen_fds.activated |= name != STORE_FDNAME;
+        listen_fds.sockets.push((name, socket));
+    }
+    Ok(listen_fds)
+}
+
+#[cfg(not(unix))]
+pub fn listen_fds() -> Result<ListenFds> {
+    Ok(ListenFds::default())
+}
+
+impl ListenFds {
+    /// Socket activated listeners are served in place of `--bind` and `--port`.
+    #[cfg(unix)]
+    pub fn override_addrs(&self, args: &mut Args) -> Result<()> {
+        if !self.activated {
+            return Ok(());
+        }
+        let mut addrs = vec![];
+        let mut port = None;
+        for (_, socket) in self.sockets.iter().filter(|(v, _)| v != STORE_FDNAME) {
+            let addr = socket.local_addr()?;
+            if let Some(addr) = addr.as_socket() {
+                if port.is_some_and(|v| v != addr.port()) {
+                    bail!("Socket activated listeners must share one port");
+                }
+                port = Some(addr.port());
+                addrs.push(BindAddr::Address(addr.ip()));
+            } else if let Some(path) = addr.as_pathname() {
+                addrs.push(BindAddr::Path(path.to_path_buf()));
+            } else {
+                bail!("Socket activated listeners must be tcp or unix path sockets");
+            }
+        }
+        args.addrs = addrs;
+        if let Some(port) = port {
+            args.port = port;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn override_addrs(&self, _args: &mut Args) -> Result<()> {
+        Ok(())
+    }
+
+    /// Take the passed listener on `addr`, if there is one.
+    #[cfg(unix)]
+    pub fn take_tcp(&mut self, addr: SocketAddr) -> Option<StdTcpListener> {
+        let index = self.sockets.iter().position(|(_, socket)| {
+            socket.local_addr().ok().and_then(|v| v.as_socket()) == Some(addr)
+        })?;
+        Some(self.sockets.remove(index).1.into())
+    }
+
+    #[cfg(not(unix))]
+    pub fn take_tcp(&mut self, _addr: SocketAddr) -> Option<StdTcpListener> {
+        None
+    }
+
+    /// Take the passed listener on the unix socket `path`, if there is one.
+    #[cfg(unix)]
+    pub fn take_unix(&mut self, path: &Path) -> Option<StdUnixListener> {
+        let index = self.sockets.iter().position(|(_, socket)| {
+            socket
+                .local_addr()
+                .is_ok_and(|v| v.as_pathname() == Some(path))
+        })?;
+        Some(OwnedFd::from(self.sockets.remove(index).1).into())
+    }
+
+    /// Remember a listener being served, to hand it over to the next run.
+    #[cfg(unix)]
+    pub fn push_listener<T: AsRawFd>(&mut self, listener: &T) {
+        self.listeners.push(listener.as_raw_fd());
+    }
+
+    #[cfg(not(unix))]
+    pub fn push_listener<T>(&mut self, _listener: &T) {}
+}
+
+/// Tell systemd the listeners are up. Unless they are socket activated, they also go to
+/// the fd store, so a restart picks them up again without refusing connections meanwhile.
+#[cfg(unix)]
+pub fn notify_ready(listen_fds: ListenFds) {
+    let fdstore = std::env::var("FDSTORE")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or_default();
+    if !listen_fds.activated && fdstore > 0 {
+        // SAFETY: the listeners stay open in their accept loops while this runs
+        let fds: Vec<_> = listen_fds
+            .listeners
+            .iter()
+            .map(|v| unsafe { BorrowedFd::borrow_raw(*v) })
+            .collect();
+        // Listeners of an earlier run which are not served anymore get closed too
+        notify(&[
+            NotifyState::FdStoreRemove,
+            NotifyState::FdName(STORE_FDNAME),
+        ]);
+        let state = [NotifyState::FdStore, NotifyState::FdName(STORE_FDNAME)];
+        if let Err(err) = sd_notify::notify_with_fds(false, &state, &fds) {
+            warn!("Failed to store listeners with systemd: {err}");
+        }
+    }
+    notify(&[NotifyState::Ready]);
+}
+
+#[cfg(not(unix))]
+pub fn notify_ready(_listen_fds: ListenFds) {}
+
+#[cfg(unix)]
+pub fn notify_stopping() {
+    notify(&[NotifyState::Stopping]);
+}
+
+#[cfg(not(unix))]
+pub fn notify_stopping() {}
+
+/// Ping the systemd watchdog at half its interval, if `WatchdogSec` is set.
+#[cfg(unix)]
+pub fn spawn_watchdog() {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(true, &mut usec) {
+        return;
+    }
+    let period = Duration::from_micros(usec / 2);
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            notify(&[NotifyState::Watchdog]);
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_watchdog() {}
+
+#[cfg(unix)]
+fn notify(state: &[NotifyState]) {
+    if let Err(err) = sd_notify::notify(false, state) {
+        warn!("Failed to notify systemd: {err}");
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listen_fds() {
+        let tcp = StdTcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let tmpdir = assert_fs::TempDir::new().unwrap();
+        let path = tmpdir.path().join("dufs.sock");
+        let unix = StdUnixListener::bind(&path).unwrap();
+        let mut listen_fds = ListenFds {
+            activated: true,
+            sockets: vec![
+                ("http".into(), Socket::from(tcp)),
+                ("http".into(), Socket::from(OwnedFd::from(unix))),
+            ],
+            ..Default::default()
+        };
+        let mut args = Args::default();
+        listen_fds.override_addrs(&mut args).unwrap();
+        assert_eq!(
+            args.addrs,
+            [BindAddr::Address(addr.ip()), BindAddr::Path(path.clone())]
+        );
+        assert_eq!(args.port, addr.port());
+        assert!(listen_fds.take_unix(&path).is_some());
+        assert!(listen_fds.take_unix(&path).is_none());
+        assert!(listen_fds.take_tcp(addr).is_some());
+        assert!(listen_fds.take_tcp(addr).is_none());
+    }
+}
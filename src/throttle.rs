@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Why a request with credentials was turned down, kept in the response extensions for
+/// the `$auth_failure` log variable.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthFailure {
+    Invalid,
+    Locked,
+}
+
+impl AuthFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthFailure::Invalid => "invalid",
+            AuthFailure::Locked => "locked",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed logins per client IP and per user. Once one of them reaches
+/// `max_failures`, it is locked out for a second, doubled on each further failure up to
+/// `max_lockout`.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    max_failures: u32,
+    max_lockout: Duration,
+    failures: Mutex<HashMap<String, Failures>>,
+}
+
+impl LoginThrottle {
+    pub fn new(max_failures: u32, max_lockout: Duration) -> Self {
+        Self {
+            max_failures,
+            max_lockout,
+            failures: Default::default(),
+        }
+    }
+
+    /// How long to wait before trying to log in again, if the IP or the user is locked out.
+    pub fn check(&self, ip: Option<IpAddr>, user: Option<&str>) -> Option<Duration> {
+        if self.max_failures == 0 {
+            return None;
+        }
+        let now = Instant::now();
+        let failures = self.failures.lock().unwrap();
+        keys(ip, user)
+            .filter_map(|key| failures.get(&key)?.locked_until)
+            .filter(|until| *until > now)
+            .max()
+            .map(|until| until - now)
+    }
+
+    pub fn fail(&self, ip: Option<IpAddr>, user: Option<&str>) {
+        if self.max_failures == 0 {
+            return;
+        }
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+        let forget = self.max_lockout.max(Duration::from_secs(60));
+        failures.retain(|_, v| now.duration_since(v.last) < forget);
+        for key in keys(ip, user) {
+            let entry = failures.entry(key).or_insert(Failures {
+                count: 0,
+                last: now,
+                locked_until: None,
+            });
+            entry.count += 1;
+            entry.last = now;
+            if entry.count >= self.max_failures {
+                let exp = (entry.count - self.max_failures).min(31);
+                let lockout = Duration::from_secs(1 << exp).min(self.max_lockout);
+                entry.locked_until = Some(now + lockout);
+            }
+        }
+    }
+
+    pub fn succeed(&self, ip: Option<IpAddr>, user: Option<&str>) {
+        if self.max_failures == 0 {
+            return;
+        }
+        let mut failures = self.failures.lock().unwrap();
+        for key in keys(ip, user) {
+            failures.remove(&key);
+        }
+    }
+}
+
+fn keys(ip: Option<IpAddr>, user: Option<&str>) -> impl Iterator<Item = String> {
+    let ip = ip.map(|v| format!("ip:{v}"));
+    let user = user.map(|v| format!("user:{v}"));
+    ip.into_iter().chain(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_throttle() {
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let throttle = LoginThrottle::new(3, Duration::from_secs(60));
+        throttle.fail(Some(ip), Some("alice"));
+        throttle.fail(Some(ip), Some("bob"));
+        assert!(throttle.check(Some(ip), None).is_none());
+        throttle.fail(Some(ip), Some("alice"));
+        assert!(throttle.check(Some(ip), Some("carol")).is_some());
+        assert!(throttle.check(None, Some("bob")).is_none());
+        assert!(throttle.check(None, Some("alice")).is_none());
+        throttle.fail(None, Some("alice"));
+        assert!(throttle.check(None, Some("alice")).is_some());
+        throttle.fail(None, Some("alice"));
+        let retry_after = throttle.check(None, Some("alice")).unwrap();
+        assert!(retry_after > Duration::from_secs(1) && retry_after <= Duration::from_secs(2));
+        throttle.succeed(Some(ip), Some("alice"));
+        assert!(throttle.check(Some(ip), Some("alice")).is_none());
+
+        let throttle = LoginThrottle::new(0, Duration::from_secs(60));
+        for _ in 0..10 {
+            throttle.fail(Some(ip), None);
+        }
+        assert!(throttle.check(Some(ip), None).is_none());
+    }
+}
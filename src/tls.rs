@@ -0,0 +1,270 @@
+use crate::acme::{Acme, ACME_TLS_ALPN_NAME};
+use crate::args::AcmeChallenge;
+use crate::utils::{get_cert_dns_names, load_certs, load_private_key};
+use crate::Args;
+
+use anyhow::{anyhow, Context, Result};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_rustls::rustls::{
+    crypto::ring::sign::any_supported_type,
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+    RootCertStore, ServerConfig,
+};
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The TLS config of the listeners, with the certificates of `--tls-cert`/`--tls-key`
+/// or issued by ACME, and, with `--tls-client-ca`, the verifier of client certificates.
+pub fn create_tls_config(args: &Args, acme: Option<Arc<Acme>>) -> Result<ServerConfig> {
+    let pairs: Vec<(PathBuf, PathBuf)> = args
+        .tls_cert
+        .iter()
+        .cloned()
+        .zip(args.tls_key.iter().cloned())
+        .collect();
+    let tls_alpn = acme
+        .as_ref()
+        .is_some_and(|v| v.challenge() == AcmeChallenge::TlsAlpn01);
+    let resolver = Arc::new(CertResolver::new(&pairs, acme)?);
+    let builder = match &args.tls_client_ca {
+        Some(client_ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca_file)? {
+                roots.add(cert)?;
+            }
+            // Clients without a certificate can still log in otherwise
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .allow_unauthenticated()
+                .build()?;
+            ServerConfig::builder().with_client_cert_verifier(verifier)
+        }
+        None => ServerConfig::builder().with_no_client_auth(),
+    };
+    let mut config = builder.with_cert_resolver(resolver.clone());
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    if tls_alpn {
+        config.alpn_protocols.push(ACME_TLS_ALPN_NAME.to_vec());
+    }
+
+    if !pairs.is_empty() {
+        spawn_reloader(resolver)?;
+    }
+
+    Ok(config)
+}
+
+/// Check the files for changes every `RELOAD_INTERVAL`, and load them all again on
+/// SIGHUP. The files are read on the blocking pool, never during a handshake.
+fn spawn_reloader(resolver: Arc<CertResolver>) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangup =
+            signal(SignalKind::hangup()).with_context(|| "Failed to install SIGHUP handler")?;
+        let resolver = resolver.clone();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                let resolver = resolver.clone();
+                let _ = tokio::task::spawn_blocking(move || resolver.reload()).await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+        loop {
+            interval.tick().await;
+            let resolver = resolver.clone();
+            let _ = tokio::task::spawn_blocking(move || resolver.reload_changed()).await;
+        }
+    });
+    Ok(())
+}
+
+/// Picks a certificate by the SNI name of the client, the first one when none matches.
+/// The files are loaded again by `reload_changed` or `reload`. The ACME certificate
+/// serves the names without a matching pair.
+pub struct CertResolver {
+    pairs: RwLock<Vec<CertPair>>,
+    acme: Option<Arc<Acme>>,
+}
+
+struct CertPair {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    mtimes: [Option<SystemTime>; 2],
+    names: Vec<String>,
+    key: Arc<CertifiedKey>,
+}
+
+impl Debug for CertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let pairs = self.pairs.read().unwrap();
+        f.debug_struct("CertResolver")
+            .field(
+                "certs",
+                &pairs.iter().map(|v| &v.cert_file).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+impl CertResolver {
+    pub fn new(pairs: &[(PathBuf, PathBuf)], acme: Option<Arc<Acme>>) -> Result<Self> {
+        if pairs.is_empty() && acme.is_none() {
+            return Err(anyhow!("No tls-cert set"));
+        }
+        let pairs = pairs
+            .iter()
+            .map(|(cert_file, key_file)| CertPair::load(cert_file, key_file))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            pairs: RwLock::new(pairs),
+            acme,
+        })
+    }
+
+    /// Load all the files again. A broken pair keeps the previous certificate.
+    pub fn reload(&self) {
+        let mut pairs = self.pairs.write().unwrap();
+        for pair in pairs.iter_mut() {
+            pair.reload();
+        }
+        info!("Reloaded TLS certificates");
+    }
+
+    /// Load the pairs whose files changed since they were loaded.
+    fn reload_changed(&self) {
+        let changed = {
+            let pairs = self.pairs.read().unwrap();
+            pairs
+                .iter()
+                .any(|v| v.mtimes != CertPair::mtimes(&v.cert_file, &v.key_file))
+        };
+        if !changed {
+            return;
+        }
+        let mut pairs = self.pairs.write().unwrap();
+        for pair in pairs.iter_mut() {
+            if pair.mtimes != CertPair::mtimes(&pair.cert_file, &pair.key_file) {
+                pair.reload();
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let name = client_hello.server_name();
+        if let Some(acme) = &self.acme {
+            let is_challenge = client_hello
+                .alpn()
+                .is_some_and(|mut v| v.any(|v| v == ACME_TLS_ALPN_NAME));
+            if is_challenge {
+                return acme.alpn_cert(name?);
+            }
+        }
+        let pairs = self.pairs.read().unwrap();
+        if let Some(pair) = name.and_then(|name| pairs.iter().find(|v| v.matches(name))) {
+            return Some(pair.key.clone());
+        }
+        self.acme
+            .as_ref()
+            .and_then(|v| v.cert())
+            .or_else(|| pairs.first().map(|v| v.key.clone()))
+    }
+}
+
+impl CertPair {
+    fn load(cert_file: &Path, key_file: &Path) -> Result<Self> {
+        let mtimes = Self::mtimes(cert_file, key_file);
+        let certs = load_certs(cert_file)?;
+        let key = load_private_key(key_file)?;
+        let signing_key = any_supported_type(&key).map_err(|_| {
+            anyhow!(
+                "Unsupported private key in `{}`, expect an RSA, ECDSA or Ed25519 key",
+                key_file.display()
+            )
+        })?;
+        let names = get_cert_dns_names(&certs[0]);
+        Ok(Self {
+            cert_file: cert_file.to_path_buf(),
+            key_file: key_file.to_path_buf(),
+            mtimes,
+            names,
+            key: Arc::new(CertifiedKey::new(certs, signing_key)),
+        })
+    }
+
+    fn mtimes(cert_file: &Path, key_file: &Path) -> [Option<SystemTime>; 2] {
+        [cert_file, key_file].map(|v| std::fs::metadata(v).ok().and_then(|v| v.modified().ok()))
+    }
+
+    fn reload(&mut self) {
+        match Self::load(&self.cert_file, &self.key_file) {
+            Ok(pair) => *self = pair,
+            Err(err) => {
+                warn!(
+                    "Failed to reload TLS certificate `{}`, {err}",
+                    self.cert_file.display()
+                );
+                // Wait for the next change instead of retrying on every check
+                self.mtimes = Self::mtimes(&self.cert_file, &self.key_file);
+            }
+        }
+    }
+
+    /// Whether the certificate is for `name`, directly or by a `*.` wildcard.
+    fn matches(&self, name: &str) -> bool {
+        let name = name.to_ascii_lowercase();
+        self.names.iter().any(|v| match v.strip_prefix("*.") {
+            Some(domain) => name
+                .split_once('.')
+                .is_some_and(|(label, rest)| !label.is_empty() && rest == domain),
+            None => *v == name,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cert_resolver() {
+        let resolver = CertResolver::new(
+            &[
+                (
+                    "tests/data/cert.pem".into(),
+                    "tests/data/key_pkcs8.pem".into(),
+                ),
+                (
+                    "tests/data/cert_ecdsa.pem".into(),
+                    "tests/data/key_ecdsa.pem".into(),
+                ),
+            ],
+            None,
+        )
+        .unwrap();
+        resolver.reload();
+        let mut pairs = resolver.pairs.write().unwrap();
+        assert_eq!(pairs[0].names, ["localhost"]);
+        assert!(pairs[0].matches("LOCALHOST"));
+        assert!(!pairs[0].matches("example.com"));
+        pairs[1].names = vec!["*.example.com".into()];
+        assert!(pairs[1].matches("files.example.com"));
+        assert!(!pairs[1].matches("example.com"));
+        assert!(!pairs[1].matches("a.b.example.com"));
+
+        let err = CertResolver::new(
+            &[("tests/data/cert.pem".into(), "tests/data/cert.pem".into())],
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("No supported private key"));
+    }
+}
@@ -0,0 +1,258 @@
+use crate::auth::AccessPaths;
+
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use uuid::Uuid;
+
+const TOKEN_PREFIX: &str = "dufs_";
+const TOKEN_ID_LEN: usize = 12;
+
+/// An API token, only the sha-256 hash of the secret is kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub label: String,
+    pub hash: String,
+    pub paths: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+}
+
+/// What the admin endpoint shows of a token.
+#[derive(Debug, Serialize)]
+pub struct TokenInfo {
+    pub id: String,
+    pub label: String,
+    pub paths: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<DateTime<Utc>>,
+    pub managed: bool,
+}
+
+#[derive(Debug)]
+struct Entry {
+    token: ApiToken,
+    access_paths: AccessPaths,
+    managed: bool,
+}
+
+/// Tokens from the config file, plus the ones managed through the admin endpoint and
+/// saved in the token file.
+#[derive(Debug)]
+pub struct TokenStore {
+    file: Option<PathBuf>,
+    entries: RwLock<Vec<Entry>>,
+}
+
+impl TokenStore {
+    pub fn new(tokens: &[ApiToken], file: Option<&Path>) -> Result<Self> {
+        let mut entries = vec![];
+        for token in tokens {
+            entries.push(Entry::new(token.clone(), false)?);
+        }
+        if let Some(file) = file {
+            if file.exists() {
+                let contents = std::fs::read_to_string(file)
+                    .with_context(|| format!("Failed to read token file `{}`", file.display()))?;
+                let tokens: Vec<ApiToken> = serde_json::from_str(&contents)
+                    .with_context(|| format!("Failed to load token file `{}`", file.display()))?;
+                for token in tokens {
+                    entries.push(Entry::new(token, true)?);
+                }
+            }
+        }
+        Ok(Self {
+            file: file.map(|v| v.to_path_buf()),
+            entries: RwLock::new(entries),
+        })
+    }
+
+    /// Find the unexpired token matching `secret`, returns its label and paths.
+    pub fn verify(&self, secret: &str) -> Option<(String, AccessPaths)> {
+        let hash = hash_token(secret);
+        let now = Utc::now();
+        let entries = self.entries.read().unwrap();
+        let entry = entries.iter().find(|v| v.token.hash == hash)?;
+        if entry.token.expires.map(|v| v <= now).unwrap_or_default() {
+            return None;
+        }
+        Some((entry.token.label.clone(), entry.access_paths.clone()))
+    }
+
+    pub fn list(&self) -> Vec<TokenInfo> {
+        self.entries
+            .read()
+            .unwrap()
+            .iter()
+            .map(|entry| TokenInfo {
+                id: token_id(&entry.token.hash),
+                label: entry.token.label.clone(),
+                paths: entry.token.paths.clone(),
+                expires: entry.token.expires,
+                created: entry.token.created,
+                managed: entry.managed,
+            })
+            .collect()
+    }
+
+    /// Mint a token and save it to the token file, returns the secret with its info.
+    pub fn create(
+        &self,
+        label: &str,
+        paths: &str,
+        expires: Option<DateTime<Utc>>,
+    ) -> Result<(String, TokenInfo)> {
+        if self.file.is_none() {
+            bail!("No token file to save tokens");
+        }
+        let secret = format!(
+            "{TOKEN_PREFIX}{}{}",
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        );
+        let token = ApiToken {
+            label: label.to_string(),
+            hash: hash_token(&secret),
+            paths: paths.to_string(),
+            expires,
+            created: Some(Utc::now()),
+        };
+        let entry = Entry::new(token, true)?;
+        let info = TokenInfo {
+            id: token_id(&entry.token.hash),
+            label: entry.token.label.clone(),
+            paths: entry.token.paths.clone(),
+            expires: entry.token.expires,
+            created: entry.token.created,
+            managed: true,
+        };
+        let mut entries = self.entries.write().unwrap();
+        entries.push(entry);
+        if let Err(err) = self.save(&entries) {
+            entries.pop();
+            return Err(err);
+        }
+        Ok((secret, info))
+    }
+
+    /// Revoke a managed token, returns false if there is no such token.
+    pub fn remove(&self, id: &str) -> Result<bool> {
+        let mut entries = self.entries.write().unwrap();
+        let Some(index) = entries.iter().position(|v| token_id(&v.token.hash) == id) else {
+            return Ok(false);
+        };
+        if !entries[index].managed {
+            bail!("Token `{id}` is set in the config file");
+        }
+        let entry = entries.remove(index);
+        if let Err(err) = self.save(&entries) {
+            entries.insert(index, entry);
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    fn save(&self, entries: &[Entry]) -> Result<()> {
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+        let tokens: Vec<&ApiToken> = entries
+            .iter()
+            .filter(|v| v.managed)
+            .map(|v| &v.token)
+            .collect();
+        let contents = serde_json::to_string_pretty(&tokens)?;
+        let tmp_file = file.with_extension(format!("{}.tmp", Uuid::new_v4().simple()));
+        std::fs::write(&tmp_file, contents)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tmp_file, std::fs::Permissions::from_mode(0o600))?;
+        }
+        std::fs::rename(&tmp_file, file).map_err(|err| {
+            let _ = std::fs::remove_file(&tmp_file);
+            anyhow!("Failed to save token file `{}`, {err}", file.display())
+        })
+    }
+}
+
+impl Entry {
+    fn new(token: ApiToken, managed: bool) -> Result<Self> {
+        if token.label.is_empty() {
+            bail!("Invalid token, no label");
+        }
+        if token.hash.len() != 64 || !token.hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("Invalid token `{}`, expect a sha-256 hex hash", token.label);
+        }
+        let mut access_paths = AccessPaths::default();
+        if !token.paths.starts_with('/') || access_paths.merge(&token.paths).is_none() {
+            bail!("Invalid token `{}` paths `{}`", token.label, token.paths);
+        }
+        Ok(Self {
+            token: ApiToken {
+                hash: token.hash.to_ascii_lowercase(),
+                ..token
+            },
+            access_paths,
+            managed,
+        })
+    }
+}
+
+fn hash_token(secret: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(secret.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|v| format!("{v:02x}"))
+        .collect()
+}
+
+fn token_id(hash: &str) -> String {
+    hash[..TOKEN_ID_LEN].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_store() {
+        let tokens = [ApiToken {
+            label: "ci".into(),
+            hash: hash_token("secret"),
+            paths: "/dir1:rw".into(),
+            expires: None,
+            created: None,
+        }];
+        let store = TokenStore::new(&tokens, None).unwrap();
+        let (label, paths) = store.verify("secret").unwrap();
+        assert_eq!(label, "ci");
+        assert!(paths.find("dir1/file", true).is_some());
+        assert!(paths.find("dir2/file", false).is_none());
+        assert!(store.verify("secret2").is_none());
+        assert!(store.create("ci2", "/:rw", None).is_err());
+        assert!(store.remove(&token_id(&hash_token("secret"))).is_err());
+    }
+
+    #[test]
+    fn test_token_expires() {
+        let tokens = [ApiToken {
+            label: "ci".into(),
+            hash: hash_token("secret"),
+            paths: "/".into(),
+            expires: Some(Utc::now() - chrono::Duration::seconds(1)),
+            created: None,
+        }];
+        let store = TokenStore::new(&tokens, None).unwrap();
+        assert!(store.verify("secret").is_none());
+    }
+}
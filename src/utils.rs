@@ -12,6 +12,7 @@ use std::{
 use url::Url;
 use reqwest::Client;
 use std::io::{self, BufRead, BufReader, Write};
+use tokio::sync::watch;
 
 // Function to rewrite the M3U8 file with local segment paths
 pub fn write_m3u8(content: &str, output_file: &str) -> io::Result<()> {
@@ -229,6 +230,12 @@ pub fn check_file_exist(file_path: &str) -> bool {
     std::fs::metadata(file_path).is_ok()
 }
 
+/// Resolves once the server starts shutting down. Every receiver still alive holds up
+/// the end of the shutdown, so only in-flight work should keep one.
+pub async fn shutting_down(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|v| *v).await;
+}
+
 pub fn unix_now() -> Result<Duration> {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -296,7 +303,10 @@ pub fn load_certs<T: AsRef<Path>>(filename: T) -> Result<Vec<CertificateDer<'sta
         certs.push(cert)
     }
     if certs.is_empty() {
-        anyhow::bail!("No supported certificate in file");
+        anyhow::bail!(
+            "No supported certificate in `{}`, expect PEM certificates",
+            filename.as_ref().display()
+        );
     }
     Ok(certs)
 }
@@ -310,7 +320,12 @@ pub fn load_private_key<T: AsRef<Path>>(filename: T) -> Result<PrivateKeyDer<'st
 
     // Load and return a single private key.
     for key in rustls_pemfile::read_all(&mut reader) {
-        let key = key.with_context(|| "There was a problem with reading private key")?;
+        let key = key.with_context(|| {
+            format!(
+                "There was a problem with reading private key `{}`",
+                filename.as_ref().display()
+            )
+        })?;
         match key {
             rustls_pemfile::Item::Pkcs1Key(key) => return Ok(PrivateKeyDer::Pkcs1(key)),
             rustls_pemfile::Item::Pkcs8Key(key) => return Ok(PrivateKeyDer::Pkcs8(key)),
@@ -318,7 +333,66 @@ pub fn load_private_key<T: AsRef<Path>>(filename: T) -> Result<PrivateKeyDer<'st
             _ => {}
         }
     }
-    anyhow::bail!("No supported private key in file");
+    anyhow::bail!(
+        "No supported private key in `{}`, expect a PKCS#8, RSA or SEC1 PEM key",
+        filename.as_ref().display()
+    );
+}
+
+/// The names a client certificate may log a user in with: the subject common name, then
+/// the email, DNS and URI subject alternative names.
+#[cfg(feature = "tls")]
+pub fn get_cert_names(cert: &CertificateDer) -> Vec<String> {
+    use x509_parser::extensions::GeneralName;
+
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(cert) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = cert
+        .subject()
+        .iter_common_name()
+        .filter_map(|v| v.as_str().ok())
+        .map(|v| v.to_string())
+        .collect();
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in san.value.general_names.iter() {
+            match name {
+                GeneralName::RFC822Name(v) | GeneralName::DNSName(v) | GeneralName::URI(v) => {
+                    names.push(v.to_string())
+                }
+                _ => {}
+            }
+        }
+    }
+    names
+}
+
+/// The host names a certificate is for, to pick it by SNI.
+#[cfg(feature = "tls")]
+pub fn get_cert_dns_names(cert: &CertificateDer) -> Vec<String> {
+    use x509_parser::extensions::GeneralName;
+
+    let Ok((_, cert)) = x509_parser::parse_x509_certificate(cert) else {
+        return vec![];
+    };
+    let mut names = vec![];
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in san.value.general_names.iter() {
+            if let GeneralName::DNSName(v) = name {
+                names.push(v.to_ascii_lowercase())
+            }
+        }
+    }
+    // The common name only counts without alternative names
+    if names.is_empty() {
+        names.extend(
+            cert.subject()
+                .iter_common_name()
+                .filter_map(|v| v.as_str().ok())
+                .map(|v| v.to_ascii_lowercase()),
+        );
+    }
+    names
 }
 
 pub fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
@@ -392,4 +466,11 @@ mod tests {
         assert_eq!(parse_range("bytes=-501", 500), None);
         assert_eq!(parse_range("bytes=0-500", 500), None);
     }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_get_cert_names() {
+        let certs = load_certs("tests/data/client_identity.pem").unwrap();
+        assert_eq!(get_cert_names(&certs[0]), ["alice", "alice@example.com"]);
+    }
 }
@@ -0,0 +1,147 @@
+use crate::journal::{ChangeJournal, ChangeKind};
+
+use anyhow::{Context, Result};
+use notify::event::{AccessKind, AccessMode, DataChange, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Writes to a path closer together than this are one change, where the platform
+/// doesn't tell when the writer is done.
+const WRITE_COALESCE: Duration = Duration::from_secs(1);
+const WRITE_COALESCE_MAX_PATHS: usize = 4096;
+/// Inotify reports when a file opened for writing is closed.
+const CLOSE_WRITE_EVENTS: bool = cfg!(any(target_os = "linux", target_os = "android"));
+
+/// Watch the serve path and record changes made outside dufs into the journal.
+pub struct FsWatcher {
+    _inner: RecommendedWatcher,
+}
+
+impl FsWatcher {
+    pub fn new(root: &Path, journal: Arc<ChangeJournal>) -> Result<Self> {
+        let base = root.to_path_buf();
+        let mut writes = WriteFilter::default();
+        let mut watcher =
+            notify::recommended_watcher(move |ret: notify::Result<Event>| match ret {
+                Ok(event) => handle_event(&base, &journal, &mut writes, event),
+                Err(err) => warn!("Failed to watch, {err}"),
+            })
+            .with_context(|| "Failed to create file watcher")?;
+        watcher
+            .watch(root, RecursiveMode::Recursive)
+            .with_context(|| format!("Failed to watch `{}`", root.display()))?;
+        Ok(Self { _inner: watcher })
+    }
+}
+
+fn handle_event(base: &Path, journal: &ChangeJournal, writes: &mut WriteFilter, event: Event) {
+    let paths: Vec<String> = event
+        .paths
+        .iter()
+        .filter_map(|v| to_relative_path(base, v))
+        .collect();
+    let kind = match event.kind {
+        EventKind::Create(_) => ChangeKind::Create,
+        EventKind::Remove(_) => ChangeKind::Delete,
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+            journal.record(ChangeKind::Move(paths[0].clone()), &paths[1]);
+            return;
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => ChangeKind::Delete,
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => ChangeKind::Create,
+        EventKind::Modify(ModifyKind::Metadata(_)) => return,
+        // Every chunk written is an event, the file changed once
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) if CLOSE_WRITE_EVENTS => {
+            ChangeKind::Modify
+        }
+        EventKind::Access(_) => return,
+        EventKind::Modify(ModifyKind::Data(DataChange::Any | DataChange::Content))
+            if CLOSE_WRITE_EVENTS =>
+        {
+            return
+        }
+        EventKind::Modify(_) => {
+            for path in paths.iter().filter(|v| writes.is_new(v)) {
+                journal.record(ChangeKind::Modify, path);
+            }
+            return;
+        }
+        _ => return,
+    };
+    for path in paths.iter() {
+        journal.record(kind.clone(), path);
+    }
+}
+
+/// The paths being written, with the time of their last write.
+#[derive(Debug, Default)]
+struct WriteFilter {
+    paths: HashMap<String, Instant>,
+}
+
+impl WriteFilter {
+    /// Whether a write to `path` starts a new change, rather than continuing one.
+    fn is_new(&mut self, path: &str) -> bool {
+        let now = Instant::now();
+        if self.paths.len() >= WRITE_COALESCE_MAX_PATHS {
+            self.paths
+                .retain(|_, v| now.duration_since(*v) < WRITE_COALESCE);
+        }
+        match self.paths.insert(path.to_string(), now) {
+            Some(last) => now.duration_since(last) >= WRITE_COALESCE,
+            None => true,
+        }
+    }
+}
+
+fn to_relative_path(base: &Path, path: &Path) -> Option<String> {
+    let path = path.strip_prefix(base).ok()?.to_str()?;
+    if path.is_empty() {
+        return None;
+    }
+    if cfg!(windows) {
+        Some(path.replace('\\', "/"))
+    } else {
+        Some(path.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_watch_chunked_write() {
+        let tmpdir = assert_fs::TempDir::new().unwrap();
+        let root = tmpdir.path().canonicalize().unwrap();
+        std::fs::write(root.join("file1"), b"").unwrap();
+        let journal = Arc::new(ChangeJournal::default());
+        let _watcher = FsWatcher::new(&root, journal.clone()).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let token = journal.token();
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(root.join("file1"))
+            .unwrap();
+        for _ in 0..50 {
+            file.write_all(&[0; 1024]).unwrap();
+            file.flush().unwrap();
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        drop(file);
+        std::thread::sleep(Duration::from_millis(500));
+
+        let changes = journal.since(&token).unwrap();
+        let modified: Vec<_> = changes
+            .iter()
+            .filter(|v| v.kind == ChangeKind::Modify)
+            .map(|v| v.path.as_str())
+            .collect();
+        assert_eq!(modified, ["file1"]);
+    }
+}
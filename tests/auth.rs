@@ -240,6 +240,22 @@ fn auth_webdav_copy(
     Ok(())
 }
 
+#[rstest]
+fn auth_webdav_move_digest(
+    #[with(&["--auth", "user:pass@/:rw", "--auth", "user3:pass3@/dir1:rw", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let origin_url = format!("{}dir1/test.html", server.url());
+    let new_url = format!("{}dir1/test2.html", server.url());
+    let resp = fetch!(b"MOVE", &origin_url)
+        .header("Destination", &new_url)
+        .send_with_digest_auth("user3", "pass3")?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"GET", &new_url).send_with_digest_auth("user3", "pass3")?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
 #[rstest]
 fn auth_path_prefix(
     #[with(&["--auth", "user:pass@/:rw", "--path-prefix", "xyz", "-A"])] server: TestServer,
@@ -333,3 +349,387 @@ fn auth_precedence(
 
     Ok(())
 }
+
+#[rstest]
+#[case("user1", "pass")]
+#[case("user2", "pass")]
+fn auth_htpasswd(
+    #[with(&["--auth-htpasswd", "tests/data/htpasswd", "-A"])] server: TestServer,
+    #[case] user: &str,
+    #[case] pass: &str,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url()).send()?;
+    assert_eq!(resp.status(), 401);
+    let values: Vec<&str> = resp
+        .headers()
+        .get_all("www-authenticate")
+        .iter()
+        .map(|v| v.to_str().unwrap())
+        .collect();
+    assert_eq!(values, ["Basic realm=\"DUFS\""]);
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth(user, Some("wrong"))
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth(user, Some(pass))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn auth_htpasswd_rules(
+    #[with(&["--auth-htpasswd", "tests/data/htpasswd", "-a", "user1@/dir1:rw", "-a", "@/", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url()).send()?;
+    assert_eq!(resp.status(), 200);
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user1", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user1", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn auth_users_file(
+    #[with(&["--auth-users-file", "tests/data/users.yaml", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("admin", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("guest", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let url = format!("{}dir1/test.html", server.url());
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("guest", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let url = format!("{}test.html", server.url());
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("guest", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn auth_users_file_reload() -> Result<(), Error> {
+    let dir = assert_fs::TempDir::new()?;
+    let users_file = dir.path().join("users.json");
+    std::fs::write(
+        &users_file,
+        r#"{"users":[{"name":"user","password":"pass","paths":"/:rw"}]}"#,
+    )?;
+    let server = server(&["--auth-users-file", users_file.to_str().unwrap(), "-A"]);
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    std::fs::write(
+        &users_file,
+        r#"{"users":[{"name":"user","password":"pass2","paths":"/:rw"}]}"#,
+    )?;
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass2"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[cfg(unix)]
+#[rstest]
+fn auth_command(
+    #[with(&["--auth-command", "tests/data/auth_command.sh", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("cmd", Some("wrong"))
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("cmd", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("cmd", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn auth_groups(
+    #[with(&["-a", "+devs=user1@/dir1:rw", "-a", "user1:pass@/", "-a", "user2:pass@/", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user2", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user1", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    Ok(())
+}
+
+#[rstest]
+fn auth_groups_user_rule_narrows(
+    #[with(&["-a", "+devs=user1@/dir1:rw", "-a", "user1:pass@/dir1:ro", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user1", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{}dir1/", server.url()))
+        .basic_auth("user1", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn auth_role_uploader(
+    #[with(&["-a", "user:pass@/:uploader", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"DELETE", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    let content = resp.text()?;
+    let json = utils::retrive_json(&content).unwrap();
+    assert_eq!(json["allow_delete"], serde_json::Value::Bool(false));
+    assert_eq!(json["allow_upload"], serde_json::Value::Bool(true));
+    Ok(())
+}
+
+#[rstest]
+fn auth_deny(
+    #[with(&["-a", "user:pass@/:rw,/dir1:deny", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/test.html", server.url());
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{}?json", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    let names: Vec<&str> = json["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|v| v["name"].as_str())
+        .collect();
+    assert!(names.contains(&"dir2"));
+    assert!(!names.contains(&"dir1"));
+    Ok(())
+}
+
+#[rstest]
+fn auth_deny_inside(
+    #[with(&["-a", "user:pass@/:rw,/dir1/test.html:deny", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1", server.url());
+    let resp = fetch!(b"DELETE", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"MOVE", &url)
+        .header("Destination", format!("{}dir3", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"MOVE", format!("{}dir2", server.url()))
+        .header("Destination", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(server.path().join("dir1/test.html").exists());
+    let resp = fetch!(b"DELETE", format!("{}dir2", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    Ok(())
+}
+
+#[rstest]
+fn auth_caps(
+    #[with(&["-a", "user:pass@/:read+list,/dir1:read+list+upload-new+delete", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", format!("{}?zip", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 404);
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"PATCH", &url)
+        .header("X-Update-Range", "append")
+        .body(b"abc".to_vec())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"MOVE", &url)
+        .header("Destination", format!("{}dir1/file2", server.url()))
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"DELETE", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    Ok(())
+}
+
+#[rstest]
+fn auth_lockout(
+    #[with(&["-a", "user:pass@/:rw", "--auth-max-failures", "2", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    for _ in 0..2 {
+        let resp = fetch!(b"GET", server.url())
+            .basic_auth("user", Some("wrong"))
+            .send()?;
+        assert_eq!(resp.status(), 401);
+    }
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 429);
+    assert_eq!(resp.headers().get("retry-after").unwrap(), "1");
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn auth_deny_ip(
+    #[with(&["--deny-ip", "127.0.0.1,::1", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url()).send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn auth_rule_ip(
+    #[with(&["-a", "user:pass@/:rw#10.0.0.0/8,!10.0.0.5", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url())
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    // Forwarded headers are ignored from untrusted peers
+    let resp = fetch!(b"GET", server.url())
+        .header("X-Forwarded-For", "10.1.2.3")
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn auth_rule_ip_trusted_proxy(
+    #[with(&[
+        "-a",
+        "user:pass@/:rw#10.0.0.0/8,!10.0.0.5",
+        "--trusted-proxy",
+        "127.0.0.1,::1",
+        "-A",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url())
+        .header("X-Forwarded-For", "10.1.2.3")
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", server.url())
+        .header("Forwarded", "for=10.0.0.5")
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
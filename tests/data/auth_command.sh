@@ -0,0 +1,8 @@
+#!/bin/sh
+# Accept cmd:pass with write access to /dir1
+read -r pass
+if [ "$DUFS_AUTH_USER" = "cmd" ] && [ "$pass" = "pass" ]; then
+  echo "/dir1:rw"
+  exit 0
+fi
+exit 1
@@ -2,4 +2,10 @@
 openssl req -subj '/CN=localhost' -x509 -newkey rsa:4096 -keyout key_pkcs8.pem -out cert.pem -nodes -days 3650
 openssl rsa -in key_pkcs8.pem -out key_pkcs1.pem
 openssl ecparam -name prime256v1 -genkey -noout -out key_ecdsa.pem
-openssl req -subj '/CN=localhost' -x509 -key key_ecdsa.pem -out cert_ecdsa.pem -nodes -days 3650
\ No newline at end of file
+openssl req -subj '/CN=localhost' -x509 -key key_ecdsa.pem -out cert_ecdsa.pem -nodes -days 3650
+openssl req -subj '/CN=dufs test client CA' -x509 -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -keyout client_ca_key.pem -out client_ca.pem -nodes -days 3650 -addext basicConstraints=critical,CA:TRUE -addext keyUsage=critical,keyCertSign,cRLSign
+openssl req -subj '/CN=alice' -newkey ec -pkeyopt ec_paramgen_curve:prime256v1 -keyout client_key.pem -out client.csr -nodes
+printf 'basicConstraints=CA:FALSE\nkeyUsage=critical,digitalSignature\nextendedKeyUsage=clientAuth\nsubjectAltName=email:alice@example.com\n' > client.ext
+openssl x509 -req -in client.csr -CA client_ca.pem -CAkey client_ca_key.pem -CAcreateserial -out client_cert.pem -days 3650 -extfile client.ext
+cat client_cert.pem client_key.pem > client_identity.pem
+rm client.csr client.ext client_ca_key.pem client_ca.srl client_cert.pem client_key.pem
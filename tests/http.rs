@@ -4,6 +4,7 @@ mod utils;
 use fixtures::{server, Error, TestServer, BIN_FILE};
 use rstest::rstest;
 use serde_json::Value;
+use std::io::{BufRead, BufReader};
 use utils::retrive_edit_file;
 
 #[rstest]
@@ -82,6 +83,280 @@ fn get_dir_simple(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+#[case("")]
+#[case("&sort=mtime&order=desc")]
+#[case("&sort=size")]
+fn get_dir_json_paginated(
+    #[with(&["-A"])] server: TestServer,
+    #[case] sort: &str,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?json{sort}", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    assert!(json.get("next_cursor").is_none());
+    let expect: Vec<Value> = json["paths"].as_array().unwrap().clone();
+    let mut paths: Vec<Value> = vec![];
+    let mut cursor = String::new();
+    loop {
+        let resp = reqwest::blocking::get(format!(
+            "{}?json&limit=3&cursor={cursor}{sort}",
+            server.url()
+        ))?;
+        assert_eq!(resp.status(), 200);
+        let json: Value = serde_json::from_str(&resp.text()?)?;
+        let page = json["paths"].as_array().unwrap();
+        assert!(page.len() <= 3);
+        paths.extend(page.iter().cloned());
+        match json["next_cursor"].as_str() {
+            Some(v) => cursor = v.to_string(),
+            None => break,
+        }
+    }
+    assert_eq!(paths, expect);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_paginated_after_change(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let list = || -> Result<Vec<String>, Error> {
+        let mut names = vec![];
+        let mut cursor = String::new();
+        loop {
+            let url = format!("{}?json&limit=3&cursor={cursor}", server.url());
+            let json: Value = serde_json::from_str(&reqwest::blocking::get(url)?.text()?)?;
+            for item in json["paths"].as_array().unwrap() {
+                names.push(item["name"].as_str().unwrap().to_string());
+            }
+            match json["next_cursor"].as_str() {
+                Some(v) => cursor = v.to_string(),
+                None => break,
+            }
+        }
+        Ok(names)
+    };
+    let before = list()?;
+    std::fs::write(server.path().join("new-file"), "")?;
+    let after = list()?;
+    assert_eq!(after.len(), before.len() + 1);
+    assert!(after.iter().any(|v| v == "new-file"));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_simple_paginated(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?simple&limit=2", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let cursor = resp.headers().get("x-next-cursor").unwrap().clone();
+    assert_eq!(resp.text()?.lines().count(), 2);
+    let resp = reqwest::blocking::get(format!(
+        "{}?simple&limit=2&cursor={}",
+        server.url(),
+        cursor.to_str()?
+    ))?;
+    assert_eq!(resp.text()?.lines().count(), 2);
+    let resp = reqwest::blocking::get(format!("{}?simple&cursor=invalid", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_ndjson(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?ndjson", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/x-ndjson"
+    );
+    let mut names = resp
+        .text()?
+        .lines()
+        .map(|v| serde_json::from_str::<Value>(v).unwrap()["name"].clone())
+        .collect::<Vec<_>>();
+    let resp = reqwest::blocking::get(format!("{}?json", server.url()))?;
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    let mut expect = json["paths"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|v| v["name"].clone())
+        .collect::<Vec<_>>();
+    names.sort_by_key(|v| v.to_string());
+    expect.sort_by_key(|v| v.to_string());
+    assert_eq!(names, expect);
+    Ok(())
+}
+
+#[rstest]
+#[case("tar", "application/x-tar", &[b'u', b's', b't', b'a', b'r'][..], 257)]
+#[case("tar.gz", "application/gzip", &[0x1f, 0x8b][..], 0)]
+#[case("tar.zst", "application/zstd", &[0x28, 0xb5, 0x2f, 0xfd][..], 0)]
+fn get_dir_archive(
+    #[with(&["-A", "--hidden", "*.txt"])] server: TestServer,
+    #[case] format: &str,
+    #[case] content_type: &str,
+    #[case] magic: &[u8],
+    #[case] magic_offset: usize,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/?archive={format}", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), content_type);
+    assert!(resp.headers()["content-disposition"]
+        .to_str()?
+        .contains(&format!("dir1.{format}")));
+    let body = resp.bytes()?;
+    assert_eq!(&body[magic_offset..magic_offset + magic.len()], magic);
+    if format == "tar" {
+        let entries = utils::retrieve_tar_entries(&body);
+        assert!(entries.iter().any(|(name, ..)| name == "test.html"));
+        assert!(!entries.iter().any(|(name, ..)| name.ends_with(".txt")));
+    }
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_archive_tar_gz_stored(
+    #[with(&["-A", "--compress", "none"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}dir1/?archive=tar.gz", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let body = resp.bytes()?;
+    assert_eq!(&body[..2], &[0x1f, 0x8b]);
+    assert!(body.windows(5).any(|v| v == b"ustar"));
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_archive_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?archive=rar", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_zip_stored(
+    #[with(&["-A", "--compress", "none"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/?zip", server.url());
+    let resp = reqwest::blocking::get(&url)?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("accept-ranges").unwrap(), "bytes");
+    let etag = resp.headers().get("etag").unwrap().clone();
+    let size: usize = resp.headers()["content-length"].to_str()?.parse()?;
+    let body = resp.bytes()?;
+    assert_eq!(body.len(), size);
+    assert_eq!(&body[..4], b"PK\x03\x04");
+
+    let resp = fetch!(b"GET", &url).header("range", "bytes=100-").send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(
+        resp.headers().get("content-range").unwrap(),
+        &format!("bytes 100-{}/{size}", size - 1)
+    );
+    assert_eq!(resp.bytes()?, body[100..]);
+
+    let resp = fetch!(b"GET", &url)
+        .header("range", "bytes=10-19")
+        .header("if-range", &etag)
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.bytes()?, body[10..20]);
+
+    let resp = fetch!(b"GET", &url)
+        .header("range", "bytes=10-19")
+        .header("if-range", r#""stale""#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.bytes()?, body);
+
+    let resp = fetch!(b"GET", &url)
+        .header("if-none-match", &etag)
+        .send()?;
+    assert_eq!(resp.status(), 304);
+
+    let resp = fetch!(b"GET", &url)
+        .header("range", format!("bytes={size}-"))
+        .send()?;
+    assert_eq!(resp.status(), 416);
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_zip_stored_param(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"HEAD", format!("{}dir1/?zip&stored", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers().contains_key("content-length"));
+    assert!(resp.headers().contains_key("etag"));
+    let resp = fetch!(b"HEAD", format!("{}dir1/?zip", server.url())).send()?;
+    assert!(!resp.headers().contains_key("etag"));
+    Ok(())
+}
+
+#[rstest]
+fn post_archive_paths(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?archive=tar", server.url()))
+        .body(r#"["dir1/test.html", "dir2/", "index.html"]"#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/x-tar");
+    let entries = utils::retrieve_tar_entries(&resp.bytes()?);
+    let names: Vec<&str> = entries.iter().map(|(name, ..)| name.as_str()).collect();
+    assert!(names.contains(&"dir1/test.html"));
+    assert!(names.contains(&"dir2/test.html"));
+    assert!(names.contains(&"index.html"));
+    assert!(!names.contains(&"dir1/index.html"));
+    assert!(!names.iter().any(|v| v.starts_with("dir3")));
+
+    let resp = fetch!(b"POST", format!("{}?zip", server.url()))
+        .body(r#"["dir1/test.html", "dir2"]"#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers().get("content-type").unwrap(), "application/zip");
+    assert_eq!(&resp.bytes()?[..4], b"PK\x03\x04");
+    Ok(())
+}
+
+#[rstest]
+#[case(r#"["../dir1"]"#, 400)]
+#[case(r#"["dir1/../dir2"]"#, 400)]
+#[case(r#"[]"#, 400)]
+#[case(r#"{"path": "dir1"}"#, 400)]
+#[case(r#"["dir-no-found/test.html"]"#, 404)]
+fn post_archive_paths_invalid(
+    #[with(&["-A"])] server: TestServer,
+    #[case] body: &'static str,
+    #[case] status: u16,
+) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?archive", server.url()))
+        .body(body)
+        .send()?;
+    assert_eq!(resp.status(), status);
+    Ok(())
+}
+
+#[rstest]
+fn post_archive_paths_forbidden(
+    #[with(&["-a", "@/dir1,/dir2", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?archive=tar", server.url()))
+        .body(r#"["dir1/test.html", "dir2"]"#)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"POST", format!("{}?archive=tar", server.url()))
+        .body(r#"["dir1/test.html", "dir3/test.html"]"#)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn post_archive_not_allowed(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"POST", format!("{}?archive", server.url()))
+        .body(r#"["dir1"]"#)
+        .send()?;
+    assert_eq!(resp.status(), 405);
+    Ok(())
+}
+
 #[rstest]
 fn head_dir_zip(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"HEAD", format!("{}?zip", server.url())).send()?;
@@ -137,6 +412,64 @@ fn get_dir_search4(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_search_content(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!(
+        "{}dir1/?q={}&content=1&json",
+        server.url(),
+        "THIS IS DIR1/TEST.HTML"
+    ))?;
+    assert_eq!(resp.status(), 200);
+    let json: Value = serde_json::from_str(&resp.text()?)?;
+    let paths = json["paths"].as_array().unwrap();
+    assert_eq!(paths.len(), 1);
+    assert_eq!(paths[0]["name"], "test.html");
+    assert_eq!(paths[0]["matches"][0]["line"], 1);
+    assert_eq!(paths[0]["matches"][0]["text"], "This is dir1/test.html");
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_content_skip_binary(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?q={}&content=1&simple", server.url(), "123"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "");
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_query(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!(
+        "{}?q={}&simple",
+        server.url(),
+        urlencoding::encode("name:*.HTML type:file mtime:<1d size:>0")
+    ))?;
+    assert_eq!(resp.status(), 200);
+    let text = resp.text()?;
+    assert!(text.lines().any(|v| v == "dir1/test.html"));
+    assert!(text.lines().all(|v| v.ends_with(".html")));
+
+    let resp = reqwest::blocking::get(format!("{}?q=dir&type=dir&simple", server.url()))?;
+    let text = resp.text()?;
+    assert!(text.lines().any(|v| v == "dir1/"));
+    assert!(text.lines().all(|v| v.ends_with('/')));
+
+    let resp = reqwest::blocking::get(format!(
+        "{}dir1/?q={}&sort=size&order=desc&simple",
+        server.url(),
+        urlencoding::encode(r"regex:^test\.")
+    ))?;
+    assert_eq!(resp.text()?, "test.html\ntest.txt\n");
+    Ok(())
+}
+
+#[rstest]
+fn get_dir_search_query_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}?q=size:>1X", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
 #[rstest]
 fn head_dir_search(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let resp = fetch!(b"HEAD", format!("{}?q={}", server.url(), "test.html")).send()?;
@@ -156,6 +489,46 @@ fn empty_search(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     Ok(())
 }
 
+#[rstest]
+fn get_dir_events(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?
+        .get(format!("{}dir1/?events", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+    let resp2 = fetch!(b"PUT", format!("{}dir1/file-events", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp2.status(), 201);
+    let line = BufReader::new(resp)
+        .lines()
+        .map_while(|v| v.ok())
+        .find(|v| v.starts_with("data: "))
+        .unwrap();
+    let event: Value = serde_json::from_str(line.trim_start_matches("data: "))?;
+    assert_eq!(event["kind"], "create");
+    assert_eq!(event["name"], "file-events");
+    assert_eq!(event["item"]["size"], 3);
+    Ok(())
+}
+
+#[rstest]
+fn head_dir_events(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"HEAD", format!("{}?events", server.url())).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "text/event-stream"
+    );
+    assert_eq!(resp.text()?, "");
+    Ok(())
+}
+
 #[rstest]
 fn get_file(server: TestServer) -> Result<(), Error> {
     let resp = reqwest::blocking::get(format!("{}index.html", server.url()))?;
@@ -250,7 +623,7 @@ fn options_dir(server: TestServer) -> Result<(), Error> {
     assert_eq!(resp.status(), 200);
     assert_eq!(
         resp.headers().get("allow").unwrap(),
-        "GET,HEAD,PUT,OPTIONS,DELETE,PATCH,PROPFIND,COPY,MOVE"
+        "GET,HEAD,PUT,OPTIONS,DELETE,PATCH,PROPFIND,COPY,MOVE,REPORT"
     );
     assert_eq!(
         resp.headers().get("dav").unwrap(),
@@ -287,6 +660,204 @@ fn put_file_conflict_dir(#[with(&["-A"])] server: TestServer) -> Result<(), Erro
     Ok(())
 }
 
+#[rstest]
+fn put_extract_tar(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let data = utils::build_tar(&[("a/b.txt", b"abc"), ("c.txt", b"def")]);
+    let resp = fetch!(b"PUT", format!("{}xyz/?extract", server.url()))
+        .body(data)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let results: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(results[0]["status"], "created");
+    assert_eq!(results[1]["path"], "c.txt");
+    let resp = reqwest::blocking::get(format!("{}xyz/a/b.txt", server.url()))?;
+    assert_eq!(resp.text()?, "abc");
+    let resp = reqwest::blocking::get(format!("{}xyz/c.txt", server.url()))?;
+    assert_eq!(resp.text()?, "def");
+    Ok(())
+}
+
+#[rstest]
+fn put_extract_zip(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let data = reqwest::blocking::get(format!("{}dir1?zip", server.url()))?.bytes()?;
+    let resp = fetch!(b"PUT", format!("{}xyz/dir1.zip?extract", server.url()))
+        .body(data.to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = reqwest::blocking::get(format!("{}xyz/test.html", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let resp = reqwest::blocking::get(format!("{}xyz/dir1.zip", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn put_extract_zip_slip(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let data = utils::build_tar(&[("../evil.txt", b"abc"), ("/abs.txt", b"abc")]);
+    let resp = fetch!(b"PUT", format!("{}dir1/?extract", server.url()))
+        .body(data)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let results: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(results[0]["status"], "failed");
+    assert_eq!(results[1]["status"], "failed");
+    let resp = reqwest::blocking::get(format!("{}evil.txt", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+#[case("skip", "skipped", "test.html", "This is dir1/test.html")]
+#[case("overwrite", "overwritten", "test.html", "new")]
+#[case("rename", "renamed", "test (1).html", "new")]
+fn put_extract_conflict(
+    #[with(&["-A"])] server: TestServer,
+    #[case] conflict: &str,
+    #[case] status: &str,
+    #[case] name: &str,
+    #[case] content: &str,
+) -> Result<(), Error> {
+    let data = utils::build_tar(&[("test.html", b"new")]);
+    let resp = fetch!(
+        b"POST",
+        format!("{}dir1/?extract&conflict={conflict}", server.url())
+    )
+    .body(data)
+    .send()?;
+    assert_eq!(resp.status(), 200);
+    let results: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(results[0]["status"], status);
+    let resp = reqwest::blocking::get(format!("{}dir1/{name}", server.url()))?;
+    assert_eq!(resp.text()?, content);
+    Ok(())
+}
+
+#[rstest]
+fn put_extract_max_size(
+    #[with(&["-A", "--extract-max-size", "5"])] server: TestServer,
+) -> Result<(), Error> {
+    let data = utils::build_tar(&[("a.txt", b"abc"), ("b.txt", b"def")]);
+    let resp = fetch!(b"PUT", format!("{}xyz/?extract", server.url()))
+        .body(data)
+        .send()?;
+    let results: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(results[0]["status"], "created");
+    assert_eq!(results[1]["status"], "failed");
+    let resp = reqwest::blocking::get(format!("{}xyz/b.txt", server.url()))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+fn put_extract_max_upload(
+    #[with(&["-A", "--extract-max-upload", "1024"])] server: TestServer,
+) -> Result<(), Error> {
+    let data = utils::build_tar(&[("a.txt", b"abc")]);
+    let resp = fetch!(b"PUT", format!("{}xyz/?extract", server.url()))
+        .body(data)
+        .send()?;
+    assert_eq!(resp.status(), 413);
+    let names: Vec<_> = std::fs::read_dir(server.path().join("xyz"))?.collect();
+    assert!(names.is_empty());
+    Ok(())
+}
+
+#[rstest]
+fn put_extract_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"PUT", format!("{}xyz/?extract", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn get_archive_list(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}bundle.tar", server.url());
+    let data = utils::build_tar(&[("a/b.txt", b"abc"), ("c.txt", b"def")]);
+    fetch!(b"PUT", &url).body(data).send()?;
+    let resp = reqwest::blocking::get(format!("{url}?list"))?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.headers().get("content-type").unwrap(),
+        "application/json"
+    );
+    let paths: Vec<Value> = serde_json::from_str(&resp.text()?)?;
+    let paths: Vec<(&str, &str)> = paths
+        .iter()
+        .map(|v| {
+            (
+                v["name"].as_str().unwrap(),
+                v["path_type"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        paths,
+        [("a", "Dir"), ("a/b.txt", "File"), ("c.txt", "File")]
+    );
+    Ok(())
+}
+
+#[rstest]
+fn get_archive_member(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let url = format!("{}bundle.tar", server.url());
+    let data = utils::build_tar(&[("a/b.txt", b"abc")]);
+    fetch!(b"PUT", &url).body(data).send()?;
+    let resp = reqwest::blocking::get(format!("{url}?member=a/b.txt"))?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["content-type"]
+        .to_str()?
+        .starts_with("text/plain"));
+    assert_eq!(resp.headers().get("accept-ranges").unwrap(), "bytes");
+    assert_eq!(resp.text()?, "abc");
+    let resp = fetch!(b"GET", format!("{url}?member=a/b.txt"))
+        .header("range", "bytes=1-")
+        .send()?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes 1-2/3");
+    assert_eq!(resp.text()?, "bc");
+    let resp = reqwest::blocking::get(format!("{url}?member=a/404.txt"))?;
+    assert_eq!(resp.status(), 404);
+    Ok(())
+}
+
+#[rstest]
+#[case("?zip")]
+#[case("?zip&stored")]
+fn get_archive_member_zip(
+    #[with(&["-A"])] server: TestServer,
+    #[case] query: &str,
+) -> Result<(), Error> {
+    let data = reqwest::blocking::get(format!("{}dir1{query}", server.url()))?.bytes()?;
+    let url = format!("{}dir1.zip", server.url());
+    fetch!(b"PUT", &url).body(data.to_vec()).send()?;
+    let resp = reqwest::blocking::get(format!("{url}?member=test.html"))?;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["content-type"]
+        .to_str()?
+        .starts_with("text/html"));
+    assert_eq!(resp.text()?, "This is dir1/test.html");
+    Ok(())
+}
+
+#[rstest]
+fn get_archive_list_invalid(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = reqwest::blocking::get(format!("{}test.html?list", server.url()))?;
+    assert_eq!(resp.status(), 400);
+    Ok(())
+}
+
+#[rstest]
+fn put_extract_not_allowed(server: TestServer) -> Result<(), Error> {
+    let data = utils::build_tar(&[("a.txt", b"abc")]);
+    let resp = fetch!(b"PUT", format!("{}xyz/?extract", server.url()))
+        .body(data)
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
 #[rstest]
 fn delete_file(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     let url = format!("{}test.html", server.url());
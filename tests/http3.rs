@@ -0,0 +1,177 @@
+#![cfg(feature = "http3")]
+
+mod fixtures;
+mod utils;
+
+use assert_cmd::Command;
+use bytes::{Buf, Bytes};
+use fixtures::{port, server, Error, TestServer};
+use hyper::{Request, Response};
+use predicates::str::contains;
+use quinn::crypto::rustls::QuicClientConfig;
+use reqwest::blocking::ClientBuilder;
+use rstest::rstest;
+use std::sync::Arc;
+use tokio_rustls::rustls::{
+    self,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    pki_types::{CertificateDer, ServerName, UnixTime},
+    DigitallySignedStruct, SignatureScheme,
+};
+
+/// Requests over HTTP/3 go through the same handlers, ranges included.
+#[rstest]
+#[tokio::test]
+async fn http3_works(
+    #[with(&[
+        "--tls-cert", "tests/data/cert.pem",
+        "--tls-key", "tests/data/key_pkcs8.pem",
+        "--http3",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let req = Request::get(format!("{}index.html", server.url()))
+        .header("range", "bytes=0-6")
+        .body(())?;
+    let (resp, body) = h3_request(server.port(), req, b"").await?;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.headers().get("content-range").unwrap(), "bytes 0-6/18");
+    assert!(resp.headers().get("alt-svc").is_none());
+    assert_eq!(body, b"This is");
+    Ok(())
+}
+
+/// Uploads over HTTP/3 are authenticated like the other protocols.
+#[rstest]
+#[tokio::test]
+async fn http3_upload(
+    #[with(&[
+        "--tls-cert", "tests/data/cert.pem",
+        "--tls-key", "tests/data/key_pkcs8.pem",
+        "--http3",
+        "-a", "user:pass@/:rw",
+        "-A",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}file1", server.url());
+    let req = Request::put(&url).body(())?;
+    let (resp, _) = h3_request(server.port(), req, b"abc").await?;
+    assert_eq!(resp.status(), 401);
+
+    let req = Request::put(&url)
+        .header("authorization", "Basic dXNlcjpwYXNz")
+        .body(())?;
+    let (resp, _) = h3_request(server.port(), req, b"abc").await?;
+    assert_eq!(resp.status(), 201);
+    assert_eq!(std::fs::read(server.path().join("file1"))?, b"abc");
+    Ok(())
+}
+
+/// HTTP/1.1 and HTTP/2 responses point to the HTTP/3 listener.
+#[rstest]
+fn http3_alt_svc(
+    #[with(&[
+        "--tls-cert", "tests/data/cert.pem",
+        "--tls-key", "tests/data/key_pkcs8.pem",
+        "--http3",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resp = client.get(server.url()).send()?;
+    assert_eq!(
+        resp.headers().get("alt-svc").unwrap(),
+        &format!("h3=\":{}\"; ma=86400", server.port())
+    );
+    Ok(())
+}
+
+/// HTTP/3 needs TLS.
+#[rstest]
+fn http3_without_tls() -> Result<(), Error> {
+    let port = port().to_string();
+    Command::cargo_bin("dufs")?
+        .args(["--http3", "--port", &port])
+        .assert()
+        .failure()
+        .stderr(contains("HTTP/3 needs TLS"));
+
+    Ok(())
+}
+
+async fn h3_request(
+    port: u16,
+    req: Request<()>,
+    body: &'static [u8],
+) -> Result<(Response<()>, Vec<u8>), Error> {
+    let mut tls = rustls::ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(NoVerify))
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse()?)?;
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(tls)?,
+    )));
+    let conn = endpoint
+        .connect(format!("127.0.0.1:{port}").parse()?, "localhost")?
+        .await?;
+    let (mut driver, mut send_request) = h3::client::new(h3_quinn::Connection::new(conn)).await?;
+    tokio::spawn(async move { std::future::poll_fn(|cx| driver.poll_close(cx)).await });
+
+    let mut stream = send_request.send_request(req).await?;
+    if !body.is_empty() {
+        stream.send_data(Bytes::from_static(body)).await?;
+    }
+    stream.finish().await?;
+    let resp = stream.recv_response().await?;
+    let mut body = vec![];
+    while let Some(mut chunk) = stream.recv_data().await? {
+        body.extend_from_slice(&chunk.copy_to_bytes(chunk.remaining()));
+    }
+    Ok((resp, body))
+}
+
+/// Accepts the self-signed test certificate, like `danger_accept_invalid_certs`.
+#[derive(Debug)]
+struct NoVerify;
+
+impl ServerCertVerifier for NoVerify {
+    fn verify_server_cert(
+        &self,
+        _: &CertificateDer,
+        _: &[CertificateDer],
+        _: &ServerName,
+        _: &[u8],
+        _: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _: &[u8],
+        _: &CertificateDer,
+        _: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        _: &[u8],
+        _: &CertificateDer,
+        _: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        Ok(HandshakeSignatureValid::assertion())
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        rustls::crypto::ring::default_provider()
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
@@ -0,0 +1,261 @@
+mod fixtures;
+mod utils;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use fixtures::{server, Error, TestServer};
+use reqwest::blocking::{Client, Response};
+use reqwest::redirect::Policy;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
+
+#[test]
+fn oidc_login() -> Result<(), Error> {
+    let idp = MockIdp::start();
+    let server = oidc_server(&idp);
+    let client = client()?;
+
+    let resp = client
+        .get(server.url())
+        .header("accept", "text/html")
+        .send()?;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(location(&resp), "/?login&next=%2F");
+
+    let session = login(&server, &idp, json!({"groups": ["admins"]}))?;
+    let url = format!("{}file1", server.url());
+    let resp = client
+        .put(&url)
+        .header("cookie", format!("dufs_session={session}"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = client
+        .get(format!("{}?json", server.url()))
+        .header("cookie", format!("dufs_session={session}"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let data: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(data["user"], "alice@example.com");
+    assert_eq!(data["oidc"], true);
+    Ok(())
+}
+
+#[test]
+fn oidc_rules() -> Result<(), Error> {
+    let idp = MockIdp::start();
+    let server = oidc_server(&idp);
+    let client = client()?;
+
+    let resp = client.get(server.url()).send()?;
+    assert_eq!(resp.status(), 401);
+    assert!(resp.headers().get("www-authenticate").is_none());
+
+    let session = login(&server, &idp, json!({"groups": ["users"]}))?;
+    let resp = client
+        .get(format!("{}dir1/test.html", server.url()))
+        .header("cookie", format!("dufs_session={session}"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = client
+        .put(format!("{}file1", server.url()))
+        .header("cookie", format!("dufs_session={session}"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[test]
+fn oidc_invalid_state() -> Result<(), Error> {
+    let idp = MockIdp::start();
+    let server = oidc_server(&idp);
+    let client = client()?;
+
+    let resp = client.get(format!("{}?login", server.url())).send()?;
+    let login_cookie = cookie(&resp, "dufs_oidc").unwrap();
+    let resp = client
+        .get(format!("{}?login&code=abc&state=wrong", server.url()))
+        .header("cookie", format!("dufs_oidc={login_cookie}"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(cookie(&resp, "dufs_session").is_none());
+    Ok(())
+}
+
+#[test]
+fn oidc_logout() -> Result<(), Error> {
+    let idp = MockIdp::start();
+    let server = oidc_server(&idp);
+    let client = client()?;
+
+    let resp = client.get(format!("{}?logout", server.url())).send()?;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(location(&resp), "/");
+    assert_eq!(cookie(&resp, "dufs_session").unwrap(), "");
+    Ok(())
+}
+
+fn oidc_server(idp: &MockIdp) -> TestServer {
+    server(&[
+        "--oidc-issuer",
+        idp.issuer.as_str(),
+        "--oidc-client-id",
+        "dufs",
+        "--oidc-rule",
+        "groups=admins@/:rw",
+        "--oidc-rule",
+        "*@/dir1",
+        "-A",
+    ])
+}
+
+/// Go through the login flow for a user with `claims`, returns the session cookie.
+fn login(server: &TestServer, idp: &MockIdp, claims: Value) -> Result<String, Error> {
+    let client = client()?;
+    let resp = client.get(format!("{}?login", server.url())).send()?;
+    assert_eq!(resp.status(), 302);
+    let authorize = Url::parse(&location(&resp))?;
+    assert!(authorize.as_str().starts_with(&idp.issuer));
+    let params: HashMap<String, String> = authorize.query_pairs().into_owned().collect();
+    assert_eq!(params["client_id"], "dufs");
+    assert_eq!(params["code_challenge_method"], "S256");
+    let login_cookie = cookie(&resp, "dufs_oidc").unwrap();
+
+    let mut id_token = json!({
+        "iss": idp.issuer,
+        "aud": "dufs",
+        "exp": now() + 300,
+        "nonce": params["nonce"],
+        "email": "alice@example.com",
+    });
+    for (k, v) in claims.as_object().unwrap() {
+        id_token[k] = v.clone();
+    }
+    idp.state.lock().unwrap().claims = id_token;
+
+    let resp = client
+        .get(format!(
+            "{}?login&code=abc&state={}",
+            server.url(),
+            params["state"]
+        ))
+        .header("cookie", format!("dufs_oidc={login_cookie}"))
+        .send()?;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(location(&resp), "/");
+
+    let form = idp.state.lock().unwrap().form.clone();
+    assert_eq!(form["code"], "abc");
+    let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(form["code_verifier"].as_bytes()));
+    assert_eq!(challenge, params["code_challenge"]);
+    Ok(cookie(&resp, "dufs_session").unwrap())
+}
+
+fn client() -> Result<Client, Error> {
+    Ok(Client::builder().redirect(Policy::none()).build()?)
+}
+
+fn location(resp: &Response) -> String {
+    resp.headers()["location"].to_str().unwrap().to_string()
+}
+
+fn cookie(resp: &Response, name: &str) -> Option<String> {
+    resp.headers().get_all("set-cookie").iter().find_map(|v| {
+        let (value, _) = v.to_str().ok()?.split_once(';')?;
+        value
+            .strip_prefix(&format!("{name}="))
+            .map(|v| v.to_string())
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[derive(Default)]
+struct IdpState {
+    claims: Value,
+    form: HashMap<String, String>,
+}
+
+/// An identity provider serving the discovery document and the token endpoint.
+struct MockIdp {
+    issuer: String,
+    state: Arc<Mutex<IdpState>>,
+}
+
+impl MockIdp {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let state: Arc<Mutex<IdpState>> = Default::default();
+        let discovery = json!({
+            "issuer": issuer,
+            "authorization_endpoint": format!("{issuer}/authorize"),
+            "token_endpoint": format!("{issuer}/token"),
+        });
+        let thread_state = state.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = handle_idp_request(stream, &discovery, &thread_state);
+            }
+        });
+        Self { issuer, state }
+    }
+}
+
+fn handle_idp_request(
+    mut stream: TcpStream,
+    discovery: &Value,
+    state: &Mutex<IdpState>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or_default();
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let (status, body) = if request_line.starts_with("GET /.well-known/openid-configuration") {
+        ("200 OK", discovery.to_string())
+    } else if request_line.starts_with("POST /token") {
+        let mut state = state.lock().unwrap();
+        state.form = form_urlencoded::parse(&body).into_owned().collect();
+        let payload = URL_SAFE_NO_PAD.encode(state.claims.to_string());
+        let id_token = format!("eyJhbGciOiJub25lIn0.{payload}.");
+        (
+            "200 OK",
+            json!({"access_token": "abc", "token_type": "Bearer", "id_token": id_token})
+                .to_string(),
+        )
+    } else {
+        ("404 Not Found", String::new())
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
@@ -0,0 +1,54 @@
+mod fixtures;
+mod utils;
+
+use assert_cmd::prelude::*;
+use assert_fs::fixture::TempDir;
+use fixtures::{port, tmpdir, wait_for_port, Error};
+use rstest::rstest;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+#[rstest]
+fn search_index(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let index_file = tmpdir.path().join("search-index");
+    let mut child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("-A")
+        .arg("--hidden")
+        .arg("dir2")
+        .arg("--search-index")
+        .arg(&index_file)
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    wait_for_port(port);
+    std::thread::sleep(Duration::from_millis(500));
+    let content = std::fs::read_to_string(&index_file)?;
+    assert!(content.contains(r#"["dir1/test.html",false,false,"#));
+
+    let resp = fetch!(b"PUT", format!("http://localhost:{port}/dir3/new/index-test.html"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    std::fs::write(tmpdir.path().join("dir1/outside-test.html"), "abc")?;
+    std::thread::sleep(Duration::from_millis(500));
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/?q=test.html&simple"))?;
+    assert_eq!(resp.status(), 200);
+    let text = resp.text()?;
+    let paths: Vec<&str> = text.lines().collect();
+    assert!(paths.contains(&"dir1/test.html"));
+    assert!(paths.contains(&"dir1/outside-test.html"));
+    assert!(paths.contains(&"dir3/new/index-test.html"));
+    assert!(!paths.iter().any(|v| v.starts_with("dir2/")));
+
+    let resp = reqwest::blocking::get(format!("http://localhost:{port}/dir3/?q=new&json"))?;
+    let json: serde_json::Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(json["paths"][0]["name"], "new");
+    assert_eq!(json["paths"][0]["path_type"], "Dir");
+
+    child.kill()?;
+    Ok(())
+}
@@ -0,0 +1,172 @@
+mod fixtures;
+mod utils;
+
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use serde_json::Value;
+
+fn create_share(server: &TestServer, path: &str, body: &str) -> Result<String, Error> {
+    let url = format!("{}{path}?share", server.url());
+    let resp = fetch!(b"POST", &url)
+        .body(body.to_string())
+        .basic_auth("admin", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let data: Value = serde_json::from_str(&resp.text()?)?;
+    let url = server.url().join(data["url"].as_str().unwrap())?;
+    Ok(url.to_string())
+}
+
+#[rstest]
+fn share_file(#[with(&["-a", "admin:pass@/:rw", "-A"])] server: TestServer) -> Result<(), Error> {
+    let url = create_share(&server, "index.html", "")?;
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is index.html");
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 403);
+    let other_url = url.replace("index.html", "test.html");
+    let resp = fetch!(b"GET", &other_url).send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", url.replace("share=", "share=x")).send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn share_dir(#[with(&["-a", "admin:pass@/:rw", "-A"])] server: TestServer) -> Result<(), Error> {
+    let url = create_share(&server, "dir1/", "")?;
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    let cookie = resp
+        .headers()
+        .get("set-cookie")
+        .unwrap()
+        .to_str()?
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+    assert!(cookie.starts_with("dufs_share="));
+    let resp = fetch!(b"GET", format!("{}dir1/test.html", server.url()))
+        .header("cookie", &cookie)
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", format!("{}dir2/test.html", server.url()))
+        .header("cookie", &cookie)
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn share_max_downloads(
+    #[with(&["-a", "admin:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = create_share(&server, "index.html", r#"{"max_downloads":1}"#)?;
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 410);
+    Ok(())
+}
+
+#[rstest]
+fn share_max_downloads_range(
+    #[with(&["-a", "admin:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = create_share(&server, "index.html", r#"{"max_downloads":1}"#)?;
+    let resp = fetch!(b"GET", &url).header("range", "bytes=0-3").send()?;
+    assert_eq!(resp.status(), 206);
+    let resp = fetch!(b"GET", &url).header("range", "bytes=4-").send()?;
+    assert_eq!(resp.status(), 206);
+    let resp = fetch!(b"GET", &url).header("range", "bytes=0-").send()?;
+    assert_eq!(resp.status(), 410);
+    let resp = fetch!(b"GET", &url).header("range", "bytes=-100").send()?;
+    assert_eq!(resp.status(), 410);
+    Ok(())
+}
+
+#[rstest]
+fn share_keep_deny(
+    #[with(&["-a", "admin:pass@/:rw,/dir1/test.html:deny", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = create_share(&server, "dir1/", "")?;
+    let (base, query) = url.split_once('?').unwrap();
+    let resp = fetch!(b"GET", format!("{base}index.html?{query}")).send()?;
+    assert_eq!(resp.status(), 200);
+    let resp = fetch!(b"GET", format!("{base}test.html?{query}")).send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{base}?{query}&json")).send()?;
+    let text = resp.text()?;
+    assert!(text.contains("index.html"));
+    assert!(!text.contains("test.html"));
+    Ok(())
+}
+
+#[rstest]
+fn share_password(
+    #[with(&["-a", "admin:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = create_share(&server, "index.html", r#"{"password":"secret"}"#)?;
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 401);
+    assert!(resp.headers().contains_key("www-authenticate"));
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("guest", Some("wrong"))
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("guest", Some("secret"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    Ok(())
+}
+
+#[rstest]
+fn share_upload_only(
+    #[with(&["-a", "admin:pass@/:rw", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = create_share(&server, "dir1", r#"{"upload_only":true}"#)?;
+    let (base, query) = url.split_once('?').unwrap();
+    let resp = fetch!(b"PUT", format!("{base}file1?{query}"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", format!("{base}file1?{query}"))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"GET", format!("{base}test.html?{query}")).send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn share_not_allowed(
+    #[with(&["-a", "admin:pass@/:rw", "-a", "user:pass@/dir1", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1?share", server.url());
+    let resp = fetch!(b"POST", &url).send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = fetch!(b"POST", &url)
+        .body(r#"{"upload_only":true}"#)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"POST", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let url = format!("{}dir2?share", server.url());
+    let resp = fetch!(b"POST", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let url = format!("{}?share", server.url());
+    let resp = fetch!(b"POST", &url)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
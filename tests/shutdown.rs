@@ -0,0 +1,113 @@
+#![cfg(unix)]
+
+mod fixtures;
+
+use assert_cmd::prelude::*;
+use assert_fs::fixture::TempDir;
+use fixtures::{port, tmpdir, wait_for_port, Error};
+use rstest::rstest;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Requests in flight on shutdown are finished and told to close the connection.
+#[rstest]
+fn shutdown_drains_requests(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_server(&tmpdir, port, "10")?;
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream.write_all(b"PUT /file1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nabc")?;
+    sleep(Duration::from_millis(200));
+
+    signal(&child)?;
+    sleep(Duration::from_millis(200));
+    assert!(!port_check::is_port_reachable(format!("localhost:{port}")));
+
+    stream.write_all(b"def")?;
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp)?;
+    assert!(resp.starts_with("HTTP/1.1 201"));
+    assert!(resp.to_lowercase().contains("connection: close"));
+    assert_eq!(std::fs::read(tmpdir.path().join("file1"))?, b"abcdef");
+    assert!(wait_exit(&mut child, 5)?.success());
+    Ok(())
+}
+
+/// Requests still running when the timeout runs out don't hold up the exit.
+#[rstest]
+fn shutdown_timeout(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_server(&tmpdir, port, "1")?;
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream.write_all(b"PUT /file1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nabc")?;
+    sleep(Duration::from_millis(200));
+
+    signal(&child)?;
+    assert!(wait_exit(&mut child, 5)?.success());
+    Ok(())
+}
+
+/// Event streams never finish on their own, so they end as soon as the shutdown starts.
+#[rstest]
+fn shutdown_ends_event_streams(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_server(&tmpdir, port, "60")?;
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream.write_all(b"GET /?events HTTP/1.1\r\nHost: localhost\r\n\r\n")?;
+    sleep(Duration::from_millis(200));
+
+    signal(&child)?;
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp)?;
+    assert!(resp.starts_with("HTTP/1.1 200"));
+    assert!(wait_exit(&mut child, 5)?.success());
+    Ok(())
+}
+
+/// A second signal exits without waiting for the timeout.
+#[rstest]
+fn shutdown_forced(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let mut child = spawn_server(&tmpdir, port, "60")?;
+    let mut stream = TcpStream::connect(("127.0.0.1", port))?;
+    stream.write_all(b"PUT /file1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nabc")?;
+    sleep(Duration::from_millis(200));
+
+    signal(&child)?;
+    sleep(Duration::from_millis(200));
+    assert!(child.try_wait()?.is_none());
+    signal(&child)?;
+    assert!(wait_exit(&mut child, 5)?.success());
+    Ok(())
+}
+
+fn spawn_server(tmpdir: &TempDir, port: u16, timeout: &str) -> Result<Child, Error> {
+    let child = Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .args(["-A", "-b", "127.0.0.1", "-p", &port.to_string()])
+        .args(["--shutdown-timeout", timeout])
+        .stdout(Stdio::null())
+        .spawn()?;
+    wait_for_port(port);
+    Ok(child)
+}
+
+fn signal(child: &Child) -> Result<(), Error> {
+    let status = Command::new("kill")
+        .args(["-TERM", &child.id().to_string()])
+        .status()?;
+    assert!(status.success());
+    Ok(())
+}
+
+fn wait_exit(child: &mut Child, secs: u64) -> Result<ExitStatus, Error> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if start.elapsed().as_secs() >= secs {
+            child.kill()?;
+            return Err("timeout waiting for dufs to exit".into());
+        }
+        sleep(Duration::from_millis(100));
+    }
+}
@@ -44,3 +44,33 @@ fn allow_symlink(
     assert!(paths.contains(&format!("{dir}/")));
     Ok(())
 }
+
+#[rstest]
+fn archive_symlink_as_link(
+    #[with(&["--allow-archive"])] server: TestServer,
+    tmpdir: TempDir,
+) -> Result<(), Error> {
+    symlink_dir(tmpdir.path(), server.path().join("foo")).expect("Couldn't create symlink");
+    let resp = reqwest::blocking::get(format!("{}?archive=tar", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let entries = utils::retrieve_tar_entries(&resp.bytes()?);
+    let (_, entry_type, link_name) = entries.iter().find(|(name, ..)| name == "foo").unwrap();
+    assert_eq!(*entry_type, b'2');
+    assert_eq!(link_name, tmpdir.path().to_str().unwrap());
+    assert!(!entries.iter().any(|(name, ..)| name.starts_with("foo/")));
+    Ok(())
+}
+
+#[rstest]
+fn archive_skip_broken_symlink(
+    #[with(&["--allow-symlink", "--allow-archive"])] server: TestServer,
+) -> Result<(), Error> {
+    symlink_dir(server.path().join("missing"), server.path().join("foo"))
+        .expect("Couldn't create symlink");
+    let resp = reqwest::blocking::get(format!("{}?archive=tar", server.url()))?;
+    assert_eq!(resp.status(), 200);
+    let entries = utils::retrieve_tar_entries(&resp.bytes()?);
+    assert!(entries.iter().any(|(name, ..)| name == "index.html"));
+    assert!(!entries.iter().any(|(name, ..)| name == "foo"));
+    Ok(())
+}
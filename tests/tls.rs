@@ -1,11 +1,16 @@
 mod fixtures;
 mod utils;
 
+use assert_cmd::cargo::CommandCargoExt;
 use assert_cmd::Command;
-use fixtures::{server, Error, TestServer};
+use assert_fs::fixture::TempDir;
+use fixtures::{server, tmpdir, wait_for_port, Error, TestServer};
 use predicates::str::contains;
 use reqwest::blocking::ClientBuilder;
+use reqwest::redirect::Policy;
+use reqwest::Identity;
 use rstest::rstest;
+use std::process::Stdio;
 
 use crate::fixtures::port;
 
@@ -71,3 +76,109 @@ fn wrong_path_key() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// A verified client certificate logs its user in, a certificate authorization can't be forged.
+#[rstest]
+fn tls_client_cert(
+    #[with(&[
+        "--tls-cert", "tests/data/cert.pem",
+        "--tls-key", "tests/data/key_pkcs8.pem",
+        "--tls-client-ca", "tests/data/client_ca.pem",
+        "-a", "alice@/:rw",
+        "-A",
+    ])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let identity = Identity::from_pem(&std::fs::read("tests/data/client_identity.pem")?)?;
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .identity(identity)
+        .build()?;
+    let resp = client
+        .put(format!("{}file1", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+
+    let client = ClientBuilder::new()
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let resp = client.get(server.url()).send()?;
+    assert_eq!(resp.status(), 401);
+    let resp = client
+        .get(server.url())
+        .header("Authorization", "Certificate alice")
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+/// Client certificates need TLS.
+#[rstest]
+fn tls_client_ca_without_cert() -> Result<(), Error> {
+    let port = port().to_string();
+    Command::cargo_bin("dufs")?
+        .args([
+            "--tls-client-ca",
+            "tests/data/client_ca.pem",
+            "--port",
+            &port,
+        ])
+        .assert()
+        .failure()
+        .stderr(contains("No tls-cert set"));
+
+    Ok(())
+}
+
+/// ACME certificates have to be cached.
+#[rstest]
+fn acme_without_cache() -> Result<(), Error> {
+    let port = port().to_string();
+    Command::cargo_bin("dufs")?
+        .args(["--acme-domain", "example.com", "--port", &port])
+        .assert()
+        .failure()
+        .stderr(contains("No acme-cache set"));
+
+    Ok(())
+}
+
+/// The http-01 port answers challenges and redirects everything else to HTTPS.
+#[rstest]
+fn acme_http_port(tmpdir: TempDir, port: u16) -> Result<(), Error> {
+    let http_port = fixtures::port();
+    let mut child = std::process::Command::cargo_bin("dufs")?
+        .arg(tmpdir.path())
+        .arg("-p")
+        .arg(port.to_string())
+        .args(["--acme-domain", "localhost", "--acme-cache"])
+        .arg(tmpdir.path().join("acme"))
+        // Unreachable, the certificate is never issued
+        .args(["--acme-directory", "http://127.0.0.1:1/dir"])
+        .args(["--acme-challenge", "http-01", "--acme-http-port"])
+        .arg(http_port.to_string())
+        .stdout(Stdio::null())
+        .spawn()?;
+
+    wait_for_port(http_port);
+
+    let client = ClientBuilder::new().redirect(Policy::none()).build()?;
+    let resp = client
+        .get(format!("http://localhost:{http_port}/dir1/?q=a"))
+        .send()?;
+    assert_eq!(resp.status(), 302);
+    assert_eq!(
+        resp.headers().get("location").unwrap(),
+        &format!("https://localhost:{port}/dir1/?q=a")
+    );
+    let resp = client
+        .get(format!(
+            "http://localhost:{http_port}/.well-known/acme-challenge/unknown"
+        ))
+        .send()?;
+    assert_eq!(resp.status(), 404);
+
+    child.kill()?;
+    Ok(())
+}
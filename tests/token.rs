@@ -0,0 +1,126 @@
+mod fixtures;
+mod utils;
+
+use assert_fs::TempDir;
+use fixtures::{server, Error, TestServer};
+use rstest::rstest;
+use serde_json::Value;
+
+#[rstest]
+fn token_bearer(
+    #[with(&["--config", "tests/data/tokens.yaml", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .bearer_auth("secret")
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .bearer_auth("wrong")
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    let url = format!("{}file1", server.url());
+    let resp = fetch!(b"PUT", &url)
+        .body(b"abc".to_vec())
+        .bearer_auth("secret")
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    Ok(())
+}
+
+#[rstest]
+fn token_expired(
+    #[with(&["--config", "tests/data/tokens.yaml", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let resp = fetch!(b"GET", server.url()).bearer_auth("old").send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn token_query(
+    #[with(&["--config", "tests/data/tokens.yaml", "--allow-query-token", "-A"])]
+    server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/test.html?token=secret", server.url());
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.text()?, "This is dir1/test.html");
+    let url = format!("{}dir1/file1?token=secret", server.url());
+    let resp = fetch!(b"PUT", &url).body(b"abc".to_vec()).send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn token_query_not_allowed(
+    #[with(&["--config", "tests/data/tokens.yaml", "-A"])] server: TestServer,
+) -> Result<(), Error> {
+    let url = format!("{}dir1/test.html?token=secret", server.url());
+    let resp = fetch!(b"GET", &url).send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
+
+#[rstest]
+fn token_admin() -> Result<(), Error> {
+    let dir = TempDir::new()?;
+    let token_file = dir.path().join("tokens.json");
+    let server = server(&[
+        "--token-file",
+        token_file.to_str().unwrap(),
+        "-a",
+        "admin:pass@/:rw",
+        "-a",
+        "user:pass@/dir1:rw",
+        "-A",
+    ]);
+    let url = format!("{}?tokens", server.url());
+    let body = r#"{"label":"ci","paths":"/dir1:rw"}"#;
+    let resp = fetch!(b"POST", &url)
+        .body(body)
+        .basic_auth("user", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    let resp = fetch!(b"POST", &url)
+        .body(body)
+        .basic_auth("admin", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let created: Value = serde_json::from_str(&resp.text()?)?;
+    let token = created["token"].as_str().unwrap().to_string();
+    let id = created["id"].as_str().unwrap().to_string();
+    assert_eq!(created["label"], "ci");
+    assert!(!std::fs::read_to_string(&token_file)?.contains(&token));
+
+    let file_url = format!("{}dir1/file1", server.url());
+    let resp = fetch!(b"PUT", &file_url)
+        .body(b"abc".to_vec())
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"GET", &url).bearer_auth(&token).send()?;
+    assert_eq!(resp.status(), 403);
+
+    let resp = fetch!(b"GET", &url)
+        .basic_auth("admin", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 200);
+    let tokens: Value = serde_json::from_str(&resp.text()?)?;
+    assert_eq!(tokens[0]["id"], id.as_str());
+    assert!(tokens[0].get("hash").is_none());
+
+    let delete_url = format!("{}?tokens&id={id}", server.url());
+    let resp = fetch!(b"DELETE", &delete_url)
+        .basic_auth("admin", Some("pass"))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"PUT", &file_url)
+        .body(b"abc".to_vec())
+        .bearer_auth(&token)
+        .send()?;
+    assert_eq!(resp.status(), 401);
+    Ok(())
+}
@@ -67,3 +67,47 @@ pub fn retrive_json(content: &str) -> Option<Value> {
     let value: Value = line[line_col..].parse().unwrap();
     Some(value)
 }
+
+/// List `(name, entry type, link name)` of the entries in a tar archive.
+#[allow(dead_code)]
+pub fn retrieve_tar_entries(data: &[u8]) -> Vec<(String, u8, String)> {
+    let to_str = |v: &[u8]| {
+        let end = v.iter().position(|c| *c == 0).unwrap_or(v.len());
+        String::from_utf8_lossy(&v[..end]).to_string()
+    };
+    let mut entries = vec![];
+    let mut offset = 0;
+    while offset + 512 <= data.len() && data[offset] != 0 {
+        let header = &data[offset..offset + 512];
+        let size = u64::from_str_radix(to_str(&header[124..136]).trim(), 8).unwrap_or(0) as usize;
+        entries.push((to_str(&header[0..100]), header[156], to_str(&header[157..257])));
+        offset += 512 + size.div_ceil(512) * 512;
+    }
+    entries
+}
+
+/// Build a tar archive of regular files from `(name, content)` pairs.
+#[allow(dead_code)]
+pub fn build_tar(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut data = vec![];
+    for (name, content) in files {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[108..116].copy_from_slice(b"0000000\0");
+        header[116..124].copy_from_slice(b"0000000\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", content.len()).as_bytes());
+        header[136..148].copy_from_slice(b"00000000000\0");
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u32 = header.iter().map(|v| *v as u32).sum();
+        header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+        data.extend_from_slice(&header);
+        data.extend_from_slice(content);
+        data.resize(data.len().div_ceil(512) * 512, 0);
+    }
+    data.resize(data.len() + 1024, 0);
+    data
+}
@@ -215,3 +215,98 @@ fn unlock_file_404(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
     assert_eq!(resp.status(), 404);
     Ok(())
 }
+
+#[rstest]
+fn report_sync_collection_initial(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", format!("{}dir1", server.url()))
+        .body(sync_collection_body(""))
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    for f in FILES {
+        assert!(body.contains(&format!("<D:href>/dir1/{}</D:href>", utils::encode_uri(f))));
+    }
+    assert!(retrieve_sync_token(&body).is_some());
+    Ok(())
+}
+
+#[rstest]
+fn report_sync_collection_changes(#[with(&["-A"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body(""))
+        .send()?;
+    let token = retrieve_sync_token(&resp.text()?).unwrap();
+
+    let resp = fetch!(b"PUT", format!("{}file1", server.url()))
+        .body(b"abc".to_vec())
+        .send()?;
+    assert_eq!(resp.status(), 201);
+    let resp = fetch!(b"DELETE", format!("{}test.html", server.url())).send()?;
+    assert_eq!(resp.status(), 204);
+    let resp = fetch!(b"MOVE", format!("{}test.txt", server.url()))
+        .header("Destination", format!("{}test2.txt", server.url()))
+        .send()?;
+    assert_eq!(resp.status(), 204);
+
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body(&token))
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    let body = resp.text()?;
+    assert!(body.contains("<D:href>/file1</D:href>"));
+    assert!(body.contains("<D:href>/test2.txt</D:href>"));
+    assert!(body.contains(
+        "<D:href>/test.html</D:href>\n<D:status>HTTP/1.1 404 Not Found</D:status>"
+    ));
+    assert!(body.contains(
+        "<D:href>/test.txt</D:href>\n<D:status>HTTP/1.1 404 Not Found</D:status>"
+    ));
+    assert!(!body.contains("<D:href>/index.html</D:href>"));
+    assert_ne!(retrieve_sync_token(&body), Some(token));
+    Ok(())
+}
+
+#[rstest]
+fn report_sync_collection_watch(#[with(&["--watch"])] server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body(""))
+        .send()?;
+    let token = retrieve_sync_token(&resp.text()?).unwrap();
+
+    std::fs::write(server.path().join("external.txt"), "abc")?;
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body(&token))
+        .send()?;
+    assert_eq!(resp.status(), 207);
+    assert!(resp.text()?.contains("<D:href>/external.txt</D:href>"));
+    Ok(())
+}
+
+#[rstest]
+fn report_sync_collection_invalid_token(server: TestServer) -> Result<(), Error> {
+    let resp = fetch!(b"REPORT", server.url())
+        .body(sync_collection_body("urn:dufs:sync:invalid:0"))
+        .send()?;
+    assert_eq!(resp.status(), 403);
+    assert!(resp.text()?.contains("<D:valid-sync-token/>"));
+    Ok(())
+}
+
+fn sync_collection_body(token: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8" ?>
+<D:sync-collection xmlns:D="DAV:">
+<D:sync-token>{token}</D:sync-token>
+<D:sync-level>1</D:sync-level>
+<D:prop><D:getlastmodified/></D:prop>
+</D:sync-collection>"#
+    )
+}
+
+fn retrieve_sync_token(body: &str) -> Option<String> {
+    let start = body.find("<D:sync-token>")? + 14;
+    let end = body.find("</D:sync-token>")?;
+    Some(body[start..end].to_string())
+}